@@ -0,0 +1,40 @@
+use futures::{future::BoxFuture, FutureExt};
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Where a [crate::RetryClient] gets the current time from, and how it waits between retries.
+/// Defaults to [TokioClock]. Tests can substitute their own to exercise backoff (and anything
+/// else built on the clock, ex: a worker's poll circuit breaker) without actually waiting.
+pub trait Clock: Debug + Send + Sync {
+    /// The current instant
+    fn now(&self) -> Instant;
+    /// Resolves once `duration` has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Tokio's clock, which is the real one unless a test has paused it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+/// Lets a [Clock] drive the elapsed time tracked by [backoff]'s exponential backoff
+#[derive(Debug, Clone)]
+pub(crate) struct BackoffClock(pub(crate) Arc<dyn Clock>);
+
+impl backoff::Clock for BackoffClock {
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+}
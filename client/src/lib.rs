@@ -7,12 +7,17 @@
 #[macro_use]
 extern crate tracing;
 
+mod clock;
 mod metrics;
 mod raw;
 mod retry;
 mod workflow_handle;
 
-pub use crate::retry::{CallType, RetryClient, RETRYABLE_ERROR_CODES};
+pub use crate::retry::{
+    classify_rpc_error, retry_after_hint, CallType, RetryAttempts, RetryClient,
+    RetryExhaustingCallback, RpcErrorClass, RETRYABLE_ERROR_CODES,
+};
+pub use clock::{Clock, TokioClock};
 pub use metrics::ClientMetricProvider;
 pub use raw::{HealthService, OperatorService, TestService, WorkflowService};
 pub use temporal_sdk_core_protos::temporal::api::{
@@ -27,7 +32,7 @@ pub use tonic;
 pub use workflow_handle::{WorkflowExecutionInfo, WorkflowExecutionResult};
 
 use crate::{
-    metrics::{GrpcMetricSvc, MetricsContext},
+    metrics::{GrpcMetricSvc, MetricsContext, SwappableChannel},
     raw::{sealed::RawClientLike, AttachMetricLabels},
     sealed::WfHandleClient,
     workflow_handle::UntypedWorkflowHandle,
@@ -73,8 +78,11 @@ use tower::ServiceBuilder;
 use url::Url;
 use uuid::Uuid;
 
-static CLIENT_NAME_HEADER_KEY: &str = "client-name";
-static CLIENT_VERSION_HEADER_KEY: &str = "client-version";
+/// Metadata key identifying the SDK making a call, see [ClientOptions::client_name]
+pub static CLIENT_NAME_HEADER_KEY: &str = "client-name";
+/// Metadata key identifying the version of the SDK making a call, see
+/// [ClientOptions::client_version]
+pub static CLIENT_VERSION_HEADER_KEY: &str = "client-version";
 /// These must match the gRPC method names, not the snake case versions that exist in the Rust code.
 static LONG_POLL_METHOD_NAMES: [&str; 2] = ["PollWorkflowTaskQueue", "PollActivityTaskQueue"];
 /// The server times out polls after 60 seconds. Set our timeout to be slightly beyond that.
@@ -122,6 +130,35 @@ pub struct ClientOptions {
     /// override.
     #[builder(default)]
     pub override_origin: Option<Uri>,
+
+    /// If set, HTTP/2 keep-alive pings are sent on the connection as configured. Useful when
+    /// intermediaries (ex: load balancers) drop connections which look idle between polls.
+    ///
+    /// Keep-alive is set up when connecting, so it applies to every worker using the connection.
+    /// See [ConfiguredClient::set_keep_alive] for changing it afterwards.
+    #[builder(setter(strip_option), default)]
+    pub keep_alive: Option<KeepAliveConfig>,
+}
+
+/// HTTP/2 keep-alive settings for the connection to the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeepAliveConfig {
+    /// How often to send a keep-alive ping
+    pub interval: Duration,
+    /// How long to wait for a ping to be acknowledged before considering the connection dead
+    pub timeout: Duration,
+    /// Whether pings are sent even when there are no in-flight requests on the connection
+    pub permit_without_stream: bool,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(15),
+            permit_without_stream: true,
+        }
+    }
 }
 
 /// Configuration options for TLS
@@ -136,6 +173,16 @@ pub struct TlsConfig {
     pub domain: Option<String>,
     /// TLS info for the client. If specified, core will attempt to use mTLS.
     pub client_tls_config: Option<ClientTlsConfig>,
+    /// Where to read the client's TLS info from each time a connection is established. Takes
+    /// precedence over `client_tls_config` when set, and allows rotating the client's certificate
+    /// with [ConfiguredClient::reload_tls].
+    pub client_cert_source: Option<Arc<dyn ClientCertSource>>,
+}
+
+/// Supplies the certificate and private key a client presents when using mTLS
+pub trait ClientCertSource: Debug + Send + Sync {
+    /// Returns the certificate and key to use for the next connection established
+    fn client_tls_config(&self) -> ClientTlsConfig;
 }
 
 /// If using mTLS, both the client cert and private key must be specified, this contains them.
@@ -164,6 +211,12 @@ pub struct RetryConfig {
     pub max_elapsed_time: Option<Duration>,
     /// maximum number of retry attempts.
     pub max_retries: usize,
+    /// If set, called when a call which keeps failing with retryable errors is about to be
+    /// attempted for the last time, with the call's name and the status of the attempt that just
+    /// failed. Useful as early warning of sustained server problems, before callers start seeing
+    /// the errors. Never called for calls retried without limit (ex: long polls), nor for calls
+    /// which give up because `max_elapsed_time` ran out.
+    pub on_retry_exhausting: Option<RetryExhaustingCallback>,
 }
 
 impl Default for RetryConfig {
@@ -175,6 +228,7 @@ impl Default for RetryConfig {
             max_interval: Duration::from_secs(5), // until it reaches 5 seconds.
             max_elapsed_time: Some(Duration::from_secs(10)), // 10 seconds total allocated time for all retries.
             max_retries: 10,
+            on_retry_exhausting: None,
         }
     }
 }
@@ -188,10 +242,13 @@ impl RetryConfig {
             max_interval: Duration::from_secs(10),
             max_elapsed_time: None,
             max_retries: 0,
+            on_retry_exhausting: None,
         }
     }
 
-    pub(crate) const fn throttle_retry_policy() -> Self {
+    /// The policy throttled calls back off with. Waits the server asks for are capped at its
+    /// `max_interval`, see [retry_after_hint].
+    pub const fn throttle_retry_policy() -> Self {
         Self {
             initial_interval: Duration::from_secs(1),
             randomization_factor: 0.2,
@@ -199,6 +256,7 @@ impl RetryConfig {
             max_interval: Duration::from_secs(10),
             max_elapsed_time: None,
             max_retries: 0,
+            on_retry_exhausting: None,
         }
     }
 
@@ -253,6 +311,7 @@ pub struct ConfiguredClient<C> {
     headers: Arc<RwLock<HashMap<String, String>>>,
     /// Capabilities as read from the `get_system_info` RPC call made on client connection
     capabilities: Option<get_system_info_response::Capabilities>,
+    channel: SwappableChannel,
 }
 
 impl<C> ConfiguredClient<C> {
@@ -272,6 +331,27 @@ impl<C> ConfiguredClient<C> {
     pub fn capabilities(&self) -> Option<&get_system_info_response::Capabilities> {
         self.capabilities.as_ref()
     }
+
+    /// Establishes a new connection to the server, reading the client's certificate anew from
+    /// [TlsConfig::client_cert_source] if one is set. This client and all its clones send calls
+    /// made after this returns over the new connection, while calls already in flight finish on
+    /// the old one.
+    pub async fn reload_tls(&self) -> Result<(), ClientInitError> {
+        let channel = self.options.connect_channel().await?;
+        self.channel.replace(channel);
+        Ok(())
+    }
+
+    /// Switches this client and all its clones over to a new connection which uses the provided
+    /// HTTP/2 keep-alive settings, and records them in this client's options so that later
+    /// reconnects (ex: [Self::reload_tls]) keep them. The new connection is established lazily,
+    /// when the next call is made, and calls already in flight finish on the old one.
+    pub fn set_keep_alive(&mut self, keep_alive: KeepAliveConfig) -> Result<(), ClientInitError> {
+        Arc::make_mut(&mut self.options).keep_alive = Some(keep_alive);
+        let channel = self.options.endpoint()?.connect_lazy();
+        self.channel.replace(channel);
+        Ok(())
+    }
 }
 
 // The configured client is effectively a "smart" (dumb) pointer
@@ -316,17 +396,50 @@ impl ClientOptions {
         headers: Option<Arc<RwLock<HashMap<String, String>>>>,
     ) -> Result<RetryClient<ConfiguredClient<TemporalServiceClientWithMetrics>>, ClientInitError>
     {
-        let channel = Channel::from_shared(self.target_url.to_string())?;
-        let channel = self.add_tls_to_channel(channel).await?;
-        let channel = if let Some(origin) = self.override_origin.clone() {
-            channel.origin(origin)
-        } else {
-            channel
+        let channel = self.connect_channel().await?;
+        let mut client = self.configured_client(channel, metrics_meter, headers);
+        match client
+            .get_system_info(GetSystemInfoRequest::default())
+            .await
+        {
+            Ok(sysinfo) => {
+                client.capabilities = sysinfo.into_inner().capabilities;
+            }
+            Err(status) => match status.code() {
+                Code::Unimplemented => {}
+                _ => return Err(ClientInitError::SystemInfoCallError(status)),
+            },
         };
-        let channel = channel.connect().await?;
+        Ok(RetryClient::new(client, self.retry_config.clone()))
+    }
+
+    /// Like [Self::connect_no_namespace], but returns without waiting to connect. The connection
+    /// is established when the first call is made instead, and since server capabilities are
+    /// learned while connecting, the client doesn't know them.
+    pub fn connect_no_namespace_lazy(
+        &self,
+        metrics_meter: Option<&dyn ClientMetricProvider>,
+        headers: Option<Arc<RwLock<HashMap<String, String>>>>,
+    ) -> Result<RetryClient<ConfiguredClient<TemporalServiceClientWithMetrics>>, ClientInitError>
+    {
+        let channel = self.endpoint()?.connect_lazy();
+        let client = self.configured_client(channel, metrics_meter, headers);
+        Ok(RetryClient::new(client, self.retry_config.clone()))
+    }
+
+    /// Wraps a channel to the server with metrics, default headers and the like
+    fn configured_client(
+        &self,
+        channel: Channel,
+        metrics_meter: Option<&dyn ClientMetricProvider>,
+        headers: Option<Arc<RwLock<HashMap<String, String>>>>,
+    ) -> ConfiguredClient<TemporalServiceClientWithMetrics> {
+        let channels = SwappableChannel::new(channel.clone());
         let service = ServiceBuilder::new()
             .layer_fn(|channel| GrpcMetricSvc {
                 inner: channel,
+                generation: 0,
+                channels: channels.clone(),
                 metrics: metrics_meter.map(|mm| MetricsContext::new(vec![], mm)),
             })
             .service(channel);
@@ -337,30 +450,44 @@ impl ClientOptions {
         };
         let svc = InterceptedService::new(service, interceptor);
 
-        let mut client = ConfiguredClient {
+        ConfiguredClient {
             headers,
             client: TemporalServiceClient::new(svc),
             options: Arc::new(self.clone()),
             capabilities: None,
+            channel: channels,
+        }
+    }
+
+    /// Builds and connects a channel to the server according to these options
+    async fn connect_channel(&self) -> Result<Channel, ClientInitError> {
+        Ok(self.endpoint()?.connect().await?)
+    }
+
+    /// Builds the endpoint channels to the server are connected through, according to these
+    /// options
+    fn endpoint(&self) -> Result<Endpoint, ClientInitError> {
+        let channel = Channel::from_shared(self.target_url.to_string())?;
+        let channel = self.add_tls_to_channel(channel)?;
+        let channel = if let Some(origin) = self.override_origin.clone() {
+            channel.origin(origin)
+        } else {
+            channel
         };
-        match client
-            .get_system_info(GetSystemInfoRequest::default())
-            .await
-        {
-            Ok(sysinfo) => {
-                client.capabilities = sysinfo.into_inner().capabilities;
-            }
-            Err(status) => match status.code() {
-                Code::Unimplemented => {}
-                _ => return Err(ClientInitError::SystemInfoCallError(status)),
-            },
+        let channel = if let Some(ka) = self.keep_alive {
+            channel
+                .http2_keep_alive_interval(ka.interval)
+                .keep_alive_timeout(ka.timeout)
+                .keep_alive_while_idle(ka.permit_without_stream)
+        } else {
+            channel
         };
-        Ok(RetryClient::new(client, self.retry_config.clone()))
+        Ok(channel)
     }
 
     /// If TLS is configured, set the appropriate options on the provided channel and return it.
     /// Passes it through if TLS options not set.
-    async fn add_tls_to_channel(&self, mut channel: Endpoint) -> Result<Endpoint, ClientInitError> {
+    fn add_tls_to_channel(&self, mut channel: Endpoint) -> Result<Endpoint, ClientInitError> {
         if let Some(tls_cfg) = &self.tls_cfg {
             let mut tls = tonic::transport::ClientTlsConfig::new();
 
@@ -380,7 +507,12 @@ impl ClientOptions {
                 channel = channel.origin(uri);
             }
 
-            if let Some(client_opts) = &tls_cfg.client_tls_config {
+            let client_opts = tls_cfg
+                .client_cert_source
+                .as_ref()
+                .map(|source| source.client_tls_config())
+                .or_else(|| tls_cfg.client_tls_config.clone());
+            if let Some(client_opts) = &client_opts {
                 let client_identity =
                     Identity::from_pem(&client_opts.client_cert, &client_opts.client_private_key);
                 tls = tls.identity(client_identity);
@@ -574,6 +706,11 @@ impl Client {
         &self.inner
     }
 
+    /// Returns a mutable reference to the underlying client
+    pub fn inner_mut(&mut self) -> &mut ConfiguredClient<TemporalServiceClientWithMetrics> {
+        &mut self.inner
+    }
+
     /// Consumes self and returns the underlying client
     pub fn into_inner(self) -> ConfiguredClient<TemporalServiceClientWithMetrics> {
         self.inner
@@ -1419,6 +1556,11 @@ impl<T> WfClientExt for T where T: WfHandleClient + Clone + Sized {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::Poll,
+    };
+    use tower::Service;
 
     #[test]
     fn respects_per_call_headers() {
@@ -1441,4 +1583,106 @@ mod tests {
         let next_req = iceptor.call(req).unwrap();
         assert_eq!(next_req.metadata().get("enchi").unwrap(), "cat");
     }
+
+    #[tokio::test]
+    async fn services_switch_to_replaced_channel() {
+        let lazy_channel = || Endpoint::from_static("http://localhost:7233").connect_lazy();
+        let channels = SwappableChannel::new(lazy_channel());
+        let mut svc = GrpcMetricSvc {
+            inner: lazy_channel(),
+            generation: 0,
+            channels: channels.clone(),
+            metrics: None,
+        };
+        let mut clone = svc.clone();
+        channels.replace(lazy_channel());
+        futures::future::poll_fn(|cx| {
+            let _ = svc.poll_ready(cx);
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(svc.generation, 1);
+        // Services cloned before the replacement pick it up too
+        futures::future::poll_fn(|cx| {
+            let _ = clone.poll_ready(cx);
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(clone.generation, 1);
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingCertSource(AtomicUsize);
+    impl ClientCertSource for CountingCertSource {
+        fn client_tls_config(&self) -> ClientTlsConfig {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ClientTlsConfig {
+                client_cert: b"not a cert".to_vec(),
+                client_private_key: b"not a key".to_vec(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_tls_rereads_cert_source() {
+        let source = Arc::new(CountingCertSource::default());
+        // Nothing listens on this port, so even if a reload got as far as connecting it'd be
+        // refused straight away
+        let opts = ClientOptionsBuilder::default()
+            .identity("enchicat".to_string())
+            .target_url(Url::parse("https://localhost:1").unwrap())
+            .client_name("cute-kitty".to_string())
+            .client_version("0.1.0".to_string())
+            .tls_cfg(TlsConfig {
+                client_cert_source: Some(source.clone()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let channels =
+            SwappableChannel::new(Endpoint::from_static("http://localhost:7233").connect_lazy());
+        let client = ConfiguredClient {
+            client: (),
+            options: Arc::new(opts),
+            headers: Default::default(),
+            capabilities: None,
+            channel: channels.clone(),
+        };
+
+        // The source hands out garbage, so the reloads fail, but each must have asked it anew
+        assert!(client.reload_tls().await.is_err());
+        assert_eq!(source.0.load(Ordering::SeqCst), 1);
+        assert!(client.reload_tls().await.is_err());
+        assert_eq!(source.0.load(Ordering::SeqCst), 2);
+        // A failed reload leaves the existing channel in place
+        assert!(channels.newer_than(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn set_keep_alive_swaps_in_new_channel() {
+        let opts = ClientOptionsBuilder::default()
+            .identity("enchicat".to_string())
+            .target_url(Url::parse("http://localhost:1").unwrap())
+            .client_name("cute-kitty".to_string())
+            .client_version("0.1.0".to_string())
+            .build()
+            .unwrap();
+        let channels =
+            SwappableChannel::new(Endpoint::from_static("http://localhost:7233").connect_lazy());
+        let mut client = ConfiguredClient {
+            client: (),
+            options: Arc::new(opts),
+            headers: Default::default(),
+            capabilities: None,
+            channel: channels.clone(),
+        };
+
+        let ka = KeepAliveConfig::default();
+        client.set_keep_alive(ka).unwrap();
+        assert_eq!(client.options().keep_alive, Some(ka));
+        assert_eq!(
+            channels.newer_than(0).map(|(generation, _)| generation),
+            Some(1)
+        );
+    }
 }
@@ -4,6 +4,7 @@ use opentelemetry::{
     metrics::{Counter, Histogram},
     KeyValue,
 };
+use parking_lot::RwLock;
 use std::{
     sync::Arc,
     task::{Context, Poll},
@@ -116,10 +117,35 @@ pub(crate) fn svc_operation(op: String) -> KeyValue {
     KeyValue::new(KEY_SVC_METHOD, op)
 }
 
+/// The channel calls are sent over, which may be replaced by a new one (ex: after reloading TLS
+/// certificates). Services switch to the replacement the next time they are polled for readiness,
+/// so calls already in flight finish on the channel they started on.
+#[derive(Debug, Clone)]
+pub(crate) struct SwappableChannel(Arc<RwLock<(u64, Channel)>>);
+
+impl SwappableChannel {
+    pub(crate) fn new(channel: Channel) -> Self {
+        Self(Arc::new(RwLock::new((0, channel))))
+    }
+
+    pub(crate) fn replace(&self, channel: Channel) {
+        let mut guard = self.0.write();
+        *guard = (guard.0 + 1, channel);
+    }
+
+    pub(crate) fn newer_than(&self, generation: u64) -> Option<(u64, Channel)> {
+        let guard = self.0.read();
+        (guard.0 != generation).then(|| guard.clone())
+    }
+}
+
 /// Implements metrics functionality for gRPC (really, any http) calls
 #[derive(Debug, Clone)]
 pub struct GrpcMetricSvc {
     pub(crate) inner: Channel,
+    /// Which replacement of the channel `inner` is, see [SwappableChannel]
+    pub(crate) generation: u64,
+    pub(crate) channels: SwappableChannel,
     // If set to none, metrics are a no-op
     pub(crate) metrics: Option<MetricsContext>,
 }
@@ -130,6 +156,10 @@ impl Service<http::Request<BoxBody>> for GrpcMetricSvc {
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Some((generation, channel)) = self.channels.newer_than(self.generation) {
+            self.inner = channel;
+            self.generation = generation;
+        }
         self.inner.poll_ready(cx).map_err(Into::into)
     }
 
@@ -5,8 +5,8 @@
 use crate::{
     metrics::{namespace_kv, task_queue_kv},
     raw::sealed::RawClientLike,
-    Client, ConfiguredClient, InterceptedMetricsSvc, RetryClient, TemporalServiceClient,
-    LONG_POLL_TIMEOUT,
+    Client, ConfiguredClient, InterceptedMetricsSvc, RetryAttempts, RetryClient,
+    TemporalServiceClient, LONG_POLL_TIMEOUT,
 };
 use futures::{future::BoxFuture, FutureExt, TryFutureExt};
 use temporal_sdk_core_protos::{
@@ -95,12 +95,21 @@ where
         F: Send + Sync + Unpin + 'static,
     {
         let rtc = self.get_retry_config(call_name);
+        let clock = self.clock().clone();
         let fact = || {
             let req_clone = req_cloner(&req);
             callfn(self, req_clone)
         };
-        let res = Self::make_future_retry(rtc, fact, call_name);
-        res.map_err(|(e, _attempt)| e).map_ok(|x| x.0).await
+        let res = Self::retry_with_backoff(rtc, fact, call_name, clock);
+        res.map_err(|(mut e, attempts)| {
+            RetryAttempts(attempts).record_on(&mut e);
+            e
+        })
+        .map_ok(|(mut resp, attempts)| {
+            resp.extensions_mut().insert(RetryAttempts(attempts));
+            resp
+        })
+        .await
     }
 }
 
@@ -391,7 +400,9 @@ proxier! {
             let mut labels = AttachMetricLabels::namespace(r.get_ref().namespace.clone());
             labels.task_q(r.get_ref().task_queue.clone());
             r.extensions_mut().insert(labels);
-            r.set_timeout(LONG_POLL_TIMEOUT);
+            if !r.metadata().contains_key("grpc-timeout") {
+                r.set_timeout(LONG_POLL_TIMEOUT);
+            }
         }
     );
     (
@@ -420,7 +431,9 @@ proxier! {
             let mut labels = AttachMetricLabels::namespace(r.get_ref().namespace.clone());
             labels.task_q(r.get_ref().task_queue.clone());
             r.extensions_mut().insert(labels);
-            r.set_timeout(LONG_POLL_TIMEOUT);
+            if !r.metadata().contains_key("grpc-timeout") {
+                r.set_timeout(LONG_POLL_TIMEOUT);
+            }
         }
     );
     (
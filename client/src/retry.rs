@@ -1,10 +1,16 @@
 use crate::{
+    clock::{BackoffClock, TokioClock},
     ClientOptions, ListClosedFilters, ListOpenFilters, Namespace, RegisterNamespaceOptions, Result,
     RetryConfig, SignalWithStartOptions, StartTimeFilter, WorkflowClientTrait, WorkflowOptions,
 };
-use backoff::{backoff::Backoff, exponential::ExponentialBackoff, Clock, SystemClock};
-use futures_retry::{ErrorHandler, FutureRetry, RetryPolicy};
-use std::{fmt::Debug, future::Future, sync::Arc, time::Duration};
+use backoff::{backoff::Backoff, exponential::ExponentialBackoff, Clock};
+use futures_retry::{ErrorHandler, RetryPolicy};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
 use temporal_sdk_core_protos::{
     coresdk::workflow_commands::QueryResult,
     temporal::api::{
@@ -27,18 +33,115 @@ pub const RETRYABLE_ERROR_CODES: [Code; 7] = [
     Code::OutOfRange,
     Code::Unavailable,
 ];
+
+/// Broad categories of RPC failure, so callers can act on what an error means rather than on
+/// specific status codes. See [classify_rpc_error].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RpcErrorClass {
+    /// A transient failure. The call may succeed if retried.
+    Retryable,
+    /// The server is overloaded or rate limiting us. The call may be retried, but should back off
+    /// more than usual.
+    Throttled,
+    /// The target of the call does not exist. For task completions and heartbeats this usually
+    /// means the task already timed out, or was completed or cancelled by someone else.
+    NotFound,
+    /// Credentials are missing, invalid, or lack permission for the call
+    Auth,
+    /// Retrying the call will not help
+    Terminal,
+}
+
+/// Classifies a failed RPC by what the failure means for the caller
+pub fn classify_rpc_error(status: &tonic::Status) -> RpcErrorClass {
+    match status.code() {
+        Code::ResourceExhausted => RpcErrorClass::Throttled,
+        Code::NotFound => RpcErrorClass::NotFound,
+        Code::Unauthenticated | Code::PermissionDenied => RpcErrorClass::Auth,
+        c if RETRYABLE_ERROR_CODES.contains(&c) => RpcErrorClass::Retryable,
+        _ => RpcErrorClass::Terminal,
+    }
+}
+
+/// Returns how long the server asked us to wait before retrying a throttled call, if it said. This
+/// is read from a `retry-after` trailer (in seconds), or the `reset` parameter of a `ratelimit`
+/// trailer (e.g. `limit=100, remaining=0, reset=5`).
+///
+/// The hint is returned as-is. Callers honoring it should cap it, as throttled retries do at the
+/// throttle policy's `max_interval`, so a bogus hint can't stall them indefinitely.
+pub fn retry_after_hint(status: &tonic::Status) -> Option<Duration> {
+    let md = status.metadata();
+    let secs = if let Some(v) = md.get("retry-after") {
+        v.to_str().ok()?.trim().parse::<f64>().ok()?
+    } else {
+        md.get("ratelimit")?
+            .to_str()
+            .ok()?
+            .split([',', ';'])
+            .find_map(|param| param.trim().strip_prefix("reset="))?
+            .parse::<f64>()
+            .ok()?
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
 const LONG_POLL_FATAL_GRACE: Duration = Duration::from_secs(60);
 /// Must match the method name in [crate::raw::WorkflowService]
 const POLL_WORKFLOW_METH_NAME: &str = "poll_workflow_task_queue";
 /// Must match the method name in [crate::raw::WorkflowService]
 const POLL_ACTIVITY_METH_NAME: &str = "poll_activity_task_queue";
 
+/// Inserted into the extensions of responses to raw RPCs made through a [RetryClient], and the
+/// metadata of their errors (see [RetryAttempts::of_status]), recording how many attempts the call
+/// took. A call which succeeded without being retried took 1 attempt. The time spent backing off
+/// between attempts isn't recorded separately, it's part of however long the whole call took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryAttempts(pub usize);
+
+/// The metadata key failed calls record their [RetryAttempts] under
+const RETRY_ATTEMPTS_METADATA_KEY: &str = "temporal-retry-attempts";
+
+impl RetryAttempts {
+    /// Returns how many attempts a call which failed with `status` took, if it was made through a
+    /// [RetryClient]
+    pub fn of_status(status: &tonic::Status) -> Option<Self> {
+        status
+            .metadata()
+            .get(RETRY_ATTEMPTS_METADATA_KEY)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+            .map(Self)
+    }
+
+    pub(crate) fn record_on(self, status: &mut tonic::Status) {
+        status
+            .metadata_mut()
+            .insert(RETRY_ATTEMPTS_METADATA_KEY, self.0.into());
+    }
+}
+
+/// Called with a call's name and latest failure, see [RetryConfig::on_retry_exhausting]
+#[derive(Clone)]
+pub struct RetryExhaustingCallback(pub Arc<dyn Fn(&str, &tonic::Status) + Send + Sync>);
+
+impl Debug for RetryExhaustingCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("RetryExhaustingCallback")
+    }
+}
+
 /// A wrapper for a [WorkflowClientTrait] or [crate::WorkflowService] implementor which performs
 /// auto-retries
 #[derive(Debug, Clone)]
 pub struct RetryClient<SG> {
     client: SG,
     retry_config: Arc<RetryConfig>,
+    /// Replaces the default long poll retry policy, if set
+    poll_retry_config: Option<Arc<RetryConfig>>,
+    /// Backoff is measured and waited out on this clock
+    clock: Arc<dyn crate::Clock>,
 }
 
 impl<SG> RetryClient<SG> {
@@ -47,8 +150,33 @@ impl<SG> RetryClient<SG> {
         Self {
             client,
             retry_config: Arc::new(retry_config),
+            poll_retry_config: None,
+            clock: Arc::new(TokioClock),
         }
     }
+
+    /// Replace the clock retries are timed with. Only affects this instance (and clones made from
+    /// it afterwards).
+    pub fn set_clock(&mut self, clock: Arc<dyn crate::Clock>) {
+        self.clock = clock;
+    }
+
+    /// The clock retries are timed with
+    pub fn clock(&self) -> &Arc<dyn crate::Clock> {
+        &self.clock
+    }
+
+    /// Replace the retry config used for calls other than long polls. Only affects this instance
+    /// (and clones made from it afterwards).
+    pub fn set_retry_config(&mut self, retry_config: RetryConfig) {
+        self.retry_config = Arc::new(retry_config);
+    }
+
+    /// Replace the retry config used for long polls, which by default are retried forever. Only
+    /// affects this instance (and clones made from it afterwards).
+    pub fn set_poll_retry_config(&mut self, retry_config: RetryConfig) {
+        self.poll_retry_config = Some(Arc::new(retry_config));
+    }
 }
 
 impl<SG> RetryClient<SG> {
@@ -80,30 +208,51 @@ impl<SG> RetryClient<SG> {
         Fut: Future<Output = Result<R>>,
     {
         let rtc = self.get_retry_config(call_name);
-        let res = Self::make_future_retry(rtc, factory, call_name).await;
+        let res = Self::retry_with_backoff(rtc, factory, call_name, self.clock.clone()).await;
         Ok(res.map_err(|(e, _attempt)| e)?.0)
     }
 
     pub(crate) fn get_retry_config(&self, call_name: &'static str) -> RetryConfig {
         match CallType::from_call_name(call_name) {
             CallType::Normal => (*self.retry_config).clone(),
-            CallType::LongPoll => RetryConfig::poll_retry_policy(),
+            CallType::LongPoll => self
+                .poll_retry_config
+                .as_deref()
+                .cloned()
+                .unwrap_or_else(RetryConfig::poll_retry_policy),
         }
     }
 
-    pub(crate) fn make_future_retry<R, F, Fut>(
+    /// Makes calls with `factory` until one succeeds or the retry policy gives up, waiting out
+    /// the backoff between attempts on `clock`. Either way, returns how many attempts were made.
+    pub(crate) async fn retry_with_backoff<R, F, Fut>(
         rtc: RetryConfig,
-        factory: F,
+        mut factory: F,
         call_name: &'static str,
-    ) -> FutureRetry<F, TonicErrorHandler<SystemClock>>
+        clock: Arc<dyn crate::Clock>,
+    ) -> Result<(R, usize), (tonic::Status, usize)>
     where
-        F: FnMut() -> Fut + Unpin,
+        F: FnMut() -> Fut,
         Fut: Future<Output = Result<R>>,
     {
-        FutureRetry::new(
-            factory,
-            TonicErrorHandler::new(rtc, RetryConfig::throttle_retry_policy(), call_name),
-        )
+        let mut handler = TonicErrorHandler::new(
+            rtc,
+            RetryConfig::throttle_retry_policy(),
+            call_name,
+            clock.clone(),
+        );
+        let mut attempt = 1;
+        loop {
+            match factory().await {
+                Ok(r) => return Ok((r, attempt)),
+                Err(e) => match handler.handle(attempt, e) {
+                    RetryPolicy::ForwardError(e) => return Err((e, attempt)),
+                    RetryPolicy::WaitRetry(wait) => clock.sleep(wait).await,
+                    RetryPolicy::Repeat => {}
+                },
+            }
+            attempt += 1;
+        }
     }
 }
 
@@ -114,15 +263,21 @@ pub(crate) struct TonicErrorHandler<C: Clock> {
     max_retries: usize,
     call_type: CallType,
     call_name: &'static str,
+    on_retry_exhausting: Option<RetryExhaustingCallback>,
 }
-impl TonicErrorHandler<SystemClock> {
-    fn new(cfg: RetryConfig, throttle_cfg: RetryConfig, call_name: &'static str) -> Self {
+impl TonicErrorHandler<BackoffClock> {
+    fn new(
+        cfg: RetryConfig,
+        throttle_cfg: RetryConfig,
+        call_name: &'static str,
+        clock: Arc<dyn crate::Clock>,
+    ) -> Self {
         Self::new_with_clock(
             cfg,
             throttle_cfg,
             call_name,
-            SystemClock::default(),
-            SystemClock::default(),
+            BackoffClock(clock.clone()),
+            BackoffClock(clock),
         )
     }
 }
@@ -141,6 +296,7 @@ where
             max_retries: cfg.max_retries,
             call_type: CallType::from_call_name(call_name),
             call_name,
+            on_retry_exhausting: cfg.on_retry_exhausting.clone(),
             backoff: cfg.into_exp_backoff(clock),
             throttle_backoff: throttle_cfg.into_exp_backoff(throttle_clock),
         }
@@ -190,7 +346,9 @@ where
         let long_poll_allowed =
             is_long_poll && [Code::Cancelled, Code::DeadlineExceeded].contains(&e.code());
 
-        if RETRYABLE_ERROR_CODES.contains(&e.code()) || long_poll_allowed {
+        let class = classify_rpc_error(&e);
+        if matches!(class, RpcErrorClass::Retryable | RpcErrorClass::Throttled) || long_poll_allowed
+        {
             if current_attempt == 1 {
                 debug!(error=?e, "gRPC call {} failed on first attempt", self.call_name);
             } else if self.should_log_retry_warning(current_attempt) {
@@ -200,12 +358,20 @@ where
             match self.backoff.next_backoff() {
                 None => RetryPolicy::ForwardError(e), // None is returned when we've ran out of time
                 Some(backoff) => {
-                    // We treat ResourceExhausted as a special case and backoff more
-                    // so we don't overload the server
-                    if e.code() == Code::ResourceExhausted {
+                    if self.max_retries > 0 && current_attempt + 1 == self.max_retries {
+                        if let Some(cb) = &self.on_retry_exhausting {
+                            (cb.0)(self.call_name, &e);
+                        }
+                    }
+                    // We treat throttling as a special case and backoff more so we don't
+                    // overload the server, or as long as it asked us to (up to the most we'd
+                    // back off anyway)
+                    if class == RpcErrorClass::Throttled {
                         let extended_backoff =
                             backoff.max(self.throttle_backoff.next_backoff().unwrap_or_default());
-                        RetryPolicy::WaitRetry(extended_backoff)
+                        let retry_after = retry_after_hint(&e)
+                            .map(|hint| hint.min(self.throttle_backoff.max_interval));
+                        RetryPolicy::WaitRetry(retry_after.unwrap_or(extended_backoff))
                     } else {
                         RetryPolicy::WaitRetry(backoff)
                     }
@@ -552,6 +718,7 @@ mod tests {
         max_interval: Duration::from_millis(2),
         max_elapsed_time: None,
         max_retries: 10,
+        on_retry_exhausting: None,
     };
 
     #[tokio::test]
@@ -581,6 +748,153 @@ mod tests {
         }
     }
 
+    #[test]
+    fn classifies_rpc_errors() {
+        for (code, class) in [
+            (Code::ResourceExhausted, RpcErrorClass::Throttled),
+            (Code::NotFound, RpcErrorClass::NotFound),
+            (Code::Unauthenticated, RpcErrorClass::Auth),
+            (Code::PermissionDenied, RpcErrorClass::Auth),
+            (Code::Unavailable, RpcErrorClass::Retryable),
+            (Code::Internal, RpcErrorClass::Retryable),
+            (Code::InvalidArgument, RpcErrorClass::Terminal),
+            (Code::DeadlineExceeded, RpcErrorClass::Terminal),
+        ] {
+            assert_eq!(classify_rpc_error(&Status::new(code, "")), class);
+        }
+        // Everything the retry client retries is classified as worth retrying
+        for code in RETRYABLE_ERROR_CODES {
+            assert_matches!(
+                classify_rpc_error(&Status::new(code, "")),
+                RpcErrorClass::Retryable | RpcErrorClass::Throttled
+            );
+        }
+    }
+
+    #[test]
+    fn failed_calls_carry_retry_attempts() {
+        let mut status = Status::new(Code::Unavailable, "down");
+        assert_eq!(RetryAttempts::of_status(&status), None);
+        RetryAttempts(3).record_on(&mut status);
+        assert_eq!(RetryAttempts::of_status(&status), Some(RetryAttempts(3)));
+    }
+
+    #[test]
+    fn parses_retry_after_hints() {
+        let with_trailer = |key: &'static str, val: &str| {
+            let mut md = tonic::metadata::MetadataMap::new();
+            md.insert(key, val.parse().unwrap());
+            Status::with_metadata(Code::ResourceExhausted, "slow down", md)
+        };
+        assert_eq!(
+            retry_after_hint(&with_trailer("retry-after", "3")),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            retry_after_hint(&with_trailer("retry-after", "0.5")),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            retry_after_hint(&with_trailer(
+                "ratelimit",
+                "limit=100, remaining=0, reset=7"
+            )),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(retry_after_hint(&with_trailer("retry-after", "soon")), None);
+        assert_eq!(retry_after_hint(&with_trailer("retry-after", "-1")), None);
+        assert_eq!(
+            retry_after_hint(&Status::new(Code::ResourceExhausted, "slow down")),
+            None
+        );
+    }
+
+    #[test]
+    fn throttle_waits_for_retry_after_hint() {
+        let mut err_handler = TonicErrorHandler {
+            max_retries: TEST_RETRY_CONFIG.max_retries,
+            call_type: CallType::Normal,
+            call_name: POLL_WORKFLOW_METH_NAME,
+            on_retry_exhausting: None,
+            backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
+            throttle_backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
+        };
+        let mut md = tonic::metadata::MetadataMap::new();
+        md.insert("retry-after", "2".parse().unwrap());
+        let result = err_handler.handle(
+            1,
+            Status::with_metadata(Code::ResourceExhausted, "leave me alone", md),
+        );
+        match result {
+            RetryPolicy::WaitRetry(duration) => assert_eq!(duration, Duration::from_secs(2)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn retry_after_hint_is_capped_at_throttle_max_interval() {
+        let mut err_handler = TonicErrorHandler {
+            max_retries: TEST_RETRY_CONFIG.max_retries,
+            call_type: CallType::Normal,
+            call_name: POLL_WORKFLOW_METH_NAME,
+            on_retry_exhausting: None,
+            backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
+            throttle_backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
+        };
+        let mut md = tonic::metadata::MetadataMap::new();
+        md.insert("retry-after", "86400".parse().unwrap());
+        let result = err_handler.handle(
+            1,
+            Status::with_metadata(Code::ResourceExhausted, "leave me alone", md),
+        );
+        match result {
+            RetryPolicy::WaitRetry(duration) => {
+                assert_eq!(duration, TEST_RETRY_CONFIG.max_interval)
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn warns_before_final_attempt() {
+        let calls = Arc::new(std::sync::Mutex::new(vec![]));
+        let calls_clone = calls.clone();
+        let cfg = RetryConfig {
+            max_retries: 3,
+            on_retry_exhausting: Some(RetryExhaustingCallback(Arc::new(move |call, status| {
+                calls_clone
+                    .lock()
+                    .unwrap()
+                    .push((call.to_string(), status.code()));
+            }))),
+            ..TEST_RETRY_CONFIG
+        };
+        let mut err_handler = TonicErrorHandler::new_with_clock(
+            cfg,
+            TEST_RETRY_CONFIG,
+            "respond_activity_task_completed",
+            FixedClock(Instant::now()),
+            FixedClock(Instant::now()),
+        );
+
+        let result = err_handler.handle(1, Status::new(Code::Unavailable, "down"));
+        assert_matches!(result, RetryPolicy::WaitRetry(_));
+        assert!(calls.lock().unwrap().is_empty());
+        // The next attempt is the last one
+        let result = err_handler.handle(2, Status::new(Code::Unavailable, "still down"));
+        assert_matches!(result, RetryPolicy::WaitRetry(_));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![(
+                "respond_activity_task_completed".to_string(),
+                Code::Unavailable
+            )]
+        );
+        let result = err_handler.handle(3, Status::new(Code::Unavailable, "gone"));
+        assert_matches!(result, RetryPolicy::ForwardError(_));
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
     struct FixedClock(Instant);
     impl Clock for FixedClock {
         fn now(&self) -> Instant {
@@ -604,6 +918,7 @@ mod tests {
                     max_retries: TEST_RETRY_CONFIG.max_retries,
                     call_type: CallType::LongPoll,
                     call_name,
+                    on_retry_exhausting: None,
                     backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
                     throttle_backoff: TEST_RETRY_CONFIG
                         .into_exp_backoff(FixedClock(Instant::now())),
@@ -629,6 +944,7 @@ mod tests {
                     max_retries: TEST_RETRY_CONFIG.max_retries,
                     call_type: CallType::LongPoll,
                     call_name,
+                    on_retry_exhausting: None,
                     backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
                     throttle_backoff: TEST_RETRY_CONFIG
                         .into_exp_backoff(FixedClock(Instant::now())),
@@ -679,6 +995,7 @@ mod tests {
             max_retries: TEST_RETRY_CONFIG.max_retries,
             call_type: CallType::Normal,
             call_name: POLL_WORKFLOW_METH_NAME,
+            on_retry_exhausting: None,
             backoff: TEST_RETRY_CONFIG.into_exp_backoff(FixedClock(Instant::now())),
             throttle_backoff: RetryConfig {
                 initial_interval: Duration::from_millis(2),
@@ -687,6 +1004,7 @@ mod tests {
                 max_interval: Duration::from_millis(10),
                 max_elapsed_time: None,
                 max_retries: 10,
+                on_retry_exhausting: None,
             }
             .into_exp_backoff(FixedClock(Instant::now())),
         };
@@ -719,6 +1037,7 @@ mod tests {
                     fake_retry.get_retry_config(call),
                     fake_retry.get_retry_config(call),
                     call,
+                    fake_retry.clock().clone(),
                 );
                 let result = err_handler.handle(i, Status::new(Code::Unknown, "Ahh"));
                 assert_matches!(result, RetryPolicy::WaitRetry(_));
@@ -726,6 +1045,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn retry_configs_can_be_overridden() {
+        let mut retry_client = RetryClient::new((), TEST_RETRY_CONFIG);
+        assert_eq!(
+            retry_client
+                .get_retry_config(POLL_WORKFLOW_METH_NAME)
+                .max_retries,
+            0
+        );
+
+        retry_client.set_poll_retry_config(RetryConfig {
+            randomization_factor: 0.5,
+            ..TEST_RETRY_CONFIG
+        });
+        retry_client.set_retry_config(RetryConfig {
+            max_retries: 2,
+            ..TEST_RETRY_CONFIG
+        });
+        for call in [POLL_WORKFLOW_METH_NAME, POLL_ACTIVITY_METH_NAME] {
+            let cfg = retry_client.get_retry_config(call);
+            assert_eq!(cfg.randomization_factor, 0.5);
+            assert_eq!(cfg.max_retries, TEST_RETRY_CONFIG.max_retries);
+        }
+        let cfg = retry_client.get_retry_config("respond_activity_task_completed");
+        assert_eq!(cfg.max_retries, 2);
+        assert_eq!(cfg.randomization_factor, 0.0);
+    }
+
     #[tokio::test]
     async fn long_poll_retries_deadline_exceeded() {
         let fake_retry = RetryClient::new((), TEST_RETRY_CONFIG);
@@ -736,6 +1083,7 @@ mod tests {
                     fake_retry.get_retry_config(call),
                     fake_retry.get_retry_config(call),
                     call,
+                    fake_retry.clock().clone(),
                 );
                 for i in 1..=5 {
                     let result = err_handler.handle(i, Status::new(code, "retryable failure"));
@@ -744,4 +1092,43 @@ mod tests {
             }
         }
     }
+
+    #[derive(Debug, Default)]
+    struct RecordingClock {
+        slept: std::sync::Mutex<Vec<Duration>>,
+    }
+
+    impl crate::Clock for RecordingClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn sleep(&self, duration: Duration) -> futures::future::BoxFuture<'static, ()> {
+            self.slept.lock().unwrap().push(duration);
+            Box::pin(futures::future::ready(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn backoff_waits_on_injected_clock() {
+        let clock = Arc::new(RecordingClock::default());
+        let mut failures = 2;
+        let res = RetryClient::<()>::retry_with_backoff(
+            TEST_RETRY_CONFIG,
+            || {
+                let res = if failures > 0 {
+                    failures -= 1;
+                    Err(Status::new(Code::Unavailable, "down"))
+                } else {
+                    Ok("up")
+                };
+                async move { res }
+            },
+            "get_system_info",
+            clock.clone(),
+        )
+        .await;
+        assert_eq!(res.unwrap(), ("up", 3));
+        assert_eq!(clock.slept.lock().unwrap().len(), 2);
+    }
 }
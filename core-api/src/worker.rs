@@ -1,8 +1,22 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+use temporal_client::{KeepAliveConfig, RetryConfig};
+use temporal_sdk_core_protos::temporal::api::common::v1::Payload;
 use tokio::sync::mpsc::UnboundedSender;
+use tonic::{codec::CompressionEncoding, metadata::MetadataMap};
 
 const MAX_OUTSTANDING_WFT_DEFAULT: usize = 100;
 const MAX_CONCURRENT_WFT_POLLS_DEFAULT: usize = 5;
+/// The server's default limit on the size of a single blob
+const MAX_HEARTBEAT_DETAILS_SIZE_DEFAULT: usize = 2 * 1024 * 1024;
+/// The server times out polls after 60 seconds. Our default is slightly beyond that.
+const DEFAULT_POLL_DEADLINE: Duration = Duration::from_secs(70);
+/// Matches the timeout the client applies to calls which don't set their own
+const DEFAULT_OTHER_DEADLINE: Duration = Duration::from_secs(30);
 
 /// Defines per-worker configuration options
 #[derive(Debug, Clone, derive_builder::Builder, serde::Serialize, serde::Deserialize)]
@@ -17,11 +31,28 @@ pub struct WorkerConfig {
     /// A string that should be unique to the set of code this worker uses. IE: All the workflow,
     /// activity, interceptor, and data converter code.
     pub worker_build_id: String,
+    /// If set, this value is always reported to the server as the worker's binary checksum,
+    /// regardless of the build id or which versioning features the server supports. Useful for
+    /// pinning a stable checksum for deployment tracking.
+    #[builder(default)]
+    pub binary_checksum_override: Option<String>,
     /// A human-readable string that can identify this worker. Using something like sdk version
     /// and host name is a good default. If set, overrides the identity set (if any) on the client
     /// used by this worker.
     #[builder(default)]
     pub client_identity_override: Option<String>,
+    /// Static gRPC metadata attached to every RPC this worker makes. Keys and values must be valid
+    /// ASCII metadata, or worker initialization will fail.
+    #[builder(default)]
+    pub rpc_headers: HashMap<String, String>,
+    /// If set, sent as the `client-name` header of this worker's RPCs instead of the name the
+    /// client was connected with. Lets SDKs built on core identify themselves to the server.
+    #[builder(default)]
+    pub client_name: Option<String>,
+    /// If set, sent as the `client-version` header of this worker's RPCs instead of the version
+    /// the client was connected with. See [WorkerConfig::client_name].
+    #[builder(default)]
+    pub client_version: Option<String>,
     /// If set nonzero, workflows will be cached and sticky task queues will be used, meaning that
     /// history updates are applied incrementally to suspended instances of workflow execution.
     /// Workflows are evicted according to a least-recently-used policy one the cache maximum is
@@ -79,6 +110,12 @@ pub struct WorkerConfig {
     #[builder(default = "Duration::from_secs(30)")]
     pub default_heartbeat_throttle_interval: Duration,
 
+    /// Heartbeats for an activity are normally coalesced, so that only the latest details are sent
+    /// at most once per throttle interval (see the two options above), and the final details are
+    /// flushed when the activity completes. If set, every heartbeat is sent to the server instead.
+    #[builder(default)]
+    pub disable_heartbeat_throttling: bool,
+
     /// Sets the maximum number of activities per second the task queue will dispatch, controlled
     /// server-side. Note that this only takes effect upon an activity poll request. If multiple
     /// workers on the same queue have different values set, they will thrash with the last poller
@@ -86,6 +123,23 @@ pub struct WorkerConfig {
     #[builder(default)]
     pub max_task_queue_activities_per_second: Option<f64>,
 
+    /// If set, activity polls ask the server for tasks affine to this build id, when the server
+    /// supports build-id based versioning. This is only a hint, and independent of
+    /// [WorkerConfig::use_worker_versioning].
+    #[builder(default)]
+    pub activity_build_id_affinity: Option<String>,
+
+    /// If set, this worker only runs activities of these types. Activity tasks of any other type
+    /// are immediately failed back to the server with a retryable `NotAcceptedHere` failure, so
+    /// they are retried, possibly by another worker polling the same task queue.
+    ///
+    /// Every time a task lands on a worker which doesn't accept it, this costs a round trip to the
+    /// server plus the activity's retry backoff, and uses up one of its retry policy's attempts.
+    /// Polling separate task queues for different activity types avoids all of that, and should
+    /// be preferred where possible.
+    #[builder(default)]
+    pub accepted_activity_types: Option<HashSet<String>>,
+
     /// Limits the number of activities per second that this worker will process. The worker will
     /// not poll for new activities if by doing so it might receive and execute an activity which
     /// would cause it to exceed this limit. Negative, zero, or NaN values will cause building
@@ -93,6 +147,12 @@ pub struct WorkerConfig {
     #[builder(default)]
     pub max_worker_activities_per_second: Option<f64>,
 
+    /// Activity heartbeats whose details are larger than this many bytes (once encoded) are
+    /// rejected by the worker without being sent, rather than being rejected by the server.
+    /// Defaults to the server's default blob size limit of 2MB.
+    #[builder(default = "MAX_HEARTBEAT_DETAILS_SIZE_DEFAULT")]
+    pub max_heartbeat_details_size: usize,
+
     /// # UNDER DEVELOPMENT
     /// If set to true this worker will opt-in to the whole-worker versioning feature.
     /// `worker_build_id` will be used as the version.
@@ -126,6 +186,226 @@ pub struct WorkerConfig {
     /// initiated and this amount of time has elapsed.
     #[builder(default)]
     pub graceful_shutdown_period: Option<Duration>,
+
+    /// If set, polls will stop being issued for a while after the server repeatedly reports it is
+    /// overloaded. See [CircuitBreakerConfig].
+    #[builder(default)]
+    pub poll_circuit_breaker: Option<CircuitBreakerConfig>,
+
+    /// If set, called after every RPC this worker makes with its outcome. Panics in the callback
+    /// are caught and logged rather than failing the RPC.
+    #[builder(default)]
+    #[serde(skip)]
+    pub on_rpc_complete: Option<RpcObserver>,
+
+    /// If set, payloads the worker sends to the server are encoded with this codec, and those it
+    /// receives (in histories, queries and activity tasks) are decoded with it. See
+    /// [PayloadCodec] for exactly which payloads are affected.
+    #[builder(default)]
+    #[serde(skip)]
+    pub payload_codec: Option<Arc<dyn PayloadCodec>>,
+
+    /// HTTP/2 keep-alive settings for this worker's connection. If the client passed to the worker
+    /// wasn't connected with these settings (see `ClientOptions::keep_alive`), it's switched over
+    /// to a new connection which uses them when the worker is initialized. Clones of the client,
+    /// including those used by other workers, share the connection and so switch too.
+    #[builder(default)]
+    #[serde(skip)]
+    pub keep_alive: Option<KeepAliveConfig>,
+
+    /// If set, this worker's `WorkflowService` RPCs (polls, completions, heartbeats and the like)
+    /// are sent compressed with this encoding, and compressed responses are accepted. Other
+    /// services the client talks to (ex: operator and health checks) are unaffected. Off by
+    /// default, since not every server or proxy supports it.
+    ///
+    /// This matters mostly for workflow task polls, whose responses include history and can be
+    /// several MB for large workflows. Compression trades some CPU on both ends (and the
+    /// corresponding latency) for less data on the wire, so it's most worthwhile when bandwidth
+    /// between worker and server is constrained or histories are large and repetitive. We haven't
+    /// measured the tradeoff, so benchmark against your own workload before turning it on.
+    #[builder(default)]
+    #[serde(skip)]
+    pub rpc_compression: Option<CompressionEncoding>,
+
+    /// If set, the worker re-fetches the server's capabilities every this many polls, so that it
+    /// starts using newly supported features (ex: build-id based versioning) if the server is
+    /// upgraded while the worker is running. Must be at least 1.
+    #[builder(default)]
+    pub capabilities_refresh_poll_interval: Option<usize>,
+
+    /// If set, at most this many task completion RPCs (workflow and activity task completions and
+    /// failures) are in flight at once. Completions beyond that wait for one to finish, rather
+    /// than all being sent at once, which can overwhelm the connection's flow control during
+    /// bursts. Polls are never limited. Must be at least 1.
+    ///
+    /// The number of completions waiting is reported in
+    /// [RpcObservation::queued_completions].
+    #[builder(default)]
+    pub max_concurrent_completions: Option<usize>,
+
+    /// Called, in order, before every RPC this worker makes, after its static headers have been
+    /// attached. See [RpcInterceptor].
+    #[builder(default)]
+    #[serde(skip)]
+    pub rpc_interceptors: Vec<Arc<dyn RpcInterceptor>>,
+
+    /// If set, replaces the retry policies of the client passed to the worker, for this worker's
+    /// RPCs only. Useful for spreading out retries (ex: with more jitter) across many workers
+    /// sharing a client configuration. See [RetryPolicyOverride].
+    #[builder(default)]
+    #[serde(skip)]
+    pub rpc_retry_override: Option<RetryPolicyOverride>,
+
+    /// gRPC deadlines for this worker's RPCs. See [RpcDeadlines] for the defaults.
+    #[builder(default)]
+    pub rpc_deadlines: RpcDeadlines,
+}
+
+/// Transforms payloads (ex: encrypting or compressing them) on their way to and from the server.
+///
+/// When configured on a worker, it is applied to:
+/// * Activity, child workflow, signal, and continue-as-new inputs, and workflow results /
+///   cancellation details in workflow task completions (encoded)
+/// * Activity results, cancellation details and heartbeat details, signal inputs, and termination
+///   details (encoded)
+/// * Query results in workflow task completions and legacy query responses (encoded)
+/// * The matching payloads in polled (or paginated) history events, workflow and signal inputs,
+///   and query arguments (decoded)
+/// * Inputs and heartbeat details of polled activity tasks (decoded)
+///
+/// Workflow and signal inputs are sent in by clients, which must encode them with the same codec.
+/// Markers, failures, memos, headers and search attributes are left as-is.
+pub trait PayloadCodec: Debug + Send + Sync {
+    /// Encodes payloads which are about to be sent to the server
+    fn encode(&self, payloads: Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError>;
+    /// Decodes payloads which were received from the server
+    fn decode(&self, payloads: Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError>;
+}
+
+/// Returned by a [PayloadCodec] which could not encode or decode a set of payloads
+#[derive(thiserror::Error, Debug)]
+#[error("Payload codec failed: {0}")]
+pub struct PayloadCodecError(pub String);
+
+/// Called before each RPC a worker makes, ex: to attach a freshly minted auth token or trace
+/// context to the request's metadata. See [WorkerConfig::rpc_interceptors].
+#[async_trait::async_trait]
+pub trait RpcInterceptor: Debug + Send + Sync {
+    /// Called once per RPC made by the worker, before the request is first sent. Retries of the
+    /// RPC resend the metadata as it was left by the interceptors.
+    async fn before(&self, ctx: &mut RpcContext<'_>);
+}
+
+/// The RPC an [RpcInterceptor] is being called for
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RpcContext<'a> {
+    /// Name of the RPC, ex: `poll_workflow_task_queue`
+    pub method: &'static str,
+    /// The request's metadata, which interceptors may add to or change. Already contains the
+    /// worker's static headers.
+    pub metadata: &'a mut MetadataMap,
+}
+
+impl<'a> RpcContext<'a> {
+    /// Create a context for the named RPC with the given request metadata
+    pub fn new(method: &'static str, metadata: &'a mut MetadataMap) -> Self {
+        Self { method, metadata }
+    }
+}
+
+/// The outcome of a single RPC made by a worker, see [WorkerConfig::on_rpc_complete]
+#[derive(Debug, Clone)]
+pub struct RpcObservation {
+    /// Name of the RPC, ex: `poll_workflow_task_queue`
+    pub method: &'static str,
+    /// How long the call took, including any retries
+    pub duration: Duration,
+    /// Status code the call completed with, `Ok` if it succeeded
+    pub code: tonic::Code,
+    /// How many attempts the call took, including retries, if known
+    pub attempts: Option<usize>,
+    /// How many task completions were waiting to be sent when the call finished. Only known if
+    /// [WorkerConfig::max_concurrent_completions] is set.
+    pub queued_completions: Option<usize>,
+    /// Set when the call failed a workflow task because of non-determinism, with what didn't
+    /// match
+    pub non_determinism: Option<NonDeterminismDetail>,
+    /// Set when the call completed (or failed, or cancelled) a task this worker polled, with how
+    /// long the task took to process
+    pub task_processing: Option<TaskProcessingLatency>,
+}
+
+/// How long a task took to process, from being handed out by a poll until the worker completed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskProcessingLatency {
+    /// The task queue the task was polled from. Tasks polled from a sticky queue report the
+    /// normal queue it belongs to.
+    pub task_queue: String,
+    /// Time from the poll returning the task until its completion was issued
+    pub latency: Duration,
+}
+
+/// Describes how a workflow's code diverged from its history
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NonDeterminismDetail {
+    /// What history says happened, ex: the type of the next command event
+    pub expected: String,
+    /// What the workflow code did instead, ex: the type of the command it issued
+    pub actual: String,
+    /// The id of the history event which didn't match
+    pub event_id: i64,
+}
+
+/// A callback invoked with an [RpcObservation] after each RPC a worker makes
+#[derive(Clone)]
+pub struct RpcObserver(pub Arc<dyn Fn(RpcObservation) + Send + Sync>);
+
+impl Debug for RpcObserver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("RpcObserver")
+    }
+}
+
+/// Configures the circuit breaker which may be placed in front of a worker's poll RPCs. After
+/// `failure_threshold` consecutive polls fail with `ResourceExhausted` or `Unavailable`, the
+/// breaker opens and polls fail immediately, without contacting the server, until `cooldown` has
+/// elapsed. The first poll after that is let through, and closes the breaker again if it succeeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// How many consecutive overload failures open the breaker. Must be at least 1.
+    pub failure_threshold: usize,
+    /// How long the breaker stays open once tripped
+    pub cooldown: Duration,
+}
+
+/// gRPC deadlines applied to each RPC a worker issues, see [WorkerConfig::rpc_deadlines]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RpcDeadlines {
+    /// Deadline for long-poll RPCs, 70 seconds by default. The server holds polls open for up to
+    /// 60 seconds, so shorter deadlines cut off polls which would otherwise have returned a task.
+    pub poll: Duration,
+    /// Deadline for everything else (completions, heartbeats, etc), 30 seconds by default
+    pub other: Duration,
+}
+
+impl Default for RpcDeadlines {
+    fn default() -> Self {
+        Self {
+            poll: DEFAULT_POLL_DEADLINE,
+            other: DEFAULT_OTHER_DEADLINE,
+        }
+    }
+}
+
+/// Retry policies replacing those a worker's client would otherwise use, see
+/// [WorkerConfig::rpc_retry_override]. Unset policies are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicyOverride {
+    /// Used for workflow and activity task polls, which are retried forever by default
+    pub poll: Option<RetryConfig>,
+    /// Used for every other RPC, including task completions
+    pub other: Option<RetryConfig>,
 }
 
 impl WorkerConfig {
@@ -148,6 +428,45 @@ impl WorkerConfigBuilder {
         if self.max_concurrent_at_polls == Some(0) {
             return Err("`max_concurrent_at_polls` must be at least 1".to_owned());
         }
+        if let Some(Some(ref cb)) = self.poll_circuit_breaker {
+            if cb.failure_threshold == 0 {
+                return Err(
+                    "`poll_circuit_breaker.failure_threshold` must be at least 1".to_owned(),
+                );
+            }
+        }
+        if self.capabilities_refresh_poll_interval == Some(Some(0)) {
+            return Err("`capabilities_refresh_poll_interval` must be at least 1".to_owned());
+        }
+        if self.max_concurrent_completions == Some(Some(0)) {
+            return Err("`max_concurrent_completions` must be at least 1".to_owned());
+        }
+        if let Some(Some(ref o)) = self.rpc_retry_override {
+            for cfg in o.poll.iter().chain(o.other.iter()) {
+                if !(0.0..=1.0).contains(&cfg.randomization_factor) {
+                    return Err(
+                        "`rpc_retry_override` randomization factors must be between 0 and 1"
+                            .to_owned(),
+                    );
+                }
+                if cfg.multiplier < 1.0 {
+                    return Err("`rpc_retry_override` multipliers must be at least 1".to_owned());
+                }
+            }
+        }
+        if let Some(ref d) = self.rpc_deadlines {
+            if d.poll.is_zero() || d.other.is_zero() {
+                return Err("`rpc_deadlines` must be positive".to_owned());
+            }
+        }
+        if let Some(timeout) = self.sticky_queue_schedule_to_start_timeout {
+            if timeout.is_zero() {
+                return Err("`sticky_queue_schedule_to_start_timeout` must be positive".to_owned());
+            }
+            if prost_types::Duration::try_from(timeout).is_err() {
+                return Err("`sticky_queue_schedule_to_start_timeout` is too large".to_owned());
+            }
+        }
         if self.max_cached_workflows > Some(0)
             && self.max_outstanding_workflow_tasks > self.max_cached_workflows
         {
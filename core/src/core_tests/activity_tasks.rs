@@ -6,7 +6,7 @@ use crate::{
         single_hist_mock_sg, test_worker_cfg, MockPollCfg, MockWorkerInputs, MocksHolder,
         QueueResponse, ResponseType, WorkerExt, WorkflowCachingPolicy, TEST_Q,
     },
-    worker::client::mocks::{mock_manual_workflow_client, mock_workflow_client},
+    worker::client::mocks::{happy_path_client, mock_manual_workflow_client, mock_workflow_client},
     ActivityHeartbeat, Worker, WorkerConfigBuilder,
 };
 use futures::FutureExt;
@@ -89,7 +89,7 @@ async fn max_activities_respected() {
     mock_client
         .expect_poll_activity_task()
         .times(3)
-        .returning(move |_, _| Ok(tasks.pop_front().unwrap()));
+        .returning(move |_, _, _, _, _| Ok(tasks.pop_front().unwrap()));
     mock_client
         .expect_complete_activity_task()
         .returning(|_, _| Ok(RespondActivityTaskCompletedResponse::default()));
@@ -154,7 +154,7 @@ async fn heartbeats_report_cancels_only_once() {
     mock_client
         .expect_cancel_activity_task()
         .times(1)
-        .returning(|_, _| Ok(RespondActivityTaskCanceledResponse::default()));
+        .returning(|_, _, _| Ok(RespondActivityTaskCanceledResponse::default()));
 
     let core = mock_worker(MocksHolder::from_client_with_activities(
         mock_client,
@@ -372,10 +372,10 @@ async fn many_concurrent_heartbeat_cancels() {
     let mut calls_map = HashMap::<_, i32>::new();
     mock_client
         .expect_poll_activity_task()
-        .returning(move |_, _| poll_resps.pop_front().unwrap());
+        .returning(move |_, _, _, _, _| poll_resps.pop_front().unwrap());
     mock_client
         .expect_cancel_activity_task()
-        .returning(move |_, _| async move { Ok(Default::default()) }.boxed());
+        .returning(move |_, _, _| async move { Ok(Default::default()) }.boxed());
     mock_client
         .expect_record_activity_heartbeat()
         .returning(move |tt, _| {
@@ -570,7 +570,7 @@ async fn complete_act_with_fail_flushes_heartbeat() {
     mock_client
         .expect_fail_activity_task()
         .times(1)
-        .returning(|_, _| Ok(RespondActivityTaskFailedResponse::default()));
+        .returning(|_, _, _| Ok(RespondActivityTaskFailedResponse::default()));
 
     let core = mock_worker(MocksHolder::from_client_with_activities(
         mock_client,
@@ -605,18 +605,66 @@ async fn complete_act_with_fail_flushes_heartbeat() {
 }
 
 #[tokio::test]
-async fn max_tq_acts_set_passed_to_poll_properly() {
-    let rate = 9.28;
+async fn fail_act_includes_last_heartbeat_details() {
     let mut mock_client = mock_workflow_client();
     mock_client
-        .expect_poll_activity_task()
-        .returning(move |_, tps| {
-            assert_eq!(tps, Some(rate));
-            Ok(PollActivityTaskQueueResponse {
-                task_token: vec![1],
-                ..Default::default()
+        .expect_record_activity_heartbeat()
+        .returning(|_, _| {
+            Ok(RecordActivityTaskHeartbeatResponse {
+                cancel_requested: false,
             })
         });
+    mock_client
+        .expect_fail_activity_task()
+        .times(1)
+        .returning(|_, _, last_hb_details| {
+            let last_hb_details = last_hb_details.expect("heartbeat details are attached");
+            assert_eq!(last_hb_details.payloads[0].data, &[3]);
+            Ok(RespondActivityTaskFailedResponse::default())
+        });
+
+    let core = mock_worker(MocksHolder::from_client_with_activities(
+        mock_client,
+        [PollActivityTaskQueueResponse {
+            task_token: vec![1],
+            activity_id: "act1".to_string(),
+            heartbeat_timeout: Some(prost_dur!(from_secs(10))),
+            ..Default::default()
+        }
+        .into()],
+    ));
+
+    let act = core.poll_activity_task().await.unwrap();
+    for i in 1..=3 {
+        core.record_activity_heartbeat(ActivityHeartbeat {
+            task_token: act.task_token.clone(),
+            details: vec![vec![i].into()],
+        });
+    }
+    core.complete_activity_task(ActivityTaskCompletion {
+        task_token: act.task_token.clone(),
+        result: Some(ActivityExecutionResult::fail("Ahh".into())),
+    })
+    .await
+    .unwrap();
+    core.drain_activity_poller_and_shutdown().await;
+}
+
+#[tokio::test]
+async fn max_tq_acts_set_passed_to_poll_properly() {
+    let rate = 9.28;
+    let mock_client = happy_path_client()
+        .customize(move |c| {
+            c.expect_poll_activity_task()
+                .returning(move |_, tps, _, _, _| {
+                    assert_eq!(tps, Some(rate));
+                    Ok(PollActivityTaskQueueResponse {
+                        task_token: vec![1],
+                        ..Default::default()
+                    })
+                });
+        })
+        .build();
 
     let cfg = WorkerConfigBuilder::default()
         .namespace("enchi")
@@ -1018,15 +1066,12 @@ async fn retryable_net_error_exhaustion_is_nonfatal() {
 
 #[tokio::test]
 async fn cant_complete_activity_with_unset_result_payload() {
-    let mut mock_client = mock_workflow_client();
-    mock_client
-        .expect_poll_activity_task()
-        .returning(move |_, _| {
-            Ok(PollActivityTaskQueueResponse {
-                task_token: vec![1],
-                ..Default::default()
-            })
-        });
+    let mock_client = happy_path_client()
+        .activity_polls([PollActivityTaskQueueResponse {
+            task_token: vec![1],
+            ..Default::default()
+        }])
+        .build();
 
     let cfg = WorkerConfigBuilder::default()
         .namespace("enchi")
@@ -1072,7 +1117,7 @@ async fn graceful_shutdown(#[values(true, false)] at_max_outstanding: bool) {
     mock_client
         .expect_fail_activity_task()
         .times(3)
-        .returning(|_, _| Ok(Default::default()));
+        .returning(|_, _, _| Ok(Default::default()));
 
     let max_outstanding = if at_max_outstanding { 3_usize } else { 100 };
     let mw = MockWorkerInputs {
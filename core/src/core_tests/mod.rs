@@ -53,7 +53,7 @@ async fn shutdown_interrupts_both_polls() {
     mock_client
         .expect_poll_activity_task()
         .times(1)
-        .returning(move |_, _| {
+        .returning(move |_, _, _, _, _| {
             async move {
                 BARR.wait().await;
                 sleep(Duration::from_secs(1)).await;
@@ -64,7 +64,7 @@ async fn shutdown_interrupts_both_polls() {
     mock_client
         .expect_poll_workflow_task()
         .times(1)
-        .returning(move |_| {
+        .returning(move |_, _, _| {
             async move {
                 BARR.wait().await;
                 sleep(Duration::from_secs(1)).await;
@@ -473,7 +473,7 @@ async fn query_cache_miss_causes_page_fetch_dont_reply_wft_too_early(
     if !matches!(hist_type, QueryHists::Full) {
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(full_hist.clone().into()),
                     ..Default::default()
@@ -4,7 +4,7 @@ use crate::{
         build_fake_worker, build_mock_pollers, canned_histories, mock_worker, MockPollCfg,
         MockWorkerInputs, MocksHolder, ResponseType, WorkerExt,
     },
-    worker::client::mocks::mock_workflow_client,
+    worker::client::mocks::{happy_path_client, mock_workflow_client},
     PollActivityError, PollWfError,
 };
 use futures_util::{stream, stream::StreamExt};
@@ -16,9 +16,7 @@ use temporal_sdk_core_protos::{
         workflow_commands::{workflow_command, CompleteWorkflowExecution, StartTimer},
         workflow_completion::WorkflowActivationCompletion,
     },
-    temporal::api::workflowservice::v1::{
-        PollWorkflowTaskQueueResponse, RespondWorkflowTaskCompletedResponse,
-    },
+    temporal::api::workflowservice::v1::PollWorkflowTaskQueueResponse,
 };
 use temporal_sdk_core_test_utils::start_timer_cmd;
 use tokio::sync::{watch, Barrier};
@@ -101,10 +99,7 @@ async fn worker_shutdown_during_poll_doesnt_deadlock() {
         ))
     });
     let mw = MockWorkerInputs::new(stream.boxed());
-    let mut mock_client = mock_workflow_client();
-    mock_client
-        .expect_complete_workflow_task()
-        .returning(|_| Ok(RespondWorkflowTaskCompletedResponse::default()));
+    let mock_client = happy_path_client().build();
     let worker = mock_worker(MocksHolder::from_mock_worker(mock_client, mw));
     let pollfut = worker.poll_workflow_activation();
     let shutdownfut = async {
@@ -164,7 +159,7 @@ async fn complete_with_task_not_found_during_shutdown() {
     let mut mock = mock_workflow_client();
     mock.expect_complete_workflow_task()
         .times(1)
-        .returning(|_| Err(tonic::Status::not_found("Workflow task not found.")));
+        .returning(|_| Err(tonic::Status::not_found("Workflow task not found.").into()));
     let mh = MockPollCfg::from_resp_batches("fakeid", t, [1], mock);
     let core = mock_worker(build_mock_pollers(mh));
 
@@ -236,7 +231,7 @@ async fn worker_does_not_panic_on_retry_exhaustion_of_nonfatal_net_err() {
     // Return a failure that counts as retryable, and hence we want to be swallowed
     mock.expect_complete_workflow_task()
         .times(1)
-        .returning(|_| Err(tonic::Status::internal("Some retryable error")));
+        .returning(|_| Err(tonic::Status::internal("Some retryable error").into()));
     let mut mh =
         MockPollCfg::from_resp_batches("fakeid", t, [1.into(), ResponseType::AllHistory], mock);
     mh.enforce_correct_number_of_polls = false;
@@ -61,7 +61,7 @@ use temporal_sdk_core_protos::{
 use temporal_sdk_core_test_utils::{fanout_tasks, start_timer_cmd, WorkerTestHelpers};
 use tokio::{
     join,
-    sync::{Barrier, Semaphore},
+    sync::{Barrier, Notify, Semaphore},
     time,
 };
 
@@ -1397,7 +1397,7 @@ async fn lang_slower_than_wft_timeouts() {
     let mut mock = mock_workflow_client();
     mock.expect_complete_workflow_task()
         .times(1)
-        .returning(|_| Err(tonic::Status::not_found("Workflow task not found.")));
+        .returning(|_| Err(tonic::Status::not_found("Workflow task not found.").into()));
     mock.expect_complete_workflow_task()
         .times(1)
         .returning(|_| Ok(Default::default()));
@@ -1577,7 +1577,7 @@ async fn cache_miss_will_fetch_history() {
     mh.mock_client
         .expect_get_workflow_execution_history()
         .times(1)
-        .returning(move |_, _, _| Ok(get_exec_resp.clone()));
+        .returning(move |_, _, _, _, _| Ok(get_exec_resp.clone()));
     let mut mock = build_mock_pollers(mh);
     mock.worker_cfg(|cfg| {
         cfg.max_cached_workflows = 1;
@@ -1761,7 +1761,7 @@ async fn pagination_works_with_tasks_from_completion() {
     let get_exec_resp: GetWorkflowExecutionHistoryResponse =
         t.get_full_history_info().unwrap().into();
     mock.expect_get_workflow_execution_history()
-        .returning(move |_, _, _| Ok(get_exec_resp.clone()))
+        .returning(move |_, _, _, _, _| Ok(get_exec_resp.clone()))
         .times(1);
 
     let mut mock = single_hist_mock_sg(wfid, t, [1], mock, true);
@@ -1919,6 +1919,67 @@ async fn poll_faster_than_complete_wont_overflow_cache() {
     assert_eq!(core.cached_workflows().await, 3);
 }
 
+#[tokio::test]
+async fn evicting_for_cache_space_disclaims_sticky() {
+    let tasks: Vec<_> = (1..=2)
+        .map(|i| FakeWfResponses {
+            wf_id: format!("wf-{i}"),
+            hist: canned_histories::single_timer("1"),
+            response_batches: vec![ResponseType::ToTaskNum(1)],
+        })
+        .collect();
+    let disclaimed = Arc::new(Notify::new());
+    let disclaimed_c = disclaimed.clone();
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_disclaim_sticky()
+        .withf(|execution| execution.workflow_id == "wf-1")
+        .times(1)
+        .returning(move |_| {
+            disclaimed_c.notify_one();
+            Ok(())
+        });
+    let mut mock_cfg = MockPollCfg::new(tasks, true, 0);
+    mock_cfg.mock_client = mock_client;
+    let mut mock = build_mock_pollers(mock_cfg);
+    mock.worker_cfg(|wc| wc.max_cached_workflows = 1);
+    let core = mock_worker(mock);
+
+    let act = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmd(
+        act.run_id,
+        start_timer_cmd(1, Duration::from_secs(1)),
+    ))
+    .await
+    .unwrap();
+    // The second run needs the first one's cache slot
+    let evict = core.poll_workflow_activation().await.unwrap();
+    assert_matches!(
+        evict.jobs.as_slice(),
+        [WorkflowActivationJob {
+            variant: Some(workflow_activation_job::Variant::RemoveFromCache(_)),
+        }]
+    );
+    core.complete_workflow_activation(WorkflowActivationCompletion::empty(evict.run_id))
+        .await
+        .unwrap();
+    let act = core.poll_workflow_activation().await.unwrap();
+    assert_matches!(
+        &act.jobs[0].variant,
+        Some(workflow_activation_job::Variant::StartWorkflow(sw)) if sw.workflow_id == "wf-2"
+    );
+    time::timeout(Duration::from_secs(5), disclaimed.notified())
+        .await
+        .unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmd(
+        act.run_id,
+        start_timer_cmd(1, Duration::from_secs(1)),
+    ))
+    .await
+    .unwrap();
+    core.shutdown().await;
+}
+
 #[tokio::test]
 async fn eviction_waits_until_replay_finished() {
     let wfid = "fake_wf_id";
@@ -2042,15 +2103,17 @@ async fn no_race_acquiring_permits() {
     // We need to allow two polls to happen by triggering two processing events in the workflow
     // stream, but then delivering the actual tasks after that
     let task_barr: &'static Barrier = Box::leak(Box::new(Barrier::new(2)));
-    mock_client.expect_poll_workflow_task().returning(move |_| {
-        let t = canned_histories::single_timer("1");
-        let poll_resp = hist_to_poll_resp(&t, wfid.to_owned(), 2.into()).resp;
-        async move {
-            task_barr.wait().await;
-            Ok(poll_resp.clone())
-        }
-        .boxed()
-    });
+    mock_client
+        .expect_poll_workflow_task()
+        .returning(move |_, _, _| {
+            let t = canned_histories::single_timer("1");
+            let poll_resp = hist_to_poll_resp(&t, wfid.to_owned(), 2.into()).resp;
+            async move {
+                task_barr.wait().await;
+                Ok(poll_resp.clone())
+            }
+            .boxed()
+        });
     mock_client
         .expect_complete_workflow_task()
         .returning(|_| async move { Ok(Default::default()) }.boxed());
@@ -2118,9 +2181,11 @@ async fn continue_as_new_preserves_some_values() {
         t.add_full_wf_task();
         t
     };
-    mock_client.expect_poll_workflow_task().returning(move |_| {
-        Ok(hist_to_poll_resp(&hist, wfid.to_owned(), ResponseType::AllHistory).resp)
-    });
+    mock_client
+        .expect_poll_workflow_task()
+        .returning(move |_, _, _| {
+            Ok(hist_to_poll_resp(&hist, wfid.to_owned(), ResponseType::AllHistory).resp)
+        });
     mock_client
         .expect_complete_workflow_task()
         .returning(move |mut c| {
@@ -2214,11 +2279,11 @@ async fn fetching_to_continue_replay_works() {
     ];
     mock_client
         .expect_get_workflow_execution_history()
-        .returning(move |_, _, _| Ok(fetch_resp.clone()))
+        .returning(move |_, _, _, _, _| Ok(fetch_resp.clone()))
         .times(1);
     mock_client
         .expect_get_workflow_execution_history()
-        .returning(move |_, _, _| Ok(final_fetch_resp.clone()))
+        .returning(move |_, _, _, _, _| Ok(final_fetch_resp.clone()))
         .times(1);
     let mut mock = single_hist_mock_sg("wfid", t, tasks, mock_client, true);
     mock.worker_cfg(|wc| wc.max_cached_workflows = 10);
@@ -2269,7 +2334,7 @@ async fn fetching_error_evicts_wf() {
     ];
     mock_client
         .expect_get_workflow_execution_history()
-        .returning(move |_, _, _| Err(tonic::Status::not_found("Ahh broken")))
+        .returning(move |_, _, _, _, _| Err(tonic::Status::not_found("Ahh broken")))
         .times(1);
     let mut mock = single_hist_mock_sg("wfid", t, tasks, mock_client, true);
     mock.worker_cfg(|wc| wc.max_cached_workflows = 10);
@@ -2324,19 +2389,19 @@ async fn ensure_fetching_fail_during_complete_sends_task_failure() {
 
     let mut mock = mock_workflow_client();
     mock.expect_get_workflow_execution_history()
-        .returning(move |_, _, _| {
+        .returning(move |_, _, _, _, _| {
             error!("Called fetch!");
             Ok(next_page.clone())
         })
         .times(1);
     mock.expect_get_workflow_execution_history()
-        .returning(move |_, _, _| {
+        .returning(move |_, _, _, _, _| {
             error!("Called fetch second time!");
             Err(tonic::Status::not_found("Ahh broken"))
         })
         .times(1);
     mock.expect_fail_workflow_task()
-        .returning(|_, _, _| Ok(Default::default()))
+        .returning(|_, _, _, _, _| Ok(Default::default()))
         .times(1);
 
     let mut mock = single_hist_mock_sg(wfid, t, [ResponseType::Raw(first_poll)], mock, true);
@@ -2584,7 +2649,7 @@ async fn history_length_with_fail_and_timeout(
             empty_fetch_resp.history.as_mut().unwrap().events = vec![];
             mock_client
                 .expect_get_workflow_execution_history()
-                .returning(move |_, _, _| Ok(empty_fetch_resp.clone()))
+                .returning(move |_, _, _, _, _| Ok(empty_fetch_resp.clone()))
                 .times(1);
             vec![
                 ResponseType::ToTaskNum(1),
@@ -2642,7 +2707,7 @@ async fn poller_wont_run_ahead_of_task_slots() {
     let mut mock_client = mock_workflow_client();
     mock_client
         .expect_poll_workflow_task()
-        .returning(move |_| Ok(bunch_of_first_tasks.next().unwrap()));
+        .returning(move |_, _, _| Ok(bunch_of_first_tasks.next().unwrap()));
     mock_client
         .expect_complete_workflow_task()
         .returning(|_| Ok(Default::default()));
@@ -2703,10 +2768,12 @@ async fn poller_wont_poll_until_lang_polls() {
     // the WFT stream, we'll never join the tasks running the pollers and thus the error
     // gets printed but doesn't bubble up to the test. So we set this explicit expectation
     // in here to ensure it isn't called.
-    mock_client.expect_poll_workflow_task().returning(move |_| {
-        let _ = tx.send(());
-        Ok(Default::default())
-    });
+    mock_client
+        .expect_poll_workflow_task()
+        .returning(move |_, _, _| {
+            let _ = tx.send(());
+            Ok(Default::default())
+        });
 
     let worker = Worker::new_test(
         test_worker_cfg()
@@ -2743,9 +2810,11 @@ async fn use_compatible_version_flag(
         t.add_full_wf_task();
         t
     };
-    mock_client.expect_poll_workflow_task().returning(move |_| {
-        Ok(hist_to_poll_resp(&hist, wfid.to_owned(), ResponseType::AllHistory).resp)
-    });
+    mock_client
+        .expect_poll_workflow_task()
+        .returning(move |_, _, _| {
+            Ok(hist_to_poll_resp(&hist, wfid.to_owned(), ResponseType::AllHistory).resp)
+        });
     let compat_flag_expected = match intent {
         VersioningIntent::Unspecified => !different_tq,
         VersioningIntent::Compatible => true,
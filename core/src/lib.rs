@@ -49,7 +49,7 @@ use crate::{
         remove_trace_subscriber_for_current_thread, set_trace_subscriber_for_current_thread,
         telemetry_init, TelemetryInstance,
     },
-    worker::client::WorkerClientBag,
+    worker::client::{WorkerClientBag, WorkerClientBagOptions},
 };
 use futures::Stream;
 use std::sync::Arc;
@@ -95,11 +95,37 @@ where
     let sticky_q = sticky_q_name_for_worker(&client_ident, &worker_config);
     let client_bag = Arc::new(WorkerClientBag::new(
         client,
-        worker_config.namespace.clone(),
-        client_ident,
-        worker_config.worker_build_id.clone(),
-        worker_config.use_worker_versioning,
-    ));
+        WorkerClientBagOptions {
+            namespace: worker_config.namespace.clone(),
+            identity: client_ident,
+            worker_build_id: worker_config.worker_build_id.clone(),
+            binary_checksum_override: worker_config.binary_checksum_override.clone(),
+            use_versioning: worker_config.use_worker_versioning,
+            deadlines: worker_config.rpc_deadlines,
+            headers: worker_config.rpc_headers.clone(),
+            client_name: worker_config.client_name.clone(),
+            client_version: worker_config.client_version.clone(),
+            poll_circuit_breaker: worker_config.poll_circuit_breaker,
+            on_rpc_complete: worker_config.on_rpc_complete.clone(),
+            payload_codec: worker_config.payload_codec.clone(),
+            keep_alive: worker_config.keep_alive,
+            compression: worker_config.rpc_compression,
+            capabilities_refresh_poll_interval: worker_config.capabilities_refresh_poll_interval,
+            max_concurrent_completions: worker_config.max_concurrent_completions,
+            interceptors: worker_config.rpc_interceptors.clone(),
+            retry_override: worker_config.rpc_retry_override.clone(),
+            max_heartbeat_details_size: worker_config.max_heartbeat_details_size,
+            clock: None,
+        },
+    )?);
+    client_bag.validate_versioning()?;
+    debug!(
+        namespace = client_bag.namespace(),
+        identity = client_bag.identity(),
+        build_id = client_bag.worker_build_id(),
+        use_versioning = client_bag.use_versioning(),
+        "Worker client configured"
+    );
 
     Ok(Worker::new(
         worker_config,
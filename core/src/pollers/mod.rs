@@ -1,4 +1,5 @@
 mod poll_buffer;
+mod scaling;
 
 pub(crate) use poll_buffer::{
     new_activity_task_buffer, new_workflow_task_buffer, WorkflowTaskPoller,
@@ -35,6 +36,35 @@ where
     /// Need a separate shutdown to be able to consume boxes :(
     async fn shutdown_box(self: Box<Self>);
 }
+/// What a long poll which didn't fail produced. A poll which times out without a task is answered
+/// with a response that has no task token, but which may still have other fields set, so it's the
+/// token that decides whether there is a task.
+#[derive(Debug)]
+pub(crate) enum PollOutcome<T> {
+    Task(Box<T>),
+    Empty,
+}
+
+impl From<PollWorkflowTaskQueueResponse> for PollOutcome<PollWorkflowTaskQueueResponse> {
+    fn from(resp: PollWorkflowTaskQueueResponse) -> Self {
+        if resp.task_token.is_empty() {
+            PollOutcome::Empty
+        } else {
+            PollOutcome::Task(Box::new(resp))
+        }
+    }
+}
+
+impl From<PollActivityTaskQueueResponse> for PollOutcome<PollActivityTaskQueueResponse> {
+    fn from(resp: PollActivityTaskQueueResponse) -> Self {
+        if resp.task_token.is_empty() {
+            PollOutcome::Empty
+        } else {
+            PollOutcome::Task(Box::new(resp))
+        }
+    }
+}
+
 pub(crate) type BoxedPoller<T> = Box<dyn Poller<T> + Send + Sync + 'static>;
 pub(crate) type BoxedWFPoller = BoxedPoller<(PollWorkflowTaskQueueResponse, OwnedMeteredSemPermit)>;
 pub(crate) type BoxedActPoller =
@@ -79,3 +109,35 @@ mockall::mock! {
             where Self: 'a;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temporal_sdk_core_protos::temporal::api::common::v1::WorkflowType;
+
+    #[test]
+    fn only_polls_with_task_tokens_have_tasks() {
+        assert_matches!(
+            PollOutcome::from(PollWorkflowTaskQueueResponse::default()),
+            PollOutcome::Empty
+        );
+        // Fields besides the token don't make it a task
+        assert_matches!(
+            PollOutcome::from(PollWorkflowTaskQueueResponse {
+                workflow_type: Some(WorkflowType {
+                    name: "wf".to_string()
+                }),
+                backlog_count_hint: 3,
+                ..Default::default()
+            }),
+            PollOutcome::Empty
+        );
+        assert_matches!(
+            PollOutcome::from(PollActivityTaskQueueResponse {
+                task_token: vec![1],
+                ..Default::default()
+            }),
+            PollOutcome::Task(t) if t.task_token == vec![1]
+        );
+    }
+}
@@ -15,6 +15,7 @@ use std::{
     },
     time::Duration,
 };
+use temporal_client::{retry_after_hint, RetryConfig};
 use temporal_sdk_core_protos::temporal::api::{
     taskqueue::v1::TaskQueue,
     workflowservice::v1::{PollActivityTaskQueueResponse, PollWorkflowTaskQueueResponse},
@@ -122,12 +123,24 @@ where
                     } else {
                         break;
                     };
-                    let _active_guard = ActiveCounter::new(ap.as_ref(), nph);
+                    let active_guard = ActiveCounter::new(ap.as_ref(), nph);
                     let r = tokio::select! {
-                        r = pf() => r,
+                        // Prefer shutdown so cancelled polls are never forwarded
+                        biased;
+
                         _ = shutdown.cancelled() => break,
+                        r = pf() => r,
                     };
+                    drop(active_guard);
+                    let retry_after = r.as_ref().err().and_then(poll_retry_after);
                     let _ = tx.send(r.map(|r| (r, permit)));
+                    // If the server throttled us and said for how long, don't poll again until then
+                    if let Some(retry_after) = retry_after {
+                        tokio::select! {
+                            _ = tokio::time::sleep(retry_after) => (),
+                            _ = shutdown.cancelled() => break,
+                        }
+                    }
                 }
             });
             join_handles.push(jh);
@@ -232,11 +245,18 @@ pub(crate) fn new_workflow_task_buffer(
     shutdown: CancellationToken,
     num_pollers_handler: Option<impl Fn(usize) + Send + Sync + 'static>,
 ) -> PollWorkflowTaskBuffer {
+    let poll_cancel = shutdown.clone();
     LongPollBuffer::new(
         move || {
             let client = client.clone();
             let task_queue = task_queue.clone();
-            async move { client.poll_workflow_task(task_queue).await }
+            let cancel = poll_cancel.clone();
+            async move {
+                client
+                    .poll_workflow_task(task_queue, None, cancel)
+                    .await
+                    .map_err(Into::into)
+            }
         },
         semaphore,
         concurrent_pollers,
@@ -254,6 +274,7 @@ pub(crate) fn new_activity_task_buffer(
     concurrent_pollers: usize,
     semaphore: Arc<MeteredSemaphore>,
     max_tps: Option<f64>,
+    build_id_affinity: Option<String>,
     shutdown: CancellationToken,
     num_pollers_handler: Option<impl Fn(usize) + Send + Sync + 'static>,
     max_worker_acts_per_sec: Option<f64>,
@@ -262,11 +283,19 @@ pub(crate) fn new_activity_task_buffer(
         Quota::with_period(Duration::from_secs_f64(ps.recip()))
             .map(|q| Arc::new(RateLimiter::direct(q)))
     });
+    let poll_cancel = shutdown.clone();
     LongPollBuffer::new(
         move || {
             let client = client.clone();
             let task_queue = task_queue.clone();
-            async move { client.poll_activity_task(task_queue, max_tps).await }
+            let build_id_affinity = build_id_affinity.clone();
+            let cancel = poll_cancel.clone();
+            async move {
+                client
+                    .poll_activity_task(task_queue, max_tps, build_id_affinity, None, cancel)
+                    .await
+                    .map_err(Into::into)
+            }
         },
         semaphore,
         concurrent_pollers,
@@ -281,6 +310,12 @@ pub(crate) fn new_activity_task_buffer(
     )
 }
 
+/// How long to hold off polling after a poll failed, if the server throttled it and said for how
+/// long. Capped like throttled retries are, so a bogus hint can't stall the poller.
+fn poll_retry_after(status: &tonic::Status) -> Option<Duration> {
+    retry_after_hint(status).map(|hint| hint.min(RetryConfig::throttle_retry_policy().max_interval))
+}
+
 #[cfg(test)]
 #[derive(derive_more::Constructor)]
 pub(crate) struct MockPermittedPollBuffer<PT> {
@@ -324,7 +359,7 @@ mod tests {
         telemetry::metrics::MetricsContext, worker::client::mocks::mock_manual_workflow_client,
     };
     use futures::FutureExt;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use temporal_sdk_core_protos::temporal::api::enums::v1::TaskQueueKind;
     use tokio::{select, sync::mpsc::channel};
 
@@ -334,7 +369,7 @@ mod tests {
         mock_client
             .expect_poll_workflow_task()
             .times(2)
-            .returning(move |_| {
+            .returning(move |_, _, _| {
                 async {
                     tokio::time::sleep(Duration::from_millis(100)).await;
                     Ok(Default::default())
@@ -383,4 +418,72 @@ mod tests {
         pb.poll().await.unwrap().unwrap();
         pb.shutdown().await;
     }
+
+    #[tokio::test]
+    async fn waits_out_retry_after_hint_before_polling_again() {
+        let mut mock_client = mock_manual_workflow_client();
+        let mut md = tonic::metadata::MetadataMap::new();
+        md.insert("retry-after", "0.2".parse().unwrap());
+        mock_client
+            .expect_poll_workflow_task()
+            .times(1)
+            .returning(move |_, _, _| {
+                let md = md.clone();
+                async move {
+                    Err(tonic::Status::with_metadata(
+                        tonic::Code::ResourceExhausted,
+                        "slow down",
+                        md,
+                    )
+                    .into())
+                }
+                .boxed()
+            });
+        mock_client
+            .expect_poll_workflow_task()
+            .returning(move |_, _, _| async { Ok(Default::default()) }.boxed());
+
+        let pb = new_workflow_task_buffer(
+            Arc::new(mock_client),
+            TaskQueue {
+                name: "sometq".to_string(),
+                kind: TaskQueueKind::Normal as i32,
+                normal_name: "".to_string(),
+            },
+            1,
+            Arc::new(MeteredSemaphore::new(
+                10,
+                MetricsContext::no_op(),
+                |_, _| {},
+            )),
+            CancellationToken::new(),
+            None::<fn(usize)>,
+        );
+
+        // The throttling error itself is delivered right away
+        let err = pb.poll().await.unwrap().unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        let throttled_at = Instant::now();
+        pb.poll().await.unwrap().unwrap();
+        assert!(throttled_at.elapsed() >= Duration::from_millis(150));
+        pb.shutdown().await;
+    }
+
+    #[test]
+    fn poll_retry_after_is_capped() {
+        let throttled = |retry_after: &str| {
+            let mut md = tonic::metadata::MetadataMap::new();
+            md.insert("retry-after", retry_after.parse().unwrap());
+            tonic::Status::with_metadata(tonic::Code::ResourceExhausted, "slow down", md)
+        };
+        assert_eq!(
+            poll_retry_after(&throttled("2")),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            poll_retry_after(&throttled("86400")),
+            Some(RetryConfig::throttle_retry_policy().max_interval)
+        );
+        assert_eq!(poll_retry_after(&tonic::Status::unavailable("nope")), None);
+    }
 }
@@ -0,0 +1,77 @@
+//! Heuristics for sizing the number of concurrent pollers a worker runs on a task queue, based on
+//! what the server reports about the queue.
+
+use temporal_sdk_core_protos::temporal::api::workflowservice::v1::DescribeTaskQueueResponse;
+
+/// What a worker should do with its number of concurrent pollers on a task queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PollerScalingDecision {
+    /// Tasks are backing up faster than the queue's pollers take them, so add a poller
+    ScaleUp,
+    /// The queue is empty and pollers are waiting on it, so one can be removed
+    ScaleDown,
+    /// Leave the number of pollers as it is
+    Hold,
+}
+
+/// Recommends how this worker should change its poller count, given a describe response for the
+/// task queue (which must have been requested with the task queue status included) and how many
+/// pollers this worker currently runs on it.
+///
+/// Every poller takes at most one task per poll, so a backlog larger than the number of pollers
+/// on the queue (this worker's, or all those the server reports, if more) won't be drained by
+/// the next round of polls. A worker always keeps at least one poller.
+#[allow(dead_code)] // Pollers are still fixed in number, nothing acts on this yet
+pub(crate) fn poller_scaling_decision(
+    resp: &DescribeTaskQueueResponse,
+    current_pollers: usize,
+) -> PollerScalingDecision {
+    if current_pollers == 0 {
+        return PollerScalingDecision::ScaleUp;
+    }
+    let backlog = match &resp.task_queue_status {
+        Some(status) => status.backlog_count_hint.max(0) as usize,
+        // Without a status there's nothing to base a decision on
+        None => return PollerScalingDecision::Hold,
+    };
+    let pollers_on_queue = resp.pollers.len().max(current_pollers);
+    if backlog > pollers_on_queue {
+        PollerScalingDecision::ScaleUp
+    } else if backlog == 0 && current_pollers > 1 {
+        PollerScalingDecision::ScaleDown
+    } else {
+        PollerScalingDecision::Hold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temporal_sdk_core_protos::temporal::api::taskqueue::v1::{PollerInfo, TaskQueueStatus};
+
+    fn describe(backlog: i64, pollers: usize) -> DescribeTaskQueueResponse {
+        DescribeTaskQueueResponse {
+            pollers: vec![PollerInfo::default(); pollers],
+            task_queue_status: Some(TaskQueueStatus {
+                backlog_count_hint: backlog,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[rstest::rstest]
+    #[case::backlog_exceeds_pollers(describe(10, 2), 2, PollerScalingDecision::ScaleUp)]
+    #[case::other_workers_drain_backlog(describe(10, 20), 2, PollerScalingDecision::Hold)]
+    #[case::backlog_drained_next_round(describe(2, 2), 2, PollerScalingDecision::Hold)]
+    #[case::empty_queue(describe(0, 4), 4, PollerScalingDecision::ScaleDown)]
+    #[case::keeps_one_poller(describe(0, 1), 1, PollerScalingDecision::Hold)]
+    #[case::no_pollers(describe(0, 0), 0, PollerScalingDecision::ScaleUp)]
+    #[case::no_status(DescribeTaskQueueResponse::default(), 3, PollerScalingDecision::Hold)]
+    fn scaling_decisions(
+        #[case] resp: DescribeTaskQueueResponse,
+        #[case] current_pollers: usize,
+        #[case] expected: PollerScalingDecision,
+    ) {
+        assert_eq!(poller_scaling_decision(&resp, current_pollers), expected);
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     CompleteActivityError, TaskToken,
 };
 use anyhow::anyhow;
+use prost::Message;
 use std::{
     collections::HashMap,
     convert::TryFrom,
@@ -28,11 +29,13 @@ use temporal_sdk_core_protos::{
         workflow_completion,
     },
     temporal::api::{
-        common::v1::{Payload, RetryPolicy, WorkflowExecution},
+        common::v1::{Header, Payload, Payloads, RetryPolicy, WorkflowExecution},
         enums::v1::EventType,
         failure::v1::Failure,
         history::v1::{history_event, History, HistoryEvent, MarkerRecordedEventAttributes},
+        protocol::v1::{message::SequencingId, Message as ProtocolMessage},
         query::v1::WorkflowQuery,
+        update,
         workflowservice::v1::PollWorkflowTaskQueueResponse,
     },
     utilities::TryIntoOrNone,
@@ -52,10 +55,12 @@ pub struct ValidPollWFTQResponse {
     pub previous_started_event_id: i64,
     pub started_event_id: i64,
     /// If this is present, `history` will be empty. This is not a very "tight" design, but it's
-    /// enforced at construction time. From the `query` field.
-    pub legacy_query: Option<WorkflowQuery>,
+    /// enforced at construction time. From the `query` field, with [LEGACY_QUERY_ID] as its id.
+    pub legacy_query: Option<QueryWorkflow>,
     /// Query requests from the `queries` field
     pub query_requests: Vec<QueryWorkflow>,
+    /// Protocol messages sent along with the task, ex: workflow update requests
+    pub messages: Vec<ProtocolMessage>,
 
     /// Zero-size field to prevent explicit construction
     _cant_construct_me: (),
@@ -101,12 +106,18 @@ impl TryFrom<PollWorkflowTaskQueueResponse> for ValidPollWFTQResponse {
                 started_event_id,
                 query,
                 queries,
+                messages,
                 ..
             } => {
-                let query_requests = queries
-                    .into_iter()
-                    .map(|(id, q)| query_to_job(id, q))
-                    .collect();
+                let mut legacy_query = None;
+                let mut query_requests = vec![];
+                for q in extract_queries(query, queries) {
+                    if q.legacy {
+                        legacy_query = Some(q.into());
+                    } else {
+                        query_requests.push(q.into());
+                    }
+                }
 
                 Ok(Self {
                     task_token: TaskToken(task_token),
@@ -118,8 +129,9 @@ impl TryFrom<PollWorkflowTaskQueueResponse> for ValidPollWFTQResponse {
                     attempt: attempt as u32,
                     previous_started_event_id,
                     started_event_id,
-                    legacy_query: query,
+                    legacy_query,
                     query_requests,
+                    messages,
                     _cant_construct_me: (),
                 })
             }
@@ -128,6 +140,57 @@ impl TryFrom<PollWorkflowTaskQueueResponse> for ValidPollWFTQResponse {
     }
 }
 
+/// A query from a polled workflow task, which may have come from either the legacy `query` field
+/// or the `queries` map of the poll response
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PendingQuery {
+    /// The query's id. Legacy queries don't have one, and use [LEGACY_QUERY_ID].
+    pub id: String,
+    pub query_type: String,
+    pub args: Option<Payloads>,
+    pub header: Option<Header>,
+    /// True if this came from the legacy `query` field, meaning it must be answered with
+    /// `respond_legacy_query` rather than as part of the task's completion
+    pub legacy: bool,
+}
+
+impl From<PendingQuery> for QueryWorkflow {
+    fn from(q: PendingQuery) -> Self {
+        query_to_job(
+            q.id,
+            WorkflowQuery {
+                query_type: q.query_type,
+                query_args: q.args,
+                header: q.header,
+            },
+        )
+    }
+}
+
+/// Normalizes the legacy query (the `query` field) and the query map (the `queries` field) of a
+/// poll response into a single list. Queries from the map are ordered by id.
+pub(crate) fn extract_queries(
+    legacy_query: Option<WorkflowQuery>,
+    queries: HashMap<String, WorkflowQuery>,
+) -> Vec<PendingQuery> {
+    let pending = |id, q: WorkflowQuery, legacy| PendingQuery {
+        id,
+        query_type: q.query_type,
+        args: q.query_args,
+        header: q.header,
+        legacy,
+    };
+    let mut pending_queries: Vec<_> = queries
+        .into_iter()
+        .map(|(id, q)| pending(id, q, false))
+        .collect();
+    pending_queries.sort_by(|a, b| a.id.cmp(&b.id));
+    if let Some(q) = legacy_query {
+        pending_queries.push(pending(LEGACY_QUERY_ID.to_string(), q, true));
+    }
+    pending_queries
+}
+
 pub(crate) trait WorkflowActivationExt {
     /// Returns true if this activation has one and only one job to perform a legacy query
     fn is_legacy_query(&self) -> bool;
@@ -151,6 +214,42 @@ pub(crate) fn legacy_query_failure(fail: workflow_completion::Failure) -> QueryR
     }
 }
 
+const UPDATE_REQUEST_TYPE_URL: &str = "type.googleapis.com/temporal.api.update.v1.Request";
+const UPDATE_REJECTION_TYPE_URL: &str = "type.googleapis.com/temporal.api.update.v1.Rejection";
+
+/// Build the messages to send back in response to the protocol messages that came with a WFT.
+/// Workflow updates aren't supported yet, so every update request is rejected rather than being
+/// silently dropped. Messages belonging to any other protocol are ignored.
+pub(crate) fn reject_update_requests(messages: Vec<ProtocolMessage>) -> Vec<ProtocolMessage> {
+    messages
+        .into_iter()
+        .filter_map(|msg| {
+            let body = msg.body.filter(|b| b.type_url == UPDATE_REQUEST_TYPE_URL)?;
+            let rejection = update::v1::Rejection {
+                rejected_request_sequencing_event_id: match msg.sequencing_id {
+                    Some(SequencingId::EventId(eid)) => eid,
+                    _ => 0,
+                },
+                rejected_request: update::v1::Request::decode(body.value.as_slice()).ok(),
+                failure: Some(Failure::application_failure(
+                    "Workflow updates are not supported by this worker".to_string(),
+                    true,
+                )),
+                rejected_request_message_id: msg.id.clone(),
+            };
+            Some(ProtocolMessage {
+                id: format!("{}/reject", msg.id),
+                protocol_instance_id: msg.protocol_instance_id,
+                sequencing_id: None,
+                body: Some(prost_types::Any {
+                    type_url: UPDATE_REJECTION_TYPE_URL.to_string(),
+                    value: rejection.encode_to_vec(),
+                }),
+            })
+        })
+        .collect()
+}
+
 pub(crate) trait HistoryEventExt {
     /// If this history event represents a `patched` marker, return the info about
     /// it. Returns `None` if it is any other kind of event or marker.
@@ -422,3 +521,83 @@ impl Display for ValidScheduleLA {
         write!(f, "ValidScheduleLA({}, {})", self.seq, self.activity_type)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query_type: &str) -> WorkflowQuery {
+        WorkflowQuery {
+            query_type: query_type.to_string(),
+            query_args: Some(Payloads {
+                payloads: vec![Payload::from(query_type.as_bytes())],
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extracts_legacy_and_map_queries() {
+        let queries = extract_queries(
+            Some(query("legacy")),
+            HashMap::from([
+                ("q2".to_string(), query("second")),
+                ("q1".to_string(), query("first")),
+            ]),
+        );
+        let summary: Vec<_> = queries
+            .iter()
+            .map(|q| (q.id.as_str(), q.query_type.as_str(), q.legacy))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("q1", "first", false),
+                ("q2", "second", false),
+                (LEGACY_QUERY_ID, "legacy", true)
+            ]
+        );
+        assert_eq!(queries[2].args, query("legacy").query_args);
+
+        assert!(extract_queries(None, HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn rejects_only_update_requests() {
+        let request = update::v1::Request {
+            meta: Some(update::v1::Meta {
+                update_id: "upd".to_string(),
+                identity: "me".to_string(),
+            }),
+            input: None,
+        };
+        let rejections = reject_update_requests(vec![
+            ProtocolMessage {
+                id: "req".to_string(),
+                protocol_instance_id: "upd".to_string(),
+                sequencing_id: Some(SequencingId::EventId(3)),
+                body: Some(prost_types::Any {
+                    type_url: UPDATE_REQUEST_TYPE_URL.to_string(),
+                    value: request.encode_to_vec(),
+                }),
+            },
+            ProtocolMessage {
+                id: "other".to_string(),
+                body: Some(prost_types::Any {
+                    type_url: "type.googleapis.com/some.other.Protocol".to_string(),
+                    value: vec![],
+                }),
+                ..Default::default()
+            },
+        ]);
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].protocol_instance_id, "upd");
+        let body = rejections[0].body.as_ref().unwrap();
+        assert_eq!(body.type_url, UPDATE_REJECTION_TYPE_URL);
+        let rejection = update::v1::Rejection::decode(body.value.as_slice()).unwrap();
+        assert_eq!(rejection.rejected_request_message_id, "req");
+        assert_eq!(rejection.rejected_request_sequencing_event_id, 3);
+        assert_eq!(rejection.rejected_request, Some(request));
+        assert!(rejection.failure.is_some());
+    }
+}
@@ -4,7 +4,7 @@
 
 use crate::{
     worker::{
-        client::{mocks::mock_manual_workflow_client, WorkerClient},
+        client::{mocks::mock_manual_workflow_client, HeartbeatError, WorkerClient},
         PostActivateHookData,
     },
     Worker,
@@ -12,7 +12,9 @@ use crate::{
 use futures::{FutureExt, Stream, StreamExt};
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
+use prost::Message;
 use std::{
+    path::Path,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -23,6 +25,9 @@ use temporal_sdk_core_protos::{
         common::v1::WorkflowExecution,
         history::v1::History,
         workflowservice::v1::{
+            GetWorkflowExecutionHistoryResponse, GetWorkflowExecutionHistoryReverseResponse,
+            RecordActivityTaskHeartbeatResponse, RespondActivityTaskCanceledResponse,
+            RespondActivityTaskCompletedResponse, RespondActivityTaskFailedResponse,
             RespondWorkflowTaskCompletedResponse, RespondWorkflowTaskFailedResponse,
         },
     },
@@ -42,6 +47,18 @@ pub struct HistoryForReplay {
     workflow_id: String,
 }
 
+impl HistoryForReplay {
+    /// Loads a history from a file containing its protobuf serialization, as exported from the
+    /// server (ex: by `tctl workflow show --output_filename`).
+    pub fn from_proto_file(
+        path: impl AsRef<Path>,
+        workflow_id: impl Into<String>,
+    ) -> Result<Self, anyhow::Error> {
+        let bytes = std::fs::read(path)?;
+        Ok(Self::new(History::decode(&*bytes)?, workflow_id.into()))
+    }
+}
+
 /// Allows lang to feed histories into the replayer one at a time. Simply drop the feeder to signal
 /// to the worker that you're done and it should initiate shutdown.
 pub struct HistoryFeeder {
@@ -82,14 +99,18 @@ impl Stream for HistoryFeederStream {
 /// return the entire history in one workflow task. If a workflow task failure is sent to the mock,
 /// it will send the complete response again.
 ///
-/// Once it runs out of histories to return, it will serve up default responses after a 10s delay
+/// Once it runs out of histories to return, it shuts down the worker and serves up empty polls.
+/// Each history is served whole, so if the worker tries to fetch more of one (because the history
+/// ends before the workflow does, ex: a truncated export), the fetch fails with `NotFound`
+/// explaining the history is exhausted. There are never real activities during replay, so any
+/// activity RPC fails with `NotFound` too.
 pub(crate) fn mock_client_from_histories(historator: Historator) -> impl WorkerClient {
     let mut mg = mock_manual_workflow_client();
 
     let hist_allow_tx = historator.replay_done_tx.clone();
     let historator = Arc::new(TokioMutex::new(historator));
 
-    mg.expect_poll_workflow_task().returning(move |_| {
+    mg.expect_poll_workflow_task().returning(move |_, _, _| {
         let historator = historator.clone();
         async move {
             let mut hlock = historator.lock().await;
@@ -117,14 +138,61 @@ pub(crate) fn mock_client_from_histories(historator: Historator) -> impl WorkerC
     mg.expect_complete_workflow_task().returning(move |_| {
         async move { Ok(RespondWorkflowTaskCompletedResponse::default()) }.boxed()
     });
-    mg.expect_fail_workflow_task().returning(move |_, _, _| {
-        hist_allow_tx.send("Failed".to_string()).unwrap();
-        async move { Ok(RespondWorkflowTaskFailedResponse::default()) }.boxed()
+    mg.expect_fail_workflow_task()
+        .returning(move |_, _, _, _, _| {
+            hist_allow_tx.send("Failed".to_string()).unwrap();
+            async move { Ok(RespondWorkflowTaskFailedResponse::default()) }.boxed()
+        });
+
+    mg.expect_get_workflow_execution_history()
+        .returning(|wf_id, _, _, _, _| {
+            async move {
+                Err::<GetWorkflowExecutionHistoryResponse, _>(replay_history_exhausted(&wf_id))
+            }
+            .boxed()
+        });
+    mg.expect_get_workflow_execution_history_reverse()
+        .returning(|wf_id, _, _| {
+            async move {
+                Err::<GetWorkflowExecutionHistoryReverseResponse, _>(replay_history_exhausted(
+                    &wf_id,
+                ))
+            }
+            .boxed()
+        });
+
+    mg.expect_complete_activity_task().returning(|_, _| {
+        async { Err::<RespondActivityTaskCompletedResponse, _>(no_activities_in_replay()) }.boxed()
+    });
+    mg.expect_fail_activity_task().returning(|_, _, _| {
+        async { Err::<RespondActivityTaskFailedResponse, _>(no_activities_in_replay()) }.boxed()
+    });
+    mg.expect_cancel_activity_task().returning(|_, _, _| {
+        async { Err::<RespondActivityTaskCanceledResponse, _>(no_activities_in_replay()) }.boxed()
+    });
+    mg.expect_record_activity_heartbeat().returning(|_, _| {
+        async {
+            Err::<RecordActivityTaskHeartbeatResponse, HeartbeatError>(
+                no_activities_in_replay().into(),
+            )
+        }
+        .boxed()
     });
 
     mg
 }
 
+fn no_activities_in_replay() -> tonic::Status {
+    tonic::Status::not_found("There are no activities to respond to during replay")
+}
+
+fn replay_history_exhausted(workflow_id: &str) -> tonic::Status {
+    tonic::Status::not_found(format!(
+        "Replay history for workflow {workflow_id} is exhausted, every event in it has already \
+         been handed to the worker"
+    ))
+}
+
 pub(crate) struct Historator {
     iter: Pin<Box<dyn Stream<Item = HistoryForReplay> + Send>>,
     allow_stream: UnboundedReceiverStream<String>,
@@ -202,3 +270,25 @@ impl Stream for Historator {
 struct HistoratorDat {
     all_dispatched: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_help::canned_histories;
+
+    #[test]
+    fn loads_history_from_proto_file() {
+        let hist: History = canned_histories::single_timer("1")
+            .get_full_history_info()
+            .unwrap()
+            .into();
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, hist.encode_to_vec()).unwrap();
+
+        let loaded = HistoryForReplay::from_proto_file(&path, "wfid").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.hist, hist);
+        assert_eq!(loaded.workflow_id, "wfid");
+        assert!(HistoryForReplay::from_proto_file(&path, "wfid").is_err());
+    }
+}
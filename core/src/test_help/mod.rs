@@ -9,6 +9,7 @@ use crate::{
     worker::{
         client::{
             mocks::mock_workflow_client, MockWorkerClient, WorkerClient, WorkflowTaskCompletion,
+            WorkflowTaskToken,
         },
         TaskPollers,
     },
@@ -370,7 +371,7 @@ pub(crate) struct MockPollCfg {
     pub mock_client: MockWorkerClient,
     /// All calls to fail WFTs must match this predicate
     pub expect_fail_wft_matcher:
-        Box<dyn Fn(&TaskToken, &WorkflowTaskFailedCause, &Option<Failure>) -> bool + Send>,
+        Box<dyn Fn(&WorkflowTaskToken, &WorkflowTaskFailedCause, &Option<Failure>) -> bool + Send>,
     pub completion_asserts: Option<Box<dyn Fn(&WorkflowTaskCompletion) + Send>>,
     pub num_expected_completions: Option<TimesRange>,
     /// If being used with the Rust SDK, this is set true. It ensures pollers will not error out
@@ -604,10 +605,10 @@ pub(crate) fn build_mock_pollers(mut cfg: MockPollCfg) -> MocksHolder {
     let outstanding = outstanding_wf_task_tokens.clone();
     cfg.mock_client
         .expect_fail_workflow_task()
-        .withf(cfg.expect_fail_wft_matcher)
+        .withf(move |tt, cause, f, _, _| (cfg.expect_fail_wft_matcher)(tt, cause, f))
         .times::<TimesRange>(cfg.num_expected_fails.into())
-        .returning(move |tt, _, _| {
-            outstanding.release_token(&tt);
+        .returning(move |tt, _, _, _, _| {
+            outstanding.release_token(&tt.0);
             Ok(Default::default())
         });
     let outstanding = outstanding_wf_task_tokens.clone();
@@ -615,10 +616,15 @@ pub(crate) fn build_mock_pollers(mut cfg: MockPollCfg) -> MocksHolder {
         .expect_respond_legacy_query()
         .times::<TimesRange>(cfg.num_expected_legacy_query_resps.into())
         .returning(move |tt, _| {
-            outstanding.release_token(&tt);
+            outstanding.release_token(&tt.0);
             Ok(Default::default())
         });
 
+    // Tests which care about sticky disclaims set their own expectations, which take precedence
+    cfg.mock_client
+        .expect_disclaim_sticky()
+        .returning(|_| Ok(()));
+
     let mut mh = MocksHolder {
         client: Arc::new(cfg.mock_client),
         inputs: mock_worker,
@@ -32,6 +32,7 @@ use futures::{
     Stream, StreamExt,
 };
 use std::{
+    collections::HashSet,
     convert::TryInto,
     future,
     sync::{
@@ -40,13 +41,15 @@ use std::{
     },
     time::{Duration, Instant},
 };
+use temporal_client::{classify_rpc_error, RpcErrorClass};
 use temporal_sdk_core_protos::{
     coresdk::{
         activity_result::{self as ar, activity_execution_result as aer},
-        activity_task::{ActivityCancelReason, ActivityTask},
-        ActivityHeartbeat,
+        activity_task::{activity_task, ActivityCancelReason, ActivityTask},
+        ActivityHeartbeat, IntoPayloadsExt,
     },
     temporal::api::{
+        common::v1::Payloads,
         failure::v1::{failure::FailureInfo, ApplicationFailureInfo, CanceledFailureInfo, Failure},
         workflowservice::v1::PollActivityTaskQueueResponse,
     },
@@ -94,6 +97,9 @@ struct RemoteInFlightActInfo {
     /// we have learned from heartbeating and issued a cancel task, in which case we may simply
     /// discard the reply.
     pub known_not_found: bool,
+    /// The details from the most recent heartbeat lang recorded for this activity, if any. Sent
+    /// along with failures so retries can resume from the last checkpoint.
+    pub last_heartbeat_details: Option<Payloads>,
     /// The permit from the max concurrent semaphore
     _permit: UsedMeteredSemPermit,
 }
@@ -111,6 +117,7 @@ impl RemoteInFlightActInfo {
             heartbeat_timeout: poll_resp.heartbeat_timeout.clone(),
             issued_cancel_to_lang: None,
             known_not_found: false,
+            last_heartbeat_details: None,
             _permit: permit,
         }
     }
@@ -139,6 +146,10 @@ pub(crate) struct WorkerActivityTasks {
 
     max_heartbeat_throttle_interval: Duration,
     default_heartbeat_throttle_interval: Duration,
+    /// If set, tasks for activity types not in here are failed back to the server
+    accepted_activity_types: Option<HashSet<String>>,
+    /// Used to fail back tasks for activity types which aren't accepted
+    client: Arc<dyn WorkerClient>,
 
     /// Wakes every time an activity is removed from the outstanding map
     complete_notify: Arc<Notify>,
@@ -162,6 +173,7 @@ impl WorkerActivityTasks {
         max_heartbeat_throttle_interval: Duration,
         default_heartbeat_throttle_interval: Duration,
         graceful_shutdown: Option<Duration>,
+        accepted_activity_types: Option<HashSet<String>>,
     ) -> Self {
         let shutdown_initiated_token = CancellationToken::new();
         let outstanding_activity_tasks = Arc::new(DashMap::new());
@@ -178,7 +190,7 @@ impl WorkerActivityTasks {
             start_tasks_stream_complete.clone(),
         );
         let (cancels_tx, cancels_rx) = unbounded_channel();
-        let heartbeat_manager = ActivityHeartbeatManager::new(client, cancels_tx.clone());
+        let heartbeat_manager = ActivityHeartbeatManager::new(client.clone(), cancels_tx.clone());
         let complete_notify = Arc::new(Notify::new());
         let source_stream = stream::select_with_strategy(
             UnboundedReceiverStream::new(cancels_rx).map(ActivityTaskSource::from),
@@ -208,6 +220,8 @@ impl WorkerActivityTasks {
             metrics,
             max_heartbeat_throttle_interval,
             default_heartbeat_throttle_interval,
+            accepted_activity_types,
+            client,
             poll_returned_shutdown_token: CancellationToken::new(),
             outstanding_activity_tasks,
         }
@@ -270,15 +284,58 @@ impl WorkerActivityTasks {
     /// Exclusive poll for activity tasks
     ///
     /// Polls the various task sources (server polls, eager activities, cancellations) while
-    /// respecting the provided rate limits and allowed concurrency. Returns
+    /// respecting the provided rate limits and allowed concurrency. Tasks for activity types this
+    /// worker doesn't accept are failed back to the server rather than returned. Returns
     /// [PollActivityError::ShutDown] after shutdown is completed and all tasks sources are
     /// depleted.
     pub(crate) async fn poll(&self) -> Result<ActivityTask, PollActivityError> {
         let mut poller_stream = self.activity_task_stream.lock().await;
-        poller_stream.next().await.unwrap_or_else(|| {
-            self.poll_returned_shutdown_token.cancel();
-            Err(PollActivityError::ShutDown)
-        })
+        loop {
+            let task = poller_stream.next().await.unwrap_or_else(|| {
+                self.poll_returned_shutdown_token.cancel();
+                Err(PollActivityError::ShutDown)
+            })?;
+            if !self.reject_if_not_accepted(&task) {
+                return Ok(task);
+            }
+        }
+    }
+
+    /// Fails the task back to the server if it starts an activity of a type this worker doesn't
+    /// accept, returning true if it did. The failure is retryable, so the server will dispatch the
+    /// activity again, hopefully to a worker which does accept it.
+    ///
+    /// The activity never ran, so unlike [WorkerActivityTasks::complete] no execution metrics are
+    /// recorded. The failure is sent from its own task, so that it goes out even if whoever is
+    /// polling stops waiting.
+    fn reject_if_not_accepted(&self, task: &ActivityTask) -> bool {
+        let act_type = match (&self.accepted_activity_types, &task.variant) {
+            (Some(accepted), Some(activity_task::Variant::Start(start)))
+                if !accepted.contains(&start.activity_type) =>
+            {
+                start.activity_type.clone()
+            }
+            _ => return false,
+        };
+        debug!(activity_type = %act_type, "Rejecting activity task of a type not accepted here");
+        let task_token = TaskToken(task.task_token.clone());
+        // Dropping the outstanding entry frees up its slot
+        self.outstanding_activity_tasks.remove(&task_token);
+        self.complete_notify.notify_waiters();
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client
+                .fail_activity_task(
+                    task_token.into(),
+                    Some(not_accepted_failure(&act_type)),
+                    None,
+                )
+                .await
+            {
+                warn!(error=?e, "Network error while rejecting activity");
+            }
+        });
+        true
     }
 
     pub(crate) async fn complete(
@@ -296,6 +353,7 @@ impl WorkerActivityTasks {
             Span::current().record("run_id", act_info.base.workflow_run_id);
             act_metrics.act_execution_latency(act_info.base.start_time.elapsed());
             let known_not_found = act_info.known_not_found;
+            let last_heartbeat_details = act_info.last_heartbeat_details;
 
             self.heartbeat_manager.evict(task_token.clone()).await;
             self.complete_notify.notify_waiters();
@@ -305,13 +363,17 @@ impl WorkerActivityTasks {
                 let maybe_net_err = match status {
                     aer::Status::WillCompleteAsync(_) => None,
                     aer::Status::Completed(ar::Success { result }) => client
-                        .complete_activity_task(task_token.clone(), result.map(Into::into))
+                        .complete_activity_task(task_token.clone().into(), result.map(Into::into))
                         .await
                         .err(),
                     aer::Status::Failed(ar::Failure { failure }) => {
                         act_metrics.act_execution_failed();
                         client
-                            .fail_activity_task(task_token.clone(), failure.map(Into::into))
+                            .fail_activity_task(
+                                task_token.clone().into(),
+                                failure.map(Into::into),
+                                last_heartbeat_details,
+                            )
                             .await
                             .err()
                     }
@@ -325,8 +387,9 @@ impl WorkerActivityTasks {
                             // happen anyway.
                             client
                                 .fail_activity_task(
-                                    task_token.clone(),
+                                    task_token.clone().into(),
                                     Some(worker_shutdown_failure()),
+                                    last_heartbeat_details,
                                 )
                                 .await
                                 .err()
@@ -346,7 +409,11 @@ impl WorkerActivityTasks {
                                 None
                             };
                             client
-                                .cancel_activity_task(task_token.clone(), details.map(Into::into))
+                                .cancel_activity_task(
+                                    task_token.clone().into(),
+                                    details.map(Into::into),
+                                    act_info.issued_cancel_to_lang,
+                                )
                                 .await
                                 .err()
                         }
@@ -354,7 +421,7 @@ impl WorkerActivityTasks {
                 };
 
                 if let Some(e) = maybe_net_err {
-                    if e.code() == tonic::Code::NotFound {
+                    if classify_rpc_error(&e) == RpcErrorClass::NotFound {
                         warn!(task_token = ?task_token, details = ?e, "Activity not found on \
                         completion. This may happen if the activity has already been cancelled but \
                         completed anyway.");
@@ -377,10 +444,12 @@ impl WorkerActivityTasks {
         details: ActivityHeartbeat,
     ) -> Result<(), ActivityHeartbeatError> {
         // TODO: Propagate these back as cancels. Silent fails is too nonobvious
-        let heartbeat_timeout: Duration = self
+        let mut act_info = self
             .outstanding_activity_tasks
-            .get(&TaskToken(details.task_token.clone()))
-            .ok_or(ActivityHeartbeatError::UnknownActivity)?
+            .get_mut(&TaskToken(details.task_token.clone()))
+            .ok_or(ActivityHeartbeatError::UnknownActivity)?;
+        act_info.last_heartbeat_details = details.details.clone().into_payloads();
+        let heartbeat_timeout: Duration = act_info
             .heartbeat_timeout
             .clone()
             // We treat None as 0 (even though heartbeat_timeout is never set to None by the server)
@@ -389,6 +458,7 @@ impl WorkerActivityTasks {
             // This technically should never happen since prost duration should be directly mappable
             // to std::time::Duration.
             .or(Err(ActivityHeartbeatError::InvalidHeartbeatTimeout))?;
+        drop(act_info);
 
         // There is a bug in the server that translates non-set heartbeat timeouts into 0 duration.
         // That's why we treat 0 the same way as None, otherwise we wouldn't know which aggregation
@@ -605,11 +675,30 @@ fn worker_shutdown_failure() -> Failure {
     }
 }
 
+fn not_accepted_failure(activity_type: &str) -> Failure {
+    Failure {
+        message: format!("Activity type `{activity_type}` is not accepted by this worker"),
+        source: "".to_string(),
+        stack_trace: "".to_string(),
+        encoded_attributes: None,
+        cause: None,
+        failure_info: Some(FailureInfo::ApplicationFailureInfo(
+            ApplicationFailureInfo {
+                r#type: "NotAcceptedHere".to_string(),
+                non_retryable: false,
+                details: None,
+            },
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{pollers::new_activity_task_buffer, worker::client::mocks::mock_workflow_client};
-    use temporal_sdk_core_protos::coresdk::activity_result::ActivityExecutionResult;
+    use temporal_sdk_core_protos::{
+        coresdk::activity_result::ActivityExecutionResult, temporal::api::common::v1::ActivityType,
+    };
 
     #[tokio::test]
     async fn per_worker_ratelimit() {
@@ -617,7 +706,7 @@ mod tests {
         mock_client
             .expect_poll_activity_task()
             .times(1)
-            .returning(move |_, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(PollActivityTaskQueueResponse {
                     task_token: vec![1],
                     activity_id: "act1".to_string(),
@@ -627,7 +716,7 @@ mod tests {
         mock_client
             .expect_poll_activity_task()
             .times(1)
-            .returning(move |_, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(PollActivityTaskQueueResponse {
                     task_token: vec![2],
                     activity_id: "act2".to_string(),
@@ -651,6 +740,7 @@ mod tests {
             5, // Lots of concurrent pollers, to ensure we don't poll to much when that's the case
             sem.clone(),
             None,
+            None,
             shutdown_token.clone(),
             None::<fn(usize)>,
             Some(2.0),
@@ -663,6 +753,7 @@ mod tests {
             Duration::from_secs(1),
             Duration::from_secs(1),
             None,
+            None,
         );
         let start = Instant::now();
         let t1 = atm.poll().await.unwrap();
@@ -689,4 +780,77 @@ mod tests {
         assert_matches!(atm.poll().await.unwrap_err(), PollActivityError::ShutDown);
         atm.shutdown().await;
     }
+
+    #[tokio::test]
+    async fn rejects_unaccepted_activity_types() {
+        let rejected = Arc::new(Notify::new());
+        let rejected_tx = rejected.clone();
+        let mut mock_client = mock_workflow_client();
+        let mut tasks = [("rejected", 1), ("accepted", 2)].into_iter();
+        mock_client
+            .expect_poll_activity_task()
+            .returning(move |_, _, _, _, _| {
+                Ok(tasks
+                    .next()
+                    .map(|(act_type, token)| PollActivityTaskQueueResponse {
+                        task_token: vec![token],
+                        activity_id: act_type.to_string(),
+                        activity_type: Some(ActivityType {
+                            name: act_type.to_string(),
+                        }),
+                        ..Default::default()
+                    })
+                    .unwrap_or_default())
+            });
+        mock_client
+            .expect_fail_activity_task()
+            .withf(|tt, f, _| {
+                tt.0 == TaskToken(vec![1])
+                    && matches!(
+                        f.as_ref().and_then(|f| f.failure_info.as_ref()),
+                        Some(FailureInfo::ApplicationFailureInfo(i))
+                            if i.r#type == "NotAcceptedHere" && !i.non_retryable
+                    )
+            })
+            .times(1)
+            .returning(move |_, _, _| {
+                rejected_tx.notify_one();
+                Ok(Default::default())
+            });
+        let mock_client = Arc::new(mock_client);
+        let sem = Arc::new(MeteredSemaphore::new(
+            10,
+            MetricsContext::no_op(),
+            MetricsContext::available_task_slots,
+        ));
+        let ap = new_activity_task_buffer(
+            mock_client.clone(),
+            "tq".to_string(),
+            1,
+            sem.clone(),
+            None,
+            None,
+            CancellationToken::new(),
+            None::<fn(usize)>,
+            None,
+        );
+        let atm = WorkerActivityTasks::new(
+            sem,
+            Box::new(ap),
+            mock_client.clone(),
+            MetricsContext::no_op(),
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+            None,
+            Some(HashSet::from(["accepted".to_string()])),
+        );
+
+        // The rejected task is never handed out
+        let accepted = atm.poll().await.unwrap();
+        assert_eq!(accepted.task_token, vec![2]);
+        assert_eq!(atm.outstanding_activity_tasks.len(), 1);
+        tokio::time::timeout(Duration::from_secs(5), rejected.notified())
+            .await
+            .expect("rejection is sent");
+    }
 }
@@ -1,6 +1,9 @@
 use crate::{
     abstractions::take_cell::TakeCell,
-    worker::{activities::PendingActivityCancel, client::WorkerClient},
+    worker::{
+        activities::PendingActivityCancel,
+        client::{HeartbeatError, WorkerClient},
+    },
     TaskToken,
 };
 use futures::StreamExt;
@@ -9,6 +12,7 @@ use std::{
     sync::Arc,
     time::{self, Duration, Instant},
 };
+use temporal_client::{classify_rpc_error, RpcErrorClass};
 use temporal_sdk_core_protos::{
     coresdk::{activity_task::ActivityCancelReason, ActivityHeartbeat, IntoPayloadsExt},
     temporal::api::{
@@ -136,7 +140,7 @@ impl ActivityHeartbeatManager {
                             }
                             HeartbeatExecutorAction::Report { task_token: tt, details } => {
                                 match sg
-                                    .record_activity_heartbeat(tt.clone(), details.into_payloads())
+                                    .record_activity_heartbeat(tt.clone().into(), details.into_payloads())
                                     .await
                                 {
                                     Ok(RecordActivityTaskHeartbeatResponse { cancel_requested }) => {
@@ -154,7 +158,9 @@ impl ActivityHeartbeatManager {
                                     // Send cancels for any activity that learns its workflow already
                                     // finished (which is one thing not found implies - other reasons
                                     // would seem equally valid).
-                                    Err(s) if s.code() == tonic::Code::NotFound => {
+                                    Err(HeartbeatError::TonicError(s))
+                                        if classify_rpc_error(&s) == RpcErrorClass::NotFound =>
+                                    {
                                         debug!(task_token = %tt,
                                            "Activity not found when recording heartbeat");
                                         cancels_tx
@@ -462,6 +468,32 @@ mod test {
         hm.shutdown().await;
     }
 
+    /// With a zero throttle_interval (ex: when throttling is disabled in the worker config),
+    /// every heartbeat is sent, even when they're recorded in quick succession.
+    #[tokio::test]
+    async fn zero_throttle_interval_sends_every_heartbeat() {
+        let sent = Arc::new(parking_lot::Mutex::new(vec![]));
+        let sent_clone = sent.clone();
+        let mut mock_client = mock_workflow_client();
+        mock_client
+            .expect_record_activity_heartbeat()
+            .returning(move |_, details| {
+                sent_clone.lock().push(details.unwrap().payloads[0].data[0]);
+                Ok(RecordActivityTaskHeartbeatResponse::default())
+            })
+            .times(5);
+        let (cancel_tx, _cancel_rx) = unbounded_channel();
+        let hm = ActivityHeartbeatManager::new(Arc::new(mock_client), cancel_tx);
+        let fake_task_token = vec![1, 2, 3];
+        for i in 0_u8..5 {
+            record_heartbeat(&hm, fake_task_token.clone(), i, Duration::ZERO);
+            // Let it propagate
+            sleep(Duration::from_millis(10)).await;
+        }
+        hm.shutdown().await;
+        assert_eq!(*sent.lock(), [0, 1, 2, 3, 4]);
+    }
+
     /// This test reports one heartbeat and waits for the throttle_interval to elapse before sending another
     #[tokio::test]
     async fn report_heartbeat_after_timeout() {
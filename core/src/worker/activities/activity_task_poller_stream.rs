@@ -1,6 +1,9 @@
-use crate::{pollers::BoxedActPoller, worker::activities::PermittedTqResp, MetricsContext};
+use crate::{
+    pollers::{BoxedActPoller, PollOutcome},
+    worker::activities::PermittedTqResp,
+    MetricsContext,
+};
 use futures::{stream, Stream};
-use temporal_sdk_core_protos::temporal::api::workflowservice::v1::PollActivityTaskQueueResponse;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
@@ -27,16 +30,19 @@ pub(crate) fn new_activity_task_poller(
             let poll = async {
                 loop {
                     return match state.poller.poll().await {
-                        Some(Ok((resp, permit))) => {
-                            if resp == PollActivityTaskQueueResponse::default() {
-                                // We get the default proto in the event that the long poll times
-                                // out.
+                        Some(Ok((resp, permit))) => match PollOutcome::from(resp) {
+                            PollOutcome::Task(resp) => Some(Ok(PermittedTqResp {
+                                permit,
+                                resp: *resp,
+                            })),
+                            // We get a tokenless response in the event that the long poll times
+                            // out.
+                            PollOutcome::Empty => {
                                 debug!("Poll activity task timeout");
                                 state.metrics.act_poll_timeout();
                                 continue;
                             }
-                            Some(Ok(PermittedTqResp { permit, resp }))
-                        }
+                        },
                         Some(Err(e)) => {
                             warn!(error=?e, "Error while polling for activity tasks");
                             Some(Err(e))
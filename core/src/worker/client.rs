@@ -1,86 +1,854 @@
 //! Worker-specific client needs
 
+mod codec;
 pub(crate) mod mocks;
 
-use temporal_client::{Client, RetryClient, WorkflowService};
+use crate::pollers::PollOutcome;
+use futures::{
+    future::{BoxFuture, Shared, WeakShared},
+    stream,
+    stream::FuturesUnordered,
+    Future, FutureExt, Stream, StreamExt,
+};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use temporal_client::{
+    Client, ClientInitError, Clock, KeepAliveConfig, RetryAttempts, RetryClient, WorkflowService,
+    CLIENT_NAME_HEADER_KEY, CLIENT_VERSION_HEADER_KEY,
+};
+use temporal_sdk_core_api::worker::{
+    CircuitBreakerConfig, NonDeterminismDetail, PayloadCodec, PayloadCodecError,
+    RetryPolicyOverride, RpcContext, RpcDeadlines, RpcInterceptor, RpcObservation, RpcObserver,
+    TaskProcessingLatency,
+};
 use temporal_sdk_core_protos::{
-    coresdk::workflow_commands::QueryResult,
+    constants::{
+        ACTIVITY_CANCEL_REASON_KEY, FAILING_COMMAND_INDEX_KEY, NON_DETERMINISM_DETAIL_KEY,
+    },
+    coresdk::{
+        activity_task::ActivityCancelReason, workflow_commands::QueryResult, AsJsonPayloadExt,
+    },
     temporal::api::{
-        command::v1::Command,
+        command::v1::{command::Attributes, Command},
         common::v1::{
-            MeteringMetadata, Payloads, WorkerVersionCapabilities, WorkerVersionStamp,
+            MeteringMetadata, Payload, Payloads, WorkerVersionCapabilities, WorkerVersionStamp,
             WorkflowExecution,
         },
-        enums::v1::{TaskQueueKind, WorkflowTaskFailedCause},
-        failure::v1::Failure,
+        enums::v1::{
+            CommandType, HistoryEventFilterType, ResetReapplyType, TaskQueueKind, TaskQueueType,
+            WorkflowTaskFailedCause,
+        },
+        failure::v1::{failure::FailureInfo, ApplicationFailureInfo, Failure},
+        filter::v1::{StartTimeFilter, WorkflowTypeFilter},
+        history::v1::HistoryEvent,
+        protocol::v1::Message as ProtocolMessage,
         query::v1::WorkflowQueryResult,
         sdk::v1::WorkflowTaskCompletedMetadata,
         taskqueue::v1::{StickyExecutionAttributes, TaskQueue, TaskQueueMetadata},
-        workflowservice::v1::{get_system_info_response::Capabilities, *},
+        workflow::v1::WorkflowExecutionInfo,
+        workflowservice::v1::{
+            get_system_info_response::Capabilities,
+            list_open_workflow_executions_request::Filters as ListOpenFilters, *,
+        },
     },
+    utilities::TryIntoOrNone,
     TaskToken,
 };
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tonic::{
+    codec::CompressionEncoding,
+    metadata::{AsciiMetadataKey, AsciiMetadataValue},
+};
+use tracing::{field, Instrument, Level};
 
 type Result<T, E = tonic::Status> = std::result::Result<T, E>;
 
+/// The server doesn't allow workflow task timeouts longer than this
+const MAX_WORKFLOW_TASK_TIMEOUT: Duration = Duration::from_secs(120);
+/// How many heartbeat RPCs from a single batch may be in flight at once
+const MAX_CONCURRENT_BATCHED_HEARTBEATS: usize = 10;
+/// How many legacy query responses from a single batch may be in flight at once
+const MAX_CONCURRENT_BATCHED_QUERY_RESPONSES: usize = 10;
+
+/// Errors returned by the poll RPCs
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum PollError {
+    /// The poll's cancellation token was cancelled before the server responded. The in-flight
+    /// RPC is dropped, which resets the stream so the server can clean up the poll.
+    #[error("Poll was cancelled")]
+    PollCancelled,
+    /// The poll circuit breaker is open because the server has recently been overloaded, so the
+    /// poll was not issued
+    #[error("Poll circuit breaker is open")]
+    CircuitOpen,
+    /// Unhandled error when calling the temporal server
+    #[error("Unhandled grpc error when polling: {0:?}")]
+    TonicError(#[from] tonic::Status),
+}
+
+impl From<PollError> for tonic::Status {
+    fn from(e: PollError) -> Self {
+        match e {
+            PollError::PollCancelled => tonic::Status::cancelled("Poll was cancelled"),
+            PollError::CircuitOpen => {
+                tonic::Status::unavailable("Poll circuit breaker is open, server is overloaded")
+            }
+            PollError::TonicError(s) => s,
+        }
+    }
+}
+
+/// Errors returned when recording an activity heartbeat
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum HeartbeatError {
+    /// The heartbeat's details are larger than the configured limit, so it was not sent
+    #[error("Heartbeat details are {size} bytes, more than the limit of {limit}")]
+    HeartbeatDetailsTooLarge { size: usize, limit: usize },
+    /// Unhandled error when calling the temporal server
+    #[error("Unhandled grpc error when recording heartbeat: {0:?}")]
+    TonicError(#[from] tonic::Status),
+}
+
+impl From<HeartbeatError> for tonic::Status {
+    fn from(e: HeartbeatError) -> Self {
+        match e {
+            HeartbeatError::HeartbeatDetailsTooLarge { .. } => {
+                tonic::Status::invalid_argument(e.to_string())
+            }
+            HeartbeatError::TonicError(s) => s,
+        }
+    }
+}
+
+/// Errors returned when completing a workflow task
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum CompleteWftError {
+    /// The server no longer knows about the task, which almost always means it timed out (and
+    /// its token expired) before it was completed. The task will be retried by the server.
+    #[error("Workflow task timed out before it was completed: {0:?}")]
+    WorkflowTaskTimedOut(tonic::Status),
+    /// Unhandled error when calling the temporal server
+    #[error("Unhandled grpc error when completing workflow task: {0:?}")]
+    TonicError(tonic::Status),
+}
+
+impl From<tonic::Status> for CompleteWftError {
+    fn from(s: tonic::Status) -> Self {
+        match s.code() {
+            tonic::Code::NotFound => CompleteWftError::WorkflowTaskTimedOut(s),
+            _ => CompleteWftError::TonicError(s),
+        }
+    }
+}
+
+impl From<CompleteWftError> for tonic::Status {
+    fn from(e: CompleteWftError) -> Self {
+        match e {
+            CompleteWftError::WorkflowTaskTimedOut(s) | CompleteWftError::TonicError(s) => s,
+        }
+    }
+}
+
+/// Result of [WorkerClient::check_health]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)] // Only read by callers of WorkerClient::check_health, not core itself
+pub(crate) enum HealthStatus {
+    /// The frontend responded and the namespace is registered
+    Healthy,
+    /// The frontend could not be reached, or did not respond in time
+    FrontendUnreachable { reason: String },
+    /// The frontend responded, but the worker's namespace is not registered with it
+    NamespaceNotFound,
+}
+
+/// Result of [WorkerClient::connection_state]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Only read by callers of WorkerClient::connection_state, not core itself
+pub(crate) enum ConnectionState {
+    /// No RPC has reached (or failed to reach) the server yet
+    Connecting,
+    /// The most recent RPC to finish reached the server, at `last_success`
+    Ready { last_success: Instant },
+    /// The most recent RPC to finish couldn't reach the server. `last_success` is when one last
+    /// did, if ever.
+    TransientFailure {
+        last_failure: Instant,
+        last_success: Option<Instant>,
+    },
+}
+
+/// A change to the compatible build id sets of a task queue, see
+/// [WorkerClient::update_worker_build_id_compatibility]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)] // Only built by callers of update_worker_build_id_compatibility
+pub(crate) enum BuildIdCompatibilityOperation {
+    /// Adds the build id in a new set of its own, which becomes the default set. It is
+    /// incompatible with every existing build id.
+    AddNewDefault(String),
+    /// Adds the build id to the set containing an existing one, marking it compatible with that
+    /// set. If `make_set_default` is true, that set also becomes the default.
+    AddCompatible {
+        new_build_id: String,
+        existing_compatible_build_id: String,
+        make_set_default: bool,
+    },
+    /// Makes the set containing this build id the default set
+    PromoteSet(String),
+    /// Makes this build id the default within its set
+    PromoteWithinSet(String),
+    /// Merges the set containing the secondary build id into the one containing the primary,
+    /// whose default becomes the merged set's default
+    MergeSets {
+        primary_set_build_id: String,
+        secondary_set_build_id: String,
+    },
+}
+
+impl From<BuildIdCompatibilityOperation>
+    for update_worker_build_id_compatibility_request::Operation
+{
+    fn from(op: BuildIdCompatibilityOperation) -> Self {
+        use update_worker_build_id_compatibility_request::{
+            AddNewCompatibleVersion, MergeSets, Operation,
+        };
+        match op {
+            BuildIdCompatibilityOperation::AddNewDefault(id) => {
+                Operation::AddNewBuildIdInNewDefaultSet(id)
+            }
+            BuildIdCompatibilityOperation::AddCompatible {
+                new_build_id,
+                existing_compatible_build_id,
+                make_set_default,
+            } => Operation::AddNewCompatibleBuildId(AddNewCompatibleVersion {
+                new_build_id,
+                existing_compatible_build_id,
+                make_set_default,
+            }),
+            BuildIdCompatibilityOperation::PromoteSet(id) => Operation::PromoteSetByBuildId(id),
+            BuildIdCompatibilityOperation::PromoteWithinSet(id) => {
+                Operation::PromoteBuildIdWithinSet(id)
+            }
+            BuildIdCompatibilityOperation::MergeSets {
+                primary_set_build_id,
+                secondary_set_build_id,
+            } => Operation::MergeSets(MergeSets {
+                primary_set_build_id,
+                secondary_set_build_id,
+            }),
+        }
+    }
+}
+
+fn build_id_compatibility_request(
+    namespace: String,
+    task_queue: String,
+) -> GetWorkerBuildIdCompatibilityRequest {
+    GetWorkerBuildIdCompatibilityRequest {
+        namespace,
+        task_queue,
+        // Zero means all sets, not just the current default one
+        max_sets: 0,
+    }
+}
+
+fn describe_task_queue_request(
+    namespace: String,
+    task_queue: String,
+    kind: TaskQueueKind,
+) -> DescribeTaskQueueRequest {
+    DescribeTaskQueueRequest {
+        namespace,
+        task_queue: Some(TaskQueue {
+            name: task_queue,
+            kind: kind as i32,
+            normal_name: "".to_string(),
+        }),
+        task_queue_type: TaskQueueType::Unspecified as i32,
+        // Needed to get the backlog count hint
+        include_task_queue_status: true,
+    }
+}
+
+/// Turns the outcome of a health check RPC into a [HealthStatus]
+fn health_status<T>(res: Result<T>) -> Result<HealthStatus> {
+    match res {
+        Ok(_) => Ok(HealthStatus::Healthy),
+        Err(s) => match s.code() {
+            tonic::Code::Unavailable | tonic::Code::DeadlineExceeded => {
+                Ok(HealthStatus::FrontendUnreachable {
+                    reason: s.message().to_string(),
+                })
+            }
+            tonic::Code::NotFound => Ok(HealthStatus::NamespaceNotFound),
+            _ => Err(s),
+        },
+    }
+}
+
+/// Returned when a worker is configured to use build-id based versioning but the server it is
+/// connected to doesn't support it
+#[derive(thiserror::Error, Debug)]
+#[error(
+    "Worker versioning was requested (build id `{build_id}`) but the server does not support \
+     build-id based versioning"
+)]
+pub(crate) struct VersioningUnsupportedError {
+    build_id: String,
+}
+
+/// Returned when completing a task whose token was issued for a different namespace than the one
+/// the worker's client is bound to
+#[derive(thiserror::Error, Debug)]
+#[error(
+    "Task token was issued by namespace id `{token_namespace_id}`, but this worker's namespace \
+     `{namespace}` has id `{namespace_id}`. The task was probably routed to the wrong worker."
+)]
+pub(crate) struct TokenNamespaceMismatch {
+    namespace: String,
+    namespace_id: String,
+    token_namespace_id: String,
+}
+
+impl From<TokenNamespaceMismatch> for tonic::Status {
+    fn from(e: TokenNamespaceMismatch) -> Self {
+        tonic::Status::invalid_argument(e.to_string())
+    }
+}
+
+/// Returned when a worker is configured with a gRPC header which can't be sent as ASCII metadata
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid gRPC header `{key}`: {reason}")]
+pub(crate) struct InvalidHeaderError {
+    key: String,
+    reason: &'static str,
+}
+
+/// Returned when the worker's client can't be set up as the worker is configured
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum WorkerClientInitError {
+    /// One of the configured gRPC headers can't be sent
+    #[error(transparent)]
+    InvalidHeader(#[from] InvalidHeaderError),
+    /// The client couldn't be switched over to a connection with the configured keep-alive
+    #[error("Couldn't reconnect with the worker's keep-alive settings: {0}")]
+    KeepAlive(#[from] ClientInitError),
+}
+
+/// A task token which came from an activity task. Only accepted by the activity RPCs, so that
+/// tokens can't accidentally be sent to the RPC for the wrong kind of task.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, derive_more::From)]
+pub(crate) struct ActivityTaskToken(pub TaskToken);
+
+/// A task token which came from a workflow (or legacy query) task. Only accepted by the workflow
+/// task RPCs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, derive_more::From)]
+pub(crate) struct WorkflowTaskToken(pub TaskToken);
+
+impl ActivityTaskToken {
+    fn into_bytes(self) -> Result<Vec<u8>> {
+        non_empty_token(self.0)
+    }
+}
+
+impl WorkflowTaskToken {
+    fn into_bytes(self) -> Result<Vec<u8>> {
+        non_empty_token(self.0)
+    }
+}
+
+fn non_empty_token(tt: TaskToken) -> Result<Vec<u8>> {
+    if tt.0.is_empty() {
+        return Err(tonic::Status::invalid_argument(
+            "Task token must not be empty",
+        ));
+    }
+    Ok(tt.0)
+}
+
+/// Bounds how many task completion RPCs a worker has in flight at once, queueing the rest, so that
+/// bursts of completions don't swamp the connection's flow control. Polls are never limited.
+struct CompletionLimiter {
+    permits: Semaphore,
+    max_in_flight: usize,
+    queued: AtomicUsize,
+}
+
+impl CompletionLimiter {
+    fn new(max_in_flight: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_in_flight),
+            max_in_flight,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many completions are currently waiting for a slot
+    fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// The fraction of slots in use, see [WorkerClient::completion_backpressure]
+    fn saturation(&self) -> f64 {
+        let in_use = self
+            .max_in_flight
+            .saturating_sub(self.permits.available_permits());
+        in_use as f64 / self.max_in_flight as f64
+    }
+
+    async fn run<T>(&self, completion: impl Future<Output = T>) -> T {
+        let _permit = {
+            let _queued = QueuedGuard::new(&self.queued);
+            self.permits
+                .acquire()
+                .await
+                .expect("Completion limiter semaphore is never closed")
+        };
+        completion.await
+    }
+}
+
+/// Counts a completion as queued until dropped, including if it's abandoned while waiting
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedGuard<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 /// Contains everything a worker needs to interact with the server
 pub(crate) struct WorkerClientBag {
     client: RetryClient<Client>,
     namespace: String,
     identity: String,
-    worker_build_id: String,
-    use_versioning: bool,
+    versioning: WorkerVersioning,
+    deadlines: RpcDeadlines,
+    /// Static metadata attached to every RPC this worker issues, validated at construction
+    headers: Vec<(AsciiMetadataKey, AsciiMetadataValue)>,
+    /// Server capabilities, initially those learned at connection time. Replaced wholesale by
+    /// [WorkerClient::refresh_capabilities], so readers always see one consistent set.
+    capabilities: Arc<RwLock<Option<Capabilities>>>,
+    poll_breaker: Option<PollCircuitBreaker>,
+    on_rpc_complete: Option<RpcObserver>,
+    payload_codec: Option<Arc<dyn PayloadCodec>>,
+    capabilities_refresh: Option<CapabilitiesRefreshTrigger>,
+    completion_limiter: Option<Arc<CompletionLimiter>>,
+    interceptors: Vec<Arc<dyn RpcInterceptor>>,
+    server_time: Arc<LatestServerTime>,
+    max_heartbeat_details_size: usize,
+    /// Only kept when there's an observer to report task processing latency to
+    task_receipts: Option<TaskReceipts>,
+    namespace_id: NamespaceId,
+    reachability: Arc<ServerReachability>,
+    history_fetches: InFlightRequests<HistoryFetchKey, SharedHistoryFetchResult>,
+}
+
+/// Everything a [WorkerClientBag] is configured with besides its client. Mostly taken from the
+/// worker's config, see the matching [temporal_sdk_core_api::worker::WorkerConfig] fields.
+#[derive(Debug, Default)]
+pub(crate) struct WorkerClientBagOptions {
+    pub namespace: String,
+    /// Reported to the server with polls and completions
+    pub identity: String,
+    pub worker_build_id: String,
+    pub binary_checksum_override: Option<String>,
+    pub use_versioning: bool,
+    pub deadlines: RpcDeadlines,
+    /// Attached to every RPC, along with the client name and version headers if those are set
+    pub headers: HashMap<String, String>,
+    pub client_name: Option<String>,
+    pub client_version: Option<String>,
+    pub poll_circuit_breaker: Option<CircuitBreakerConfig>,
+    pub on_rpc_complete: Option<RpcObserver>,
+    pub payload_codec: Option<Arc<dyn PayloadCodec>>,
+    pub keep_alive: Option<KeepAliveConfig>,
+    pub compression: Option<CompressionEncoding>,
+    pub capabilities_refresh_poll_interval: Option<usize>,
+    pub max_concurrent_completions: Option<usize>,
+    pub interceptors: Vec<Arc<dyn RpcInterceptor>>,
+    pub retry_override: Option<RetryPolicyOverride>,
+    pub max_heartbeat_details_size: usize,
+    /// Times RPC retries and the poll circuit breaker. The client's own clock is kept if unset.
+    pub clock: Option<Arc<dyn Clock>>,
 }
 
 impl WorkerClientBag {
     pub fn new(
-        client: RetryClient<Client>,
-        namespace: String,
-        identity: String,
-        worker_build_id: String,
-        use_versioning: bool,
-    ) -> Self {
-        Self {
-            client,
+        mut client: RetryClient<Client>,
+        options: WorkerClientBagOptions,
+    ) -> Result<Self, WorkerClientInitError> {
+        let WorkerClientBagOptions {
             namespace,
             identity,
             worker_build_id,
+            binary_checksum_override,
             use_versioning,
+            deadlines,
+            headers,
+            client_name,
+            client_version,
+            poll_circuit_breaker,
+            on_rpc_complete,
+            payload_codec,
+            keep_alive,
+            compression,
+            capabilities_refresh_poll_interval,
+            max_concurrent_completions,
+            interceptors,
+            retry_override,
+            max_heartbeat_details_size,
+            clock,
+        } = options;
+        let headers = parse_headers(with_client_identity(headers, client_name, client_version))?;
+        if let Some(o) = retry_override {
+            if let Some(cfg) = o.poll {
+                client.set_poll_retry_config(cfg);
+            }
+            if let Some(cfg) = o.other {
+                client.set_retry_config(cfg);
+            }
+        }
+        if let Some(clock) = clock {
+            client.set_clock(clock);
+        }
+        if let Some(encoding) = compression {
+            // Clones of the client (including those made per-RPC) share this configured service
+            let svc = client.get_client_mut().inner_mut().workflow_svc_mut();
+            *svc = svc
+                .clone()
+                .send_compressed(encoding)
+                .accept_compressed(encoding);
+        }
+        if let Some(ka) = keep_alive {
+            let inner = client.get_client_mut().inner_mut();
+            if inner.options().keep_alive != Some(ka) {
+                inner.set_keep_alive(ka)?;
+            }
+        }
+        let capabilities = client.get_client().inner().capabilities().cloned();
+        let task_receipts = on_rpc_complete.is_some().then(TaskReceipts::default);
+        Ok(Self {
+            client,
+            namespace,
+            identity,
+            versioning: WorkerVersioning {
+                build_id: worker_build_id,
+                binary_checksum_override,
+                use_versioning,
+            },
+            deadlines,
+            headers,
+            capabilities: Arc::new(RwLock::new(capabilities)),
+            poll_breaker: poll_circuit_breaker.map(PollCircuitBreaker::new),
+            on_rpc_complete,
+            payload_codec,
+            capabilities_refresh: capabilities_refresh_poll_interval
+                .map(CapabilitiesRefreshTrigger::new),
+            completion_limiter: max_concurrent_completions
+                .map(|max| Arc::new(CompletionLimiter::new(max))),
+            interceptors,
+            server_time: Default::default(),
+            max_heartbeat_details_size,
+            task_receipts,
+            namespace_id: Default::default(),
+            reachability: Default::default(),
+            history_fetches: Default::default(),
+        })
+    }
+
+    /// Returns a new bag bound to a different namespace which shares this bag's underlying
+    /// connection and already-fetched server capabilities. All other settings are kept.
+    #[allow(dead_code)] // Workers only ever poll the namespace they were configured with
+    pub fn with_namespace(&self, namespace: String) -> WorkerClientBag {
+        WorkerClientBag {
+            client: self.client.clone(),
+            namespace,
+            identity: self.identity.clone(),
+            versioning: self.versioning.clone(),
+            deadlines: self.deadlines,
+            headers: self.headers.clone(),
+            capabilities: self.capabilities.clone(),
+            // Overload is often namespace-specific (ex: rate limits), so don't share breaker state
+            poll_breaker: self
+                .poll_breaker
+                .as_ref()
+                .map(|b| PollCircuitBreaker::new(b.config)),
+            on_rpc_complete: self.on_rpc_complete.clone(),
+            payload_codec: self.payload_codec.clone(),
+            capabilities_refresh: self
+                .capabilities_refresh
+                .as_ref()
+                .map(|t| CapabilitiesRefreshTrigger::new(t.every_n_polls)),
+            // The connection is shared, so its flow control is too
+            completion_limiter: self.completion_limiter.clone(),
+            interceptors: self.interceptors.clone(),
+            // It's the same server, so the same clock
+            server_time: self.server_time.clone(),
+            max_heartbeat_details_size: self.max_heartbeat_details_size,
+            // Tasks are completed through the bag which polled them
+            task_receipts: self.task_receipts.as_ref().map(|_| TaskReceipts::default()),
+            namespace_id: Default::default(),
+            // The connection is shared, so whether it works is too
+            reachability: self.reachability.clone(),
+            // Histories are fetched from the bag's own namespace
+            history_fetches: Default::default(),
         }
     }
 
-    fn default_capabilities(&self) -> Capabilities {
-        self.capabilities().cloned().unwrap_or_default()
+    /// Appends a suffix to this bag's identity, separated by a `/` (ex: `worker@host/pod-xyz`), so
+    /// that pollers and completions can be attributed to ex: a specific pod. Applies to every RPC
+    /// which sends the bag's identity, but not to polls given an identity override. An empty
+    /// suffix leaves the identity as is.
+    #[allow(dead_code)] // Workers don't take an identity suffix in their config yet
+    pub fn with_identity_suffix(mut self, suffix: String) -> Self {
+        self.identity = suffixed_identity(&self.identity, &suffix);
+        self
     }
 
-    fn binary_checksum(&self) -> String {
-        if self.default_capabilities().build_id_based_versioning {
-            "".to_string()
-        } else {
-            self.worker_build_id.clone()
+    /// Reconnects to the server, picking up the client's current certificate from its
+    /// [temporal_client::ClientCertSource] if one is configured. Calls already in flight finish on
+    /// the old connection.
+    #[allow(dead_code)] // Only called by whoever rotates the certificate, not core itself
+    pub async fn reload_tls(&self) -> Result<(), ClientInitError> {
+        self.client.get_client().inner().reload_tls().await
+    }
+
+    /// Checks that the task token was issued by this bag's namespace, so that completing a task
+    /// routed to the wrong worker fails clearly, rather than with whatever the server makes of it.
+    /// The namespace's id, which tokens carry, is learned from the first poll to return a task
+    /// (or a health check). Until then, and for tokens which can't be decoded, the check passes.
+    pub(crate) fn validate_token_namespace(
+        &self,
+        token: &TaskToken,
+    ) -> Result<(), TokenNamespaceMismatch> {
+        self.namespace_id.validate(&self.namespace, token)
+    }
+
+    /// The namespace this bag issues RPCs against
+    pub(crate) fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The identity reported to the server with polls and completions
+    pub(crate) fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// The build id of the worker this bag belongs to
+    pub(crate) fn worker_build_id(&self) -> &str {
+        &self.versioning.build_id
+    }
+
+    /// Whether the worker opted in to build-id based versioning
+    pub(crate) fn use_versioning(&self) -> bool {
+        self.versioning.use_versioning
+    }
+
+    /// Returns an error if the worker was configured to use build-id based versioning but the
+    /// server does not support it, rather than silently behaving as an unversioned worker.
+    pub fn validate_versioning(&self) -> Result<(), VersioningUnsupportedError> {
+        if self.versioning.use_versioning && !self.capabilities_snapshot().build_id_based_versioning
+        {
+            return Err(VersioningUnsupportedError {
+                build_id: self.versioning.build_id.clone(),
+            });
         }
+        Ok(())
     }
 
-    fn worker_version_capabilities(&self) -> Option<WorkerVersionCapabilities> {
-        if self.default_capabilities().build_id_based_versioning {
-            Some(WorkerVersionCapabilities {
-                build_id: self.worker_build_id.clone(),
-                use_versioning: self.use_versioning,
-            })
-        } else {
-            None
+    /// Forgets the task, returning how long it took to process if latency is being tracked
+    fn task_completed(&self, task_token: &[u8]) -> Option<TaskProcessingLatency> {
+        self.task_receipts.as_ref()?.completed(task_token)
+    }
+
+    /// Runs the RPC inside a span recording its method, duration and status, and reports the
+    /// outcome to the configured observer, if any. The span is a child of whichever span the
+    /// caller is in, so RPCs show up under the application's own traces.
+    async fn observed<T>(
+        &self,
+        method: &'static str,
+        rpc: impl Future<Output = Result<tonic::Response<T>>>,
+    ) -> Result<tonic::Response<T>> {
+        self.observed_with(method, rpc, None, None).await
+    }
+
+    /// Same as [Self::observed], also reporting the non-determinism which caused the call, and
+    /// how long the task the call completes took to process (if either applies) to the observer
+    async fn observed_with<T>(
+        &self,
+        method: &'static str,
+        rpc: impl Future<Output = Result<tonic::Response<T>>>,
+        non_determinism: Option<NonDeterminismDetail>,
+        task_processing: Option<TaskProcessingLatency>,
+    ) -> Result<tonic::Response<T>> {
+        let span = span!(
+            Level::DEBUG,
+            "rpc",
+            method,
+            code = field::Empty,
+            duration_ms = field::Empty
+        );
+        let start = Instant::now();
+        let res = rpc.instrument(span.clone()).await;
+        let duration = start.elapsed();
+        let code = res.as_ref().map_or_else(|s| s.code(), |_| tonic::Code::Ok);
+        span.record("code", field::debug(code));
+        span.record("duration_ms", duration.as_millis() as u64);
+        self.reachability.record(code, self.client.clock().now());
+
+        let observer = match &self.on_rpc_complete {
+            Some(o) => o,
+            None => return res,
+        };
+        let observation = RpcObservation {
+            method,
+            duration,
+            code,
+            attempts: match &res {
+                Ok(r) => r.extensions().get::<RetryAttempts>().copied(),
+                Err(s) => RetryAttempts::of_status(s),
+            }
+            .map(|a| a.0),
+            queued_completions: self.completion_limiter.as_ref().map(|l| l.queued()),
+            non_determinism,
+            task_processing,
+        };
+        // A misbehaving observer must not interfere with the RPC itself
+        if panic::catch_unwind(AssertUnwindSafe(|| (observer.0)(observation))).is_err() {
+            warn!(method, "RPC observer panicked");
         }
+        res
     }
 
-    fn worker_version_stamp(&self) -> Option<WorkerVersionStamp> {
-        if self.default_capabilities().build_id_based_versioning {
-            Some(WorkerVersionStamp {
-                build_id: self.worker_build_id.clone(),
-                bundle_id: "".to_string(),
-                use_versioning: self.use_versioning,
-            })
-        } else {
-            None
+    /// Same as [Self::observed], but for task completion RPCs, which wait for a slot from the
+    /// completion limiter (if one is configured) before being issued. `task_processing` is how
+    /// long the completed task took to process, if it was polled by this bag.
+    async fn observed_completion<T>(
+        &self,
+        method: &'static str,
+        rpc: impl Future<Output = Result<tonic::Response<T>>>,
+        task_processing: Option<TaskProcessingLatency>,
+    ) -> Result<tonic::Response<T>> {
+        self.limited(self.observed_with(method, rpc, None, task_processing))
+            .await
+    }
+
+    /// Waits for a slot from the completion limiter (if one is configured) before running `fut`
+    async fn limited<T>(&self, fut: impl Future<Output = T>) -> T {
+        match &self.completion_limiter {
+            Some(limiter) => limiter.run(fut).await,
+            None => fut.await,
+        }
+    }
+
+    /// Runs the poll through the circuit breaker, if one is configured
+    async fn guarded_poll<T>(
+        &self,
+        poll: impl Future<Output = Result<T, PollError>>,
+    ) -> Result<T, PollError> {
+        let breaker = match &self.poll_breaker {
+            Some(b) => b,
+            None => return poll.await,
+        };
+        breaker.check(self.client.clock().now())?;
+        let res = poll.await;
+        breaker.record(&res, self.client.clock().now());
+        res
+    }
+
+    async fn poll_request<T>(
+        &self,
+        method: &'static str,
+        msg: T,
+        timeout_override: Option<Duration>,
+    ) -> tonic::Request<T> {
+        self.request(method, msg, timeout_override.unwrap_or(self.deadlines.poll))
+            .await
+    }
+
+    async fn unary_request<T>(&self, method: &'static str, msg: T) -> tonic::Request<T> {
+        self.request(method, msg, self.deadlines.other).await
+    }
+
+    async fn request<T>(
+        &self,
+        method: &'static str,
+        msg: T,
+        deadline: Duration,
+    ) -> tonic::Request<T> {
+        let mut req = tonic::Request::new(msg);
+        req.set_timeout(deadline);
+        let md = req.metadata_mut();
+        for (k, v) in &self.headers {
+            md.insert(k.clone(), v.clone());
+        }
+        intercept(&self.interceptors, method, &mut req).await;
+        req
+    }
+
+    /// Encodes payloads headed for the server with the payload codec, if there is one
+    fn encoded(&self, mut payloads: Option<Payloads>) -> Result<Option<Payloads>, tonic::Status> {
+        if let Some(codec) = &self.payload_codec {
+            codec::encode_payloads(codec.as_ref(), &mut payloads).map_err(codec_failed)?;
+        }
+        Ok(payloads)
+    }
+
+    fn decoded_history_page(
+        &self,
+        mut resp: GetWorkflowExecutionHistoryResponse,
+    ) -> Result<GetWorkflowExecutionHistoryResponse> {
+        if let (Some(codec), Some(history)) = (&self.payload_codec, resp.history.as_mut()) {
+            codec::decode_history(codec.as_ref(), history).map_err(codec_failed)?;
+        }
+        Ok(resp)
+    }
+
+    fn binary_checksum(&self, caps: &Capabilities) -> String {
+        self.versioning.binary_checksum(caps)
+    }
+
+    fn worker_version_capabilities(
+        &self,
+        caps: &Capabilities,
+    ) -> Option<WorkerVersionCapabilities> {
+        self.versioning.capabilities(caps)
+    }
+
+    fn worker_version_stamp(&self, caps: &Capabilities) -> Option<WorkerVersionStamp> {
+        self.versioning.stamp(caps)
+    }
+
+    /// Re-fetches server capabilities if enough polls have been issued since they were last
+    /// fetched. See [CapabilitiesRefreshTrigger].
+    async fn maybe_refresh_capabilities(&self) {
+        if !self
+            .capabilities_refresh
+            .as_ref()
+            .map_or(false, CapabilitiesRefreshTrigger::record_poll)
+        {
+            return;
+        }
+        if let Err(e) = self.refresh_capabilities().await {
+            warn!(error=?e, "Failed to refresh server capabilities, continuing with old ones");
         }
     }
 }
@@ -93,56 +861,281 @@ pub(crate) trait WorkerClient: Sync + Send {
     async fn poll_workflow_task(
         &self,
         task_queue: TaskQueue,
-    ) -> Result<PollWorkflowTaskQueueResponse>;
+        identity_override: Option<String>,
+        cancel: CancellationToken,
+    ) -> Result<PollWorkflowTaskQueueResponse, PollError>;
+    /// Same as [WorkerClient::poll_workflow_task] but also returns the response metadata.
+    ///
+    /// If `poll_timeout_override` is set, it replaces the configured poll deadline for this poll.
+    /// See [WorkerClient::poll_activity_task_of_kind] for how such polls behave.
+    async fn poll_workflow_task_with_meta(
+        &self,
+        task_queue: TaskQueue,
+        identity_override: Option<String>,
+        poll_timeout_override: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<WithMeta<PollWorkflowTaskQueueResponse>, PollError>;
+    /// Poll the given sticky queue, filling in the sticky kind and the normal queue name
+    async fn poll_workflow_task_sticky(
+        &self,
+        sticky_queue_name: String,
+        normal_name: String,
+        cancel: CancellationToken,
+    ) -> Result<PollWorkflowTaskQueueResponse, PollError>;
+    /// Poll for an activity task. If `build_id_affinity` is set and the server supports build-id
+    /// based versioning, the poll asks for tasks affine to that build id rather than the worker's
+    /// own. This is only a routing hint, there is no guarantee tasks will be dispatched
+    /// accordingly.
     async fn poll_activity_task(
         &self,
         task_queue: String,
         max_tasks_per_sec: Option<f64>,
-    ) -> Result<PollActivityTaskQueueResponse>;
+        build_id_affinity: Option<String>,
+        identity_override: Option<String>,
+        cancel: CancellationToken,
+    ) -> Result<PollActivityTaskQueueResponse, PollError>;
+    /// Same as [WorkerClient::poll_activity_task], but polls the given queue as whatever kind it
+    /// specifies, rather than as a normal queue. Sticky queues must name the normal queue they
+    /// belong to in `normal_name`, or the poll fails without being sent.
+    ///
+    /// If `poll_timeout_override` is set, it replaces the configured poll deadline for this poll.
+    /// The deadline is sent to the server, which answers with no task shortly before it passes,
+    /// so a short timeout (ex: while draining pollers during shutdown) makes the poll return
+    /// quickly. Such polls aren't retried, and running out of time is treated as the server
+    /// answering with no task rather than as an error.
+    async fn poll_activity_task_of_kind(
+        &self,
+        task_queue: TaskQueue,
+        max_tasks_per_sec: Option<f64>,
+        build_id_affinity: Option<String>,
+        identity_override: Option<String>,
+        poll_timeout_override: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<PollActivityTaskQueueResponse, PollError>;
+    /// Complete a workflow task. A task which the server no longer knows about is reported as
+    /// [CompleteWftError::WorkflowTaskTimedOut] rather than as a generic failure.
     async fn complete_workflow_task(
         &self,
         request: WorkflowTaskCompletion,
-    ) -> Result<RespondWorkflowTaskCompletedResponse>;
+    ) -> Result<RespondWorkflowTaskCompletedResponse, CompleteWftError>;
+    /// Same as [WorkerClient::complete_workflow_task] but also returns the response metadata
+    async fn complete_workflow_task_with_meta(
+        &self,
+        request: WorkflowTaskCompletion,
+    ) -> Result<WithMeta<RespondWorkflowTaskCompletedResponse>, CompleteWftError>;
     async fn complete_activity_task(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         result: Option<Payloads>,
     ) -> Result<RespondActivityTaskCompletedResponse>;
     async fn record_activity_heartbeat(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
+        details: Option<Payloads>,
+    ) -> Result<RecordActivityTaskHeartbeatResponse, HeartbeatError>;
+    /// Records a heartbeat and returns only whether the server has asked the activity to cancel
+    async fn heartbeat_and_check_cancel(
+        &self,
+        task_token: ActivityTaskToken,
         details: Option<Payloads>,
-    ) -> Result<RecordActivityTaskHeartbeatResponse>;
+    ) -> Result<bool>;
+    /// Record heartbeats for many activities at once. The returned results are index-aligned with
+    /// the input, and a failure recording one heartbeat does not affect the others.
+    async fn record_activity_heartbeats(
+        &self,
+        beats: Vec<(ActivityTaskToken, Option<Payloads>)>,
+    ) -> Vec<Result<RecordActivityTaskHeartbeatResponse, HeartbeatError>>;
+    /// Report an activity as cancelled. If core knows why it asked lang to cancel the activity,
+    /// `reason` is recorded in a payload appended to the details, see
+    /// [ACTIVITY_CANCEL_REASON_KEY].
     async fn cancel_activity_task(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         details: Option<Payloads>,
+        reason: Option<ActivityCancelReason>,
     ) -> Result<RespondActivityTaskCanceledResponse>;
     async fn fail_activity_task(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         failure: Option<Failure>,
+        last_heartbeat_details: Option<Payloads>,
     ) -> Result<RespondActivityTaskFailedResponse>;
+    /// Complete an activity identified by its workflow and activity id rather than its task token,
+    /// for use when whatever finished the activity (ex: an external system completing it
+    /// asynchronously) only knows those. Without a run id, the workflow's latest run is used.
+    async fn complete_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        result: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCompletedByIdResponse>;
+    /// Fail an activity identified by id, see [WorkerClient::complete_activity_task_by_id]
+    async fn fail_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        failure: Option<Failure>,
+        last_heartbeat_details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskFailedByIdResponse>;
+    /// Report an activity identified by id as cancelled, see
+    /// [WorkerClient::complete_activity_task_by_id]
+    async fn cancel_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCanceledByIdResponse>;
+    /// Fail a workflow task. If the failure was caused by a specific command, its index can be
+    /// given as `failing_command_index`, which is noted in the failure's message and recorded in
+    /// its application failure details under [FAILING_COMMAND_INDEX_KEY].
+    ///
+    /// If the task failed because the workflow is non-deterministic, what didn't match can be
+    /// given as `non_determinism`. The cause is then always reported as
+    /// [WorkflowTaskFailedCause::NonDeterministicError]. The detail is noted and recorded like the
+    /// index, under [NON_DETERMINISM_DETAIL_KEY], and passed on to the RPC observer.
     async fn fail_workflow_task(
         &self,
-        task_token: TaskToken,
+        task_token: WorkflowTaskToken,
         cause: WorkflowTaskFailedCause,
         failure: Option<Failure>,
+        failing_command_index: Option<usize>,
+        non_determinism: Option<NonDeterminismDetail>,
     ) -> Result<RespondWorkflowTaskFailedResponse>;
     async fn get_workflow_execution_history(
         &self,
         workflow_id: String,
         run_id: Option<String>,
         page_token: Vec<u8>,
+        wait_new_event: bool,
+        filter_type: HistoryEventFilterType,
     ) -> Result<GetWorkflowExecutionHistoryResponse>;
+    async fn get_workflow_execution_history_reverse(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        page_token: Vec<u8>,
+    ) -> Result<GetWorkflowExecutionHistoryReverseResponse>;
     async fn respond_legacy_query(
         &self,
-        task_token: TaskToken,
+        task_token: WorkflowTaskToken,
         query_result: QueryResult,
     ) -> Result<RespondQueryTaskCompletedResponse>;
+    async fn respond_legacy_queries(
+        &self,
+        responses: Vec<(WorkflowTaskToken, QueryResult)>,
+    ) -> Vec<Result<RespondQueryTaskCompletedResponse>>;
+    async fn describe_task_queue(
+        &self,
+        task_queue: String,
+        kind: TaskQueueKind,
+    ) -> Result<DescribeTaskQueueResponse>;
+    /// Fetches every set of compatible build ids on the task queue, oldest first. The last set
+    /// holds the current default build id, which new workflows are routed to.
+    async fn get_worker_build_id_compatibility(
+        &self,
+        task_queue: String,
+    ) -> Result<GetWorkerBuildIdCompatibilityResponse>;
+    /// Changes the compatible build id sets of the task queue, ex: to promote a freshly deployed
+    /// build id to be the default.
+    async fn update_worker_build_id_compatibility(
+        &self,
+        task_queue: String,
+        operation: BuildIdCompatibilityOperation,
+    ) -> Result<UpdateWorkerBuildIdCompatibilityResponse>;
+    async fn reset_sticky_task_queue(
+        &self,
+        execution: WorkflowExecution,
+    ) -> Result<ResetStickyTaskQueueResponse>;
+    /// Tells the server this worker no longer has the run cached, so that its next workflow task
+    /// is dispatched to the normal task queue right away instead of to our sticky queue, where it
+    /// would sit until the sticky schedule-to-start timeout expired.
+    ///
+    /// There's no completion flag for this, and by the time a run is evicted to make room in the
+    /// cache its last task has already been completed with sticky attributes, so this is done
+    /// with the `ResetStickyTaskQueue` RPC.
+    async fn disclaim_sticky(&self, execution: WorkflowExecution) -> Result<()>;
+    async fn delete_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+    ) -> Result<DeleteWorkflowExecutionResponse>;
+    async fn terminate_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        reason: String,
+        details: Option<Payloads>,
+    ) -> Result<TerminateWorkflowExecutionResponse>;
+    /// Resets a workflow execution to the given workflow task finished (completed, failed or timed
+    /// out) or started event, starting a new run from that point. `reset_reapply_type` decides
+    /// whether signals received after that point are reapplied to the new run. Each call is given
+    /// its own request id, which the server uses to de-dupe retries of it.
+    async fn reset_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        reset_to_event_id: i64,
+        reason: String,
+        reset_reapply_type: ResetReapplyType,
+    ) -> Result<ResetWorkflowExecutionResponse>;
+    /// Signal a workflow execution directly. This happens outside of any workflow's history, so
+    /// it bypasses determinism guarantees: it is only for use by activities (or other non-workflow
+    /// code), never from workflow code, which must use signal-external-workflow commands instead.
+    async fn signal_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        signal_name: String,
+        input: Option<Payloads>,
+    ) -> Result<SignalWorkflowExecutionResponse>;
+    async fn count_workflow_executions(
+        &self,
+        query: String,
+    ) -> Result<CountWorkflowExecutionsResponse>;
+    /// Lists one page of the open workflow executions in the worker's namespace, optionally
+    /// narrowed to those started within a time range and/or of one workflow type. An empty page
+    /// token fetches the first page. See [stream_open_workflow_executions] to fetch all pages.
+    async fn list_open_workflow_executions(
+        &self,
+        start_time_filter: Option<StartTimeFilter>,
+        type_filter: Option<WorkflowTypeFilter>,
+        page_token: Vec<u8>,
+    ) -> Result<ListOpenWorkflowExecutionsResponse>;
+    /// Checks that the frontend is reachable and knows the worker's namespace, by describing the
+    /// namespace. Errors other than those two conditions are returned as-is.
+    async fn check_health(&self) -> Result<HealthStatus>;
 
-    #[allow(clippy::needless_lifetimes)] // Clippy is wrong here
-    fn capabilities<'a>(&'a self) -> Option<&'a get_system_info_response::Capabilities>;
+    /// Re-fetches the server's capabilities and replaces the cached ones with them. RPCs already
+    /// in flight keep using whichever capabilities they were built with.
+    async fn refresh_capabilities(&self) -> Result<Capabilities>;
+
+    /// Makes sure the connection is up and the server's capabilities are known, so the worker's
+    /// first poll doesn't pay for either. Every call re-fetches the capabilities, which makes it
+    /// safe to call any number of times, concurrently or not.
+    async fn warmup(&self) -> Result<()>;
+
+    fn capabilities(&self) -> Option<Capabilities>;
+    /// Returns an owned copy of the server capabilities, defaulted if they are unknown. The worker
+    /// currently depends on:
+    /// * `build_id_based_versioning` - whether to send version capabilities / stamps or the
+    ///   legacy binary checksum
+    /// * `sdk_metadata` - whether internal SDK flags may be recorded in history
+    fn capabilities_snapshot(&self) -> Capabilities;
+    /// The latest time the server has reported as its current time, which is when it started
+    /// the task handed out by a poll. None until a poll has returned a task. Comparing this to
+    /// local time when a poll returns gives a rough (within the poll's network latency) measure
+    /// of clock skew between worker and server.
+    fn observed_server_time(&self) -> Option<SystemTime>;
+    /// How saturated the completion limiter is, from 0.0 (no completions in flight) to 1.0 (as
+    /// many in flight as allowed, so any more are queued). Pollers can throttle on this to avoid
+    /// taking on more tasks than the worker can send results for. Always 0.0 if the worker's
+    /// `max_concurrent_completions` isn't set.
+    fn completion_backpressure(&self) -> f64;
+    /// Whether the client can currently reach the server, without issuing an RPC. tonic doesn't
+    /// expose the connectivity of its channels, so this is inferred from when RPCs last did and
+    /// didn't reach the server. Any response from the server counts as reaching it, even an
+    /// error, while `Unavailable` counts as not. Cancelled calls and those exceeding their
+    /// deadline say nothing either way.
+    fn connection_state(&self) -> ConnectionState;
 }
 
 #[async_trait::async_trait]
@@ -150,188 +1143,491 @@ impl WorkerClient for WorkerClientBag {
     async fn poll_workflow_task(
         &self,
         task_queue: TaskQueue,
-    ) -> Result<PollWorkflowTaskQueueResponse> {
+        identity_override: Option<String>,
+        cancel: CancellationToken,
+    ) -> Result<PollWorkflowTaskQueueResponse, PollError> {
+        self.poll_workflow_task_with_meta(task_queue, identity_override, None, cancel)
+            .await
+            .map(|r| r.body)
+    }
+
+    async fn poll_workflow_task_with_meta(
+        &self,
+        task_queue: TaskQueue,
+        identity_override: Option<String>,
+        poll_timeout_override: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<WithMeta<PollWorkflowTaskQueueResponse>, PollError> {
+        self.maybe_refresh_capabilities().await;
+        let caps = self.capabilities_snapshot();
+        let queue_name = normal_queue_name(&task_queue);
         let request = PollWorkflowTaskQueueRequest {
             namespace: self.namespace.clone(),
             task_queue: Some(task_queue),
-            identity: self.identity.clone(),
-            binary_checksum: self.binary_checksum(),
-            worker_version_capabilities: self.worker_version_capabilities(),
+            identity: identity_override.unwrap_or_else(|| self.identity.clone()),
+            binary_checksum: self.binary_checksum(&caps),
+            worker_version_capabilities: self.worker_version_capabilities(&caps),
         };
 
-        Ok(self
-            .client
-            .clone()
-            .poll_workflow_task_queue(request)
+        let req = self
+            .poll_request("poll_workflow_task_queue", request, poll_timeout_override)
+            .await;
+        let poll = self.observed("poll_workflow_task_queue", async {
+            if poll_timeout_override.is_some() {
+                // Retrying would defeat the point of the shorter timeout
+                self.client
+                    .get_client()
+                    .clone()
+                    .poll_workflow_task_queue(req)
+                    .await
+            } else {
+                self.client.clone().poll_workflow_task_queue(req).await
+            }
+        });
+        let poll = timeout_as_empty(poll, poll_timeout_override);
+        let mut resp: WithMeta<_> = self
+            .guarded_poll(cancellable_poll(poll, cancel))
             .await?
-            .into_inner())
+            .into();
+        self.server_time.observe(resp.body.started_time.clone());
+        self.namespace_id.learn_from_token(&resp.body.task_token);
+        if let Some(receipts) = &self.task_receipts {
+            receipts.received(&resp.body.task_token, queue_name, MAX_WORKFLOW_TASK_TIMEOUT);
+        }
+        if let Some(codec) = &self.payload_codec {
+            codec::decode_poll_response(codec.as_ref(), &mut resp.body).map_err(codec_failed)?;
+        }
+        Ok(resp)
+    }
+
+    async fn poll_workflow_task_sticky(
+        &self,
+        sticky_queue_name: String,
+        normal_name: String,
+        cancel: CancellationToken,
+    ) -> Result<PollWorkflowTaskQueueResponse, PollError> {
+        self.poll_workflow_task(
+            TaskQueue {
+                name: sticky_queue_name,
+                kind: TaskQueueKind::Sticky as i32,
+                normal_name,
+            },
+            None,
+            cancel,
+        )
+        .await
     }
 
     async fn poll_activity_task(
         &self,
         task_queue: String,
         max_tasks_per_sec: Option<f64>,
-    ) -> Result<PollActivityTaskQueueResponse> {
-        let request = PollActivityTaskQueueRequest {
-            namespace: self.namespace.clone(),
-            task_queue: Some(TaskQueue {
+        build_id_affinity: Option<String>,
+        identity_override: Option<String>,
+        cancel: CancellationToken,
+    ) -> Result<PollActivityTaskQueueResponse, PollError> {
+        self.poll_activity_task_of_kind(
+            TaskQueue {
                 name: task_queue,
                 kind: TaskQueueKind::Normal as i32,
                 normal_name: "".to_string(),
-            }),
-            identity: self.identity.clone(),
-            task_queue_metadata: max_tasks_per_sec.map(|tps| TaskQueueMetadata {
+            },
+            max_tasks_per_sec,
+            build_id_affinity,
+            identity_override,
+            None,
+            cancel,
+        )
+        .await
+    }
+
+    async fn poll_activity_task_of_kind(
+        &self,
+        task_queue: TaskQueue,
+        max_tasks_per_sec: Option<f64>,
+        build_id_affinity: Option<String>,
+        identity_override: Option<String>,
+        poll_timeout_override: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<PollActivityTaskQueueResponse, PollError> {
+        validate_task_queue_kind(&task_queue)?;
+        self.maybe_refresh_capabilities().await;
+        let caps = self.capabilities_snapshot();
+        let queue_name = normal_queue_name(&task_queue);
+        let request = PollActivityTaskQueueRequest {
+            namespace: self.namespace.clone(),
+            task_queue: Some(task_queue),
+            identity: identity_override.unwrap_or_else(|| self.identity.clone()),
+            task_queue_metadata: max_tasks_per_sec.map(|tps| TaskQueueMetadata {
                 max_tasks_per_second: Some(tps),
             }),
-            worker_version_capabilities: self.worker_version_capabilities(),
+            worker_version_capabilities: match build_id_affinity {
+                Some(build_id) if caps.build_id_based_versioning => {
+                    Some(WorkerVersionCapabilities {
+                        build_id,
+                        use_versioning: true,
+                    })
+                }
+                _ => self.worker_version_capabilities(&caps),
+            },
         };
 
-        Ok(self
-            .client
-            .clone()
-            .poll_activity_task_queue(request)
+        let req = self
+            .poll_request("poll_activity_task_queue", request, poll_timeout_override)
+            .await;
+        let poll = self.observed("poll_activity_task_queue", async {
+            if poll_timeout_override.is_some() {
+                // Retrying would defeat the point of the shorter timeout
+                self.client
+                    .get_client()
+                    .clone()
+                    .poll_activity_task_queue(req)
+                    .await
+            } else {
+                self.client.clone().poll_activity_task_queue(req).await
+            }
+        });
+        let poll = timeout_as_empty(poll, poll_timeout_override);
+        let mut resp = self
+            .guarded_poll(cancellable_poll(poll, cancel))
             .await?
-            .into_inner())
+            .into_inner();
+        self.server_time.observe(resp.started_time.clone());
+        self.namespace_id.learn_from_token(&resp.task_token);
+        // The server always sets the start-to-close timeout on the tasks it hands out
+        let timeout: Option<Duration> = resp
+            .start_to_close_timeout
+            .clone()
+            .or_else(|| resp.schedule_to_close_timeout.clone())
+            .try_into_or_none();
+        if let (Some(receipts), Some(timeout)) = (&self.task_receipts, timeout) {
+            receipts.received(&resp.task_token, queue_name, timeout);
+        }
+        if let Some(codec) = &self.payload_codec {
+            codec::decode_activity_task(codec.as_ref(), &mut resp).map_err(codec_failed)?;
+        }
+        Ok(resp)
     }
 
     async fn complete_workflow_task(
         &self,
         request: WorkflowTaskCompletion,
-    ) -> Result<RespondWorkflowTaskCompletedResponse> {
-        let request = RespondWorkflowTaskCompletedRequest {
-            task_token: request.task_token.into(),
-            commands: request.commands,
-            identity: self.identity.clone(),
-            sticky_attributes: request.sticky_attributes,
-            return_new_workflow_task: request.return_new_workflow_task,
-            force_create_new_workflow_task: request.force_create_new_workflow_task,
-            worker_version_stamp: self.worker_version_stamp(),
-            messages: vec![],
-            binary_checksum: self.binary_checksum(),
-            query_results: request
-                .query_responses
-                .into_iter()
-                .map(|qr| {
-                    let (id, completed_type, query_result, error_message) = qr.into_components();
-                    (
-                        id,
-                        WorkflowQueryResult {
-                            result_type: completed_type as i32,
-                            answer: query_result,
-                            error_message,
-                        },
-                    )
-                })
-                .collect(),
-            namespace: self.namespace.clone(),
-            sdk_metadata: Some(request.sdk_metadata),
-            metering_metadata: Some(request.metering_metadata),
-        };
-        Ok(self
-            .client
-            .clone()
-            .respond_workflow_task_completed(request)
+    ) -> Result<RespondWorkflowTaskCompletedResponse, CompleteWftError> {
+        self.complete_workflow_task_with_meta(request)
+            .await
+            .map(|r| r.body)
+    }
+
+    async fn complete_workflow_task_with_meta(
+        &self,
+        request: WorkflowTaskCompletion,
+    ) -> Result<WithMeta<RespondWorkflowTaskCompletedResponse>, CompleteWftError> {
+        self.validate_token_namespace(&request.task_token)
+            .map_err(tonic::Status::from)?;
+        let request = request.to_proto(self)?;
+        let task_processing = self.task_completed(&request.task_token);
+        let mut resp: WithMeta<_> = self
+            .observed_completion(
+                "respond_workflow_task_completed",
+                self.client.clone().respond_workflow_task_completed(
+                    self.unary_request("respond_workflow_task_completed", request)
+                        .await,
+                ),
+                task_processing,
+            )
             .await?
-            .into_inner())
+            .into();
+        if let Some(codec) = &self.payload_codec {
+            codec::decode_completion_response(codec.as_ref(), &mut resp.body)
+                .map_err(codec_failed)?;
+        }
+        Ok(resp)
     }
 
     async fn complete_activity_task(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         result: Option<Payloads>,
     ) -> Result<RespondActivityTaskCompletedResponse> {
+        self.validate_token_namespace(&task_token.0)?;
+        let result = self.encoded(result)?;
+        let task_processing = self.task_completed(&task_token.0 .0);
+        let caps = self.capabilities_snapshot();
         Ok(self
-            .client
-            .clone()
-            .respond_activity_task_completed(RespondActivityTaskCompletedRequest {
-                task_token: task_token.0,
-                result,
-                identity: self.identity.clone(),
-                namespace: self.namespace.clone(),
-                worker_version: self.worker_version_stamp(),
-            })
+            .observed_completion(
+                "respond_activity_task_completed",
+                self.client.clone().respond_activity_task_completed(
+                    self.unary_request(
+                        "respond_activity_task_completed",
+                        RespondActivityTaskCompletedRequest {
+                            task_token: task_token.into_bytes()?,
+                            result,
+                            identity: self.identity.clone(),
+                            namespace: self.namespace.clone(),
+                            worker_version: self.worker_version_stamp(&caps),
+                        },
+                    )
+                    .await,
+                ),
+                task_processing,
+            )
             .await?
             .into_inner())
     }
 
     async fn record_activity_heartbeat(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         details: Option<Payloads>,
-    ) -> Result<RecordActivityTaskHeartbeatResponse> {
+    ) -> Result<RecordActivityTaskHeartbeatResponse, HeartbeatError> {
+        // The limit applies to what's actually sent
+        let details = self.encoded(details)?;
+        check_heartbeat_details_size(details.as_ref(), self.max_heartbeat_details_size)?;
         Ok(self
-            .client
-            .clone()
-            .record_activity_task_heartbeat(RecordActivityTaskHeartbeatRequest {
-                task_token: task_token.0,
-                details,
-                identity: self.identity.clone(),
-                namespace: self.namespace.clone(),
-            })
+            .observed(
+                "record_activity_task_heartbeat",
+                self.client.clone().record_activity_task_heartbeat(
+                    self.unary_request(
+                        "record_activity_task_heartbeat",
+                        RecordActivityTaskHeartbeatRequest {
+                            task_token: task_token.into_bytes()?,
+                            details,
+                            identity: self.identity.clone(),
+                            namespace: self.namespace.clone(),
+                        },
+                    )
+                    .await,
+                ),
+            )
             .await?
             .into_inner())
     }
 
+    async fn heartbeat_and_check_cancel(
+        &self,
+        task_token: ActivityTaskToken,
+        details: Option<Payloads>,
+    ) -> Result<bool> {
+        // The server API this is built against has no notion of paused activities, so
+        // cancellation is the only signal a heartbeat response carries.
+        Ok(self
+            .record_activity_heartbeat(task_token, details)
+            .await?
+            .cancel_requested)
+    }
+
+    async fn record_activity_heartbeats(
+        &self,
+        beats: Vec<(ActivityTaskToken, Option<Payloads>)>,
+    ) -> Vec<Result<RecordActivityTaskHeartbeatResponse, HeartbeatError>> {
+        // There's no batch heartbeat RPC (yet), so issue them individually with bounded
+        // concurrency. `buffered` preserves input order.
+        stream::iter(beats)
+            .map(|(task_token, details)| self.record_activity_heartbeat(task_token, details))
+            .buffered(MAX_CONCURRENT_BATCHED_HEARTBEATS)
+            .collect()
+            .await
+    }
+
     async fn cancel_activity_task(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         details: Option<Payloads>,
+        reason: Option<ActivityCancelReason>,
     ) -> Result<RespondActivityTaskCanceledResponse> {
+        self.validate_token_namespace(&task_token.0)?;
+        let details = self.encoded(with_cancel_reason(details, reason))?;
+        let task_processing = self.task_completed(&task_token.0 .0);
+        let caps = self.capabilities_snapshot();
         Ok(self
-            .client
-            .clone()
-            .respond_activity_task_canceled(RespondActivityTaskCanceledRequest {
-                task_token: task_token.0,
-                details,
-                identity: self.identity.clone(),
-                namespace: self.namespace.clone(),
-                worker_version: self.worker_version_stamp(),
-            })
+            .observed_completion(
+                "respond_activity_task_canceled",
+                self.client.clone().respond_activity_task_canceled(
+                    self.unary_request(
+                        "respond_activity_task_canceled",
+                        RespondActivityTaskCanceledRequest {
+                            task_token: task_token.into_bytes()?,
+                            details,
+                            identity: self.identity.clone(),
+                            namespace: self.namespace.clone(),
+                            worker_version: self.worker_version_stamp(&caps),
+                        },
+                    )
+                    .await,
+                ),
+                task_processing,
+            )
             .await?
             .into_inner())
     }
 
     async fn fail_activity_task(
         &self,
-        task_token: TaskToken,
+        task_token: ActivityTaskToken,
         failure: Option<Failure>,
+        last_heartbeat_details: Option<Payloads>,
     ) -> Result<RespondActivityTaskFailedResponse> {
+        self.validate_token_namespace(&task_token.0)?;
+        let last_heartbeat_details = self.encoded(last_heartbeat_details)?;
+        let task_processing = self.task_completed(&task_token.0 .0);
+        let caps = self.capabilities_snapshot();
         Ok(self
-            .client
-            .clone()
-            .respond_activity_task_failed(RespondActivityTaskFailedRequest {
-                task_token: task_token.0,
-                failure,
-                identity: self.identity.clone(),
-                namespace: self.namespace.clone(),
-                // TODO: Implement - https://github.com/temporalio/sdk-core/issues/293
-                last_heartbeat_details: None,
-                worker_version: self.worker_version_stamp(),
-            })
+            .observed_completion(
+                "respond_activity_task_failed",
+                self.client.clone().respond_activity_task_failed(
+                    self.unary_request(
+                        "respond_activity_task_failed",
+                        RespondActivityTaskFailedRequest {
+                            task_token: task_token.into_bytes()?,
+                            failure,
+                            identity: self.identity.clone(),
+                            namespace: self.namespace.clone(),
+                            last_heartbeat_details,
+                            worker_version: self.worker_version_stamp(&caps),
+                        },
+                    )
+                    .await,
+                ),
+                task_processing,
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn complete_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        result: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCompletedByIdResponse> {
+        let result = self.encoded(result)?;
+        Ok(self
+            .observed_completion(
+                "respond_activity_task_completed_by_id",
+                self.client.clone().respond_activity_task_completed_by_id(
+                    self.unary_request(
+                        "respond_activity_task_completed_by_id",
+                        RespondActivityTaskCompletedByIdRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_id,
+                            run_id: run_id.unwrap_or_default(),
+                            activity_id,
+                            result,
+                            identity: self.identity.clone(),
+                        },
+                    )
+                    .await,
+                ),
+                None,
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn fail_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        failure: Option<Failure>,
+        last_heartbeat_details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskFailedByIdResponse> {
+        let last_heartbeat_details = self.encoded(last_heartbeat_details)?;
+        Ok(self
+            .observed_completion(
+                "respond_activity_task_failed_by_id",
+                self.client.clone().respond_activity_task_failed_by_id(
+                    self.unary_request(
+                        "respond_activity_task_failed_by_id",
+                        RespondActivityTaskFailedByIdRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_id,
+                            run_id: run_id.unwrap_or_default(),
+                            activity_id,
+                            failure,
+                            identity: self.identity.clone(),
+                            last_heartbeat_details,
+                        },
+                    )
+                    .await,
+                ),
+                None,
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn cancel_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCanceledByIdResponse> {
+        let details = self.encoded(details)?;
+        Ok(self
+            .observed_completion(
+                "respond_activity_task_canceled_by_id",
+                self.client.clone().respond_activity_task_canceled_by_id(
+                    self.unary_request(
+                        "respond_activity_task_canceled_by_id",
+                        RespondActivityTaskCanceledByIdRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_id,
+                            run_id: run_id.unwrap_or_default(),
+                            activity_id,
+                            details,
+                            identity: self.identity.clone(),
+                        },
+                    )
+                    .await,
+                ),
+                None,
+            )
             .await?
             .into_inner())
     }
 
     async fn fail_workflow_task(
         &self,
-        task_token: TaskToken,
+        task_token: WorkflowTaskToken,
         cause: WorkflowTaskFailedCause,
         failure: Option<Failure>,
+        failing_command_index: Option<usize>,
+        non_determinism: Option<NonDeterminismDetail>,
     ) -> Result<RespondWorkflowTaskFailedResponse> {
+        let caps = self.capabilities_snapshot();
+        let failure = with_failing_command(failure, failing_command_index);
+        self.validate_token_namespace(&task_token.0)?;
+        let task_processing = self.task_completed(&task_token.0 .0);
+        let cause = if non_determinism.is_some() {
+            WorkflowTaskFailedCause::NonDeterministicError
+        } else {
+            cause
+        };
         let request = RespondWorkflowTaskFailedRequest {
-            task_token: task_token.0,
+            task_token: task_token.into_bytes()?,
             cause: cause as i32,
-            failure,
+            failure: with_non_determinism(failure, non_determinism.as_ref()),
             identity: self.identity.clone(),
-            binary_checksum: self.binary_checksum(),
+            binary_checksum: self.binary_checksum(&caps),
             namespace: self.namespace.clone(),
             messages: vec![],
-            worker_version: self.worker_version_stamp(),
+            worker_version: self.worker_version_stamp(&caps),
         };
         Ok(self
-            .client
-            .clone()
-            .respond_workflow_task_failed(request)
+            .limited(
+                self.observed_with(
+                    "respond_workflow_task_failed",
+                    self.client.clone().respond_workflow_task_failed(
+                        self.unary_request("respond_workflow_task_failed", request)
+                            .await,
+                    ),
+                    non_determinism,
+                    task_processing,
+                ),
+            )
             .await?
             .into_inner())
     }
@@ -341,66 +1637,2961 @@ impl WorkerClient for WorkerClientBag {
         workflow_id: String,
         run_id: Option<String>,
         page_token: Vec<u8>,
+        wait_new_event: bool,
+        filter_type: HistoryEventFilterType,
     ) -> Result<GetWorkflowExecutionHistoryResponse> {
-        Ok(self
-            .client
-            .clone()
-            .get_workflow_execution_history(GetWorkflowExecutionHistoryRequest {
-                namespace: self.namespace.clone(),
-                execution: Some(WorkflowExecution {
-                    workflow_id,
-                    run_id: run_id.unwrap_or_default(),
-                }),
-                next_page_token: page_token,
-                ..Default::default()
-            })
+        let req = GetWorkflowExecutionHistoryRequest {
+            namespace: self.namespace.clone(),
+            execution: Some(WorkflowExecution {
+                workflow_id,
+                run_id: run_id.unwrap_or_default(),
+            }),
+            next_page_token: page_token,
+            wait_new_event,
+            history_event_filter_type: filter_type as i32,
+            ..Default::default()
+        };
+        // Waiting for new events is a long poll, and needs the matching deadline
+        let req = if wait_new_event {
+            self.poll_request("get_workflow_execution_history", req, None)
+                .await
+        } else {
+            self.unary_request("get_workflow_execution_history", req)
+                .await
+        };
+        if wait_new_event {
+            let resp = self
+                .observed(
+                    "get_workflow_execution_history",
+                    self.client.clone().get_workflow_execution_history(req),
+                )
+                .await?
+                .into_inner();
+            return self.decoded_history_page(resp);
+        }
+
+        // Concurrent fetches of the same page (ex: by replay tooling and queries) share one RPC.
+        // Every call sharing it is observed, so none go missing if the one which issued the RPC
+        // gives up before it completes.
+        let key = HistoryFetchKey::new(req.get_ref());
+        let mut client = self.client.clone();
+        let fetch = self.history_fetches.join_or_start(key, || {
+            async move {
+                client
+                    .get_workflow_execution_history(req)
+                    .await
+                    .map(|resp| {
+                        let attempts = resp.extensions().get::<RetryAttempts>().copied();
+                        (resp.into_inner(), attempts)
+                    })
+                    .map_err(Arc::new)
+            }
+            .boxed()
+        });
+        let fetch = fetch.map(|res| match res {
+            Ok((resp, attempts)) => {
+                let mut resp = tonic::Response::new(resp);
+                if let Some(attempts) = attempts {
+                    resp.extensions_mut().insert(attempts);
+                }
+                Ok(resp)
+            }
+            Err(status) => Err(tonic::Status::with_details_and_metadata(
+                status.code(),
+                status.message(),
+                status.details().to_vec().into(),
+                status.metadata().clone(),
+            )),
+        });
+        let resp = self
+            .observed("get_workflow_execution_history", fetch)
+            .await?;
+        // Decoded per caller, since the shared response is left as the server sent it
+        self.decoded_history_page(resp.into_inner())
+    }
+
+    async fn get_workflow_execution_history_reverse(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        page_token: Vec<u8>,
+    ) -> Result<GetWorkflowExecutionHistoryReverseResponse> {
+        let req = GetWorkflowExecutionHistoryReverseRequest {
+            namespace: self.namespace.clone(),
+            execution: Some(WorkflowExecution {
+                workflow_id,
+                run_id: run_id.unwrap_or_default(),
+            }),
+            next_page_token: page_token,
+            ..Default::default()
+        };
+        let mut resp = self
+            .observed(
+                "get_workflow_execution_history_reverse",
+                self.client.clone().get_workflow_execution_history_reverse(
+                    self.unary_request("get_workflow_execution_history_reverse", req)
+                        .await,
+                ),
+            )
             .await?
-            .into_inner())
+            .into_inner();
+        if let (Some(codec), Some(history)) = (&self.payload_codec, resp.history.as_mut()) {
+            codec::decode_history(codec.as_ref(), history).map_err(codec_failed)?;
+        }
+        Ok(resp)
     }
 
     async fn respond_legacy_query(
         &self,
-        task_token: TaskToken,
+        task_token: WorkflowTaskToken,
         query_result: QueryResult,
     ) -> Result<RespondQueryTaskCompletedResponse> {
         let (_, completed_type, query_result, error_message) = query_result.into_components();
+        let query_result = self.encoded(query_result)?;
         Ok(self
-            .client
-            .clone()
-            .respond_query_task_completed(RespondQueryTaskCompletedRequest {
-                task_token: task_token.into(),
-                completed_type: completed_type as i32,
-                query_result,
-                error_message,
-                namespace: self.namespace.clone(),
-            })
+            .observed(
+                "respond_query_task_completed",
+                self.client.clone().respond_query_task_completed(
+                    self.unary_request(
+                        "respond_query_task_completed",
+                        RespondQueryTaskCompletedRequest {
+                            task_token: task_token.into_bytes()?,
+                            completed_type: completed_type as i32,
+                            query_result,
+                            error_message,
+                            namespace: self.namespace.clone(),
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn respond_legacy_queries(
+        &self,
+        responses: Vec<(WorkflowTaskToken, QueryResult)>,
+    ) -> Vec<Result<RespondQueryTaskCompletedResponse>> {
+        // Each legacy query is answered by its own RPC, so issue them individually with bounded
+        // concurrency. `buffered` preserves input order.
+        stream::iter(responses)
+            .map(|(task_token, query_result)| self.respond_legacy_query(task_token, query_result))
+            .buffered(MAX_CONCURRENT_BATCHED_QUERY_RESPONSES)
+            .collect()
+            .await
+    }
+
+    async fn describe_task_queue(
+        &self,
+        task_queue: String,
+        kind: TaskQueueKind,
+    ) -> Result<DescribeTaskQueueResponse> {
+        Ok(self
+            .observed(
+                "describe_task_queue",
+                self.client.clone().describe_task_queue(
+                    self.unary_request(
+                        "describe_task_queue",
+                        describe_task_queue_request(self.namespace.clone(), task_queue, kind),
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn get_worker_build_id_compatibility(
+        &self,
+        task_queue: String,
+    ) -> Result<GetWorkerBuildIdCompatibilityResponse> {
+        Ok(self
+            .observed(
+                "get_worker_build_id_compatibility",
+                self.client.clone().get_worker_build_id_compatibility(
+                    self.unary_request(
+                        "get_worker_build_id_compatibility",
+                        build_id_compatibility_request(self.namespace.clone(), task_queue),
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn update_worker_build_id_compatibility(
+        &self,
+        task_queue: String,
+        operation: BuildIdCompatibilityOperation,
+    ) -> Result<UpdateWorkerBuildIdCompatibilityResponse> {
+        Ok(self
+            .observed(
+                "update_worker_build_id_compatibility",
+                self.client.clone().update_worker_build_id_compatibility(
+                    self.unary_request(
+                        "update_worker_build_id_compatibility",
+                        UpdateWorkerBuildIdCompatibilityRequest {
+                            namespace: self.namespace.clone(),
+                            task_queue,
+                            operation: Some(operation.into()),
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn reset_sticky_task_queue(
+        &self,
+        execution: WorkflowExecution,
+    ) -> Result<ResetStickyTaskQueueResponse> {
+        Ok(self
+            .observed(
+                "reset_sticky_task_queue",
+                self.client.clone().reset_sticky_task_queue(
+                    self.unary_request(
+                        "reset_sticky_task_queue",
+                        ResetStickyTaskQueueRequest {
+                            namespace: self.namespace.clone(),
+                            execution: Some(execution),
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn disclaim_sticky(&self, execution: WorkflowExecution) -> Result<()> {
+        self.reset_sticky_task_queue(execution).await.map(|_| ())
+    }
+
+    async fn delete_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+    ) -> Result<DeleteWorkflowExecutionResponse> {
+        Ok(self
+            .observed(
+                "delete_workflow_execution",
+                self.client.clone().delete_workflow_execution(
+                    self.unary_request(
+                        "delete_workflow_execution",
+                        DeleteWorkflowExecutionRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_execution: Some(execution),
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn terminate_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        reason: String,
+        details: Option<Payloads>,
+    ) -> Result<TerminateWorkflowExecutionResponse> {
+        let details = self.encoded(details)?;
+        Ok(self
+            .observed(
+                "terminate_workflow_execution",
+                self.client.clone().terminate_workflow_execution(
+                    self.unary_request(
+                        "terminate_workflow_execution",
+                        TerminateWorkflowExecutionRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_execution: Some(execution),
+                            reason,
+                            details,
+                            identity: self.identity.clone(),
+                            first_execution_run_id: "".to_string(),
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn reset_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        reset_to_event_id: i64,
+        reason: String,
+        reset_reapply_type: ResetReapplyType,
+    ) -> Result<ResetWorkflowExecutionResponse> {
+        Ok(self
+            .observed(
+                "reset_workflow_execution",
+                self.client.clone().reset_workflow_execution(
+                    self.unary_request(
+                        "reset_workflow_execution",
+                        ResetWorkflowExecutionRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_execution: Some(execution),
+                            reason,
+                            workflow_task_finish_event_id: reset_to_event_id,
+                            request_id: uuid::Uuid::new_v4().to_string(),
+                            reset_reapply_type: reset_reapply_type as i32,
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn signal_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        signal_name: String,
+        input: Option<Payloads>,
+    ) -> Result<SignalWorkflowExecutionResponse> {
+        let input = self.encoded(input)?;
+        Ok(self
+            .observed(
+                "signal_workflow_execution",
+                self.client.clone().signal_workflow_execution(
+                    self.unary_request(
+                        "signal_workflow_execution",
+                        SignalWorkflowExecutionRequest {
+                            namespace: self.namespace.clone(),
+                            workflow_execution: Some(execution),
+                            signal_name,
+                            input,
+                            identity: self.identity.clone(),
+                            request_id: uuid::Uuid::new_v4().to_string(),
+                            ..Default::default()
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn count_workflow_executions(
+        &self,
+        query: String,
+    ) -> Result<CountWorkflowExecutionsResponse> {
+        Ok(self
+            .observed(
+                "count_workflow_executions",
+                self.client.clone().count_workflow_executions(
+                    self.unary_request(
+                        "count_workflow_executions",
+                        CountWorkflowExecutionsRequest {
+                            namespace: self.namespace.clone(),
+                            query,
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await?
+            .into_inner())
+    }
+
+    async fn list_open_workflow_executions(
+        &self,
+        start_time_filter: Option<StartTimeFilter>,
+        type_filter: Option<WorkflowTypeFilter>,
+        page_token: Vec<u8>,
+    ) -> Result<ListOpenWorkflowExecutionsResponse> {
+        Ok(self
+            .observed(
+                "list_open_workflow_executions",
+                self.client.clone().list_open_workflow_executions(
+                    self.unary_request(
+                        "list_open_workflow_executions",
+                        ListOpenWorkflowExecutionsRequest {
+                            namespace: self.namespace.clone(),
+                            next_page_token: page_token,
+                            start_time_filter,
+                            filters: type_filter.map(ListOpenFilters::TypeFilter),
+                            ..Default::default()
+                        },
+                    )
+                    .await,
+                ),
+            )
             .await?
             .into_inner())
     }
 
-    fn capabilities(&self) -> Option<&Capabilities> {
-        self.client.get_client().inner().capabilities()
+    async fn check_health(&self) -> Result<HealthStatus> {
+        let res = self
+            .observed(
+                "describe_namespace",
+                self.client.clone().describe_namespace(
+                    self.unary_request(
+                        "describe_namespace",
+                        DescribeNamespaceRequest {
+                            namespace: self.namespace.clone(),
+                            ..Default::default()
+                        },
+                    )
+                    .await,
+                ),
+            )
+            .await;
+        if let Some(info) = res
+            .as_ref()
+            .ok()
+            .and_then(|r| r.get_ref().namespace_info.as_ref())
+        {
+            self.namespace_id.learn(&info.id);
+        }
+        health_status(res)
+    }
+
+    async fn refresh_capabilities(&self) -> Result<Capabilities> {
+        let caps = self
+            .observed(
+                "get_system_info",
+                self.client.clone().get_system_info(
+                    self.unary_request("get_system_info", GetSystemInfoRequest::default())
+                        .await,
+                ),
+            )
+            .await?
+            .into_inner()
+            .capabilities
+            .unwrap_or_default();
+        *self.capabilities.write() = Some(caps.clone());
+        Ok(caps)
+    }
+
+    async fn warmup(&self) -> Result<()> {
+        // The client connects and fetches capabilities when created, but the channel may since
+        // have been dropped, and the capabilities fetch may have failed. A capabilities fetch
+        // covers both.
+        self.refresh_capabilities().await.map(|_| ())
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities.read().clone()
+    }
+
+    fn capabilities_snapshot(&self) -> Capabilities {
+        self.capabilities().unwrap_or_default()
+    }
+
+    fn observed_server_time(&self) -> Option<SystemTime> {
+        self.server_time.get()
+    }
+
+    fn completion_backpressure(&self) -> f64 {
+        self.completion_limiter
+            .as_ref()
+            .map_or(0.0, |l| l.saturation())
+    }
+
+    fn connection_state(&self) -> ConnectionState {
+        self.reachability.state()
     }
 }
 
-/// A version of [RespondWorkflowTaskCompletedRequest] that will finish being filled out by the
-/// server client
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) struct WorkflowTaskCompletion {
-    /// The task token that would've been received from polling for a workflow activation
-    pub task_token: TaskToken,
-    /// A list of new commands to send to the server, such as starting a timer.
-    pub commands: Vec<Command>,
-    /// If set, indicate that next task should be queued on sticky queue with given attributes.
-    pub sticky_attributes: Option<StickyExecutionAttributes>,
-    /// Responses to queries in the `queries` field of the workflow task.
-    pub query_responses: Vec<QueryResult>,
-    /// Indicate that the task completion should return a new WFT if one is available
-    pub return_new_workflow_task: bool,
-    /// Force a new WFT to be created after this completion
-    pub force_create_new_workflow_task: bool,
-    /// SDK-specific metadata to send
-    pub sdk_metadata: WorkflowTaskCompletedMetadata,
+/// The latest of the server timestamps seen by a client, see
+/// [WorkerClient::observed_server_time]
+#[derive(Debug, Default)]
+struct LatestServerTime(Mutex<Option<SystemTime>>);
+
+impl LatestServerTime {
+    /// Records the timestamp if it's later than any seen so far. Responses may arrive out of
+    /// order, so an earlier timestamp doesn't mean the server's clock went backwards.
+    fn observe(&self, ts: Option<prost_types::Timestamp>) {
+        let ts: Option<SystemTime> = ts.try_into_or_none();
+        if let Some(ts) = ts {
+            let mut latest = self.0.lock();
+            if latest.map_or(true, |l| ts > l) {
+                *latest = Some(ts);
+            }
+        }
+    }
+
+    fn get(&self) -> Option<SystemTime> {
+        *self.0.lock()
+    }
+}
+
+/// When a client's RPCs last did and didn't reach the server, see [WorkerClient::connection_state]
+#[derive(Debug, Default)]
+struct ServerReachability(Mutex<Reachability>);
+
+#[derive(Debug, Default)]
+struct Reachability {
+    last_success: Option<Instant>,
+    last_failure: Option<Instant>,
+}
+
+impl ServerReachability {
+    fn record(&self, code: tonic::Code, at: Instant) {
+        let mut reachability = self.0.lock();
+        match code {
+            tonic::Code::Unavailable => reachability.last_failure = Some(at),
+            tonic::Code::Cancelled | tonic::Code::DeadlineExceeded => {}
+            _ => reachability.last_success = Some(at),
+        }
+    }
+
+    fn state(&self) -> ConnectionState {
+        let reachability = self.0.lock();
+        match (reachability.last_success, reachability.last_failure) {
+            (None, None) => ConnectionState::Connecting,
+            (Some(last_success), None) => ConnectionState::Ready { last_success },
+            (Some(last_success), Some(last_failure)) if last_success >= last_failure => {
+                ConnectionState::Ready { last_success }
+            }
+            (last_success, Some(last_failure)) => ConnectionState::TransientFailure {
+                last_failure,
+                last_success,
+            },
+        }
+    }
+}
+
+/// The id of a client's namespace, which task tokens identify their namespace by. Learned from the
+/// first response revealing it, see [WorkerClientBag::validate_token_namespace].
+#[derive(Debug, Default)]
+struct NamespaceId(Mutex<Option<String>>);
+
+impl NamespaceId {
+    fn learn(&self, id: &str) {
+        let mut known = self.0.lock();
+        if known.is_none() && !id.is_empty() {
+            *known = Some(id.to_string());
+        }
+    }
+
+    /// Polls only hand out tasks from the client's own namespace, so their tokens reveal its id
+    fn learn_from_token(&self, task_token: &[u8]) {
+        if let Some(id) = token_namespace_id(task_token) {
+            self.learn(&id);
+        }
+    }
+
+    fn validate(&self, namespace: &str, token: &TaskToken) -> Result<(), TokenNamespaceMismatch> {
+        let known = self.0.lock();
+        match (known.as_ref(), token_namespace_id(&token.0)) {
+            (Some(namespace_id), Some(token_namespace_id))
+                if *namespace_id != token_namespace_id =>
+            {
+                Err(TokenNamespaceMismatch {
+                    namespace: namespace.to_string(),
+                    namespace_id: namespace_id.clone(),
+                    token_namespace_id,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The leading field of the server's (protobuf) task token encoding, which is all we need of it
+#[derive(Clone, PartialEq, prost::Message)]
+struct TaskTokenNamespace {
+    #[prost(string, tag = "1")]
+    namespace_id: String,
+}
+
+/// The id of the namespace which issued a task token, if it decodes as one the server issued
+fn token_namespace_id(task_token: &[u8]) -> Option<String> {
+    <TaskTokenNamespace as prost::Message>::decode(task_token)
+        .ok()
+        .map(|t| t.namespace_id)
+        .filter(|id| !id.is_empty())
+}
+
+/// How many outstanding tasks a client tracks the receipt of. Activities may run for a very long
+/// time, so this keeps workers which never complete theirs from growing without bound.
+const MAX_TASK_RECEIPTS: usize = 10_000;
+
+/// When each task handed out by a client's polls was received, so that how long it took to process
+/// can be reported once it's completed
+#[derive(Debug, Default)]
+struct TaskReceipts(Mutex<HashMap<Vec<u8>, TaskReceipt>>);
+
+#[derive(Debug)]
+struct TaskReceipt {
+    task_queue: String,
+    received: Instant,
+    /// After this the task has timed out, so it can no longer be completed
+    expires: Instant,
+}
+
+impl TaskReceipts {
+    /// Records that a task was just received, if the poll returned one. Tasks which never get
+    /// completed (ex: they time out while being processed) are forgotten once `timeout` passes.
+    /// At most [MAX_TASK_RECEIPTS] tasks are tracked, those received beyond that aren't.
+    fn received(&self, task_token: &[u8], task_queue: String, timeout: Duration) {
+        if task_token.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut receipts = self.0.lock();
+        receipts.retain(|_, r| r.expires > now);
+        if receipts.len() >= MAX_TASK_RECEIPTS {
+            return;
+        }
+        receipts.insert(
+            task_token.to_vec(),
+            TaskReceipt {
+                task_queue,
+                received: now,
+                expires: now + timeout,
+            },
+        );
+    }
+
+    /// Forgets the task, returning how long it took to process if it was received by this client
+    fn completed(&self, task_token: &[u8]) -> Option<TaskProcessingLatency> {
+        self.0
+            .lock()
+            .remove(task_token)
+            .map(|r| TaskProcessingLatency {
+                task_queue: r.task_queue,
+                latency: r.received.elapsed(),
+            })
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.0.lock().len()
+    }
+}
+
+/// Identifies a page of history, so that concurrent fetches of it can share one RPC
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct HistoryFetchKey {
+    workflow_id: String,
+    run_id: String,
+    page_token: Vec<u8>,
+    /// Fetches of only the close event return a different response for the same page
+    filter_type: i32,
+}
+
+impl HistoryFetchKey {
+    fn new(req: &GetWorkflowExecutionHistoryRequest) -> Self {
+        let execution = req.execution.clone().unwrap_or_default();
+        Self {
+            workflow_id: execution.workflow_id,
+            run_id: execution.run_id,
+            page_token: req.next_page_token.clone(),
+            filter_type: req.history_event_filter_type,
+        }
+    }
+}
+
+/// The result of a history fetch, in a form every call sharing the fetch can get a copy of
+type SharedHistoryFetchResult =
+    Result<(GetWorkflowExecutionHistoryResponse, Option<RetryAttempts>), Arc<tonic::Status>>;
+
+/// Requests which are in flight, so that identical requests made concurrently can await the same
+/// one rather than each issuing their own. A request is forgotten once it completes, or once every
+/// caller awaiting it has given up, so later calls always get a fresh result. Only the callers keep
+/// a request alive, each entry is just a weak reference tagged with the request's id.
+struct InFlightRequests<K, T: Clone>(Arc<InFlightMap<K, T>>);
+
+type InFlightMap<K, T> = Mutex<HashMap<K, (usize, WeakShared<BoxFuture<'static, T>>)>>;
+
+/// Source of the ids which tell a request's entry apart from a later one for the same key
+static NEXT_IN_FLIGHT_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl<K, T: Clone> Default for InFlightRequests<K, T> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(HashMap::new())))
+    }
+}
+
+impl<K, T> InFlightRequests<K, T>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    /// Returns the request in flight for `key`, or starts one with `start` if there isn't any
+    fn join_or_start(
+        &self,
+        key: K,
+        start: impl FnOnce() -> BoxFuture<'static, T>,
+    ) -> Shared<BoxFuture<'static, T>> {
+        let mut in_flight = self.0.lock();
+        if let Some(req) = in_flight.get(&key).and_then(|(_, req)| req.upgrade()) {
+            return req;
+        }
+        let id = NEXT_IN_FLIGHT_ID.fetch_add(1, Ordering::Relaxed);
+        let forget = ForgetOnDrop {
+            requests: Arc::downgrade(&self.0),
+            key: key.clone(),
+            id,
+        };
+        let req = start();
+        let req = async move {
+            // Dropped along with the request, whether it completed or every caller gave up on it
+            let _forget = forget;
+            req.await
+        }
+        .boxed()
+        .shared();
+        if let Some(weak) = req.downgrade() {
+            in_flight.insert(key, (id, weak));
+        }
+        req
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.0.lock().len()
+    }
+}
+
+/// Owned by a request started by [InFlightRequests::join_or_start], removes its entry when dropped
+struct ForgetOnDrop<K: Hash + Eq, T> {
+    requests: Weak<InFlightMap<K, T>>,
+    key: K,
+    id: usize,
+}
+
+impl<K: Hash + Eq, T> Drop for ForgetOnDrop<K, T> {
+    fn drop(&mut self) {
+        if let Some(requests) = self.requests.upgrade() {
+            let mut requests = requests.lock();
+            // A later request for the key may have replaced this one's entry already
+            if matches!(requests.get(&self.key), Some((id, _)) if *id == self.id) {
+                requests.remove(&self.key);
+            }
+        }
+    }
+}
+
+fn suffixed_identity(identity: &str, suffix: &str) -> String {
+    if suffix.is_empty() {
+        identity.to_string()
+    } else {
+        format!("{identity}/{suffix}")
+    }
+}
+
+/// The name of the normal task queue a polled queue belongs to
+fn normal_queue_name(task_queue: &TaskQueue) -> String {
+    if task_queue.kind == TaskQueueKind::Sticky as i32 {
+        task_queue.normal_name.clone()
+    } else {
+        task_queue.name.clone()
+    }
+}
+
+/// The worker's build id and versioning settings, which together with the server's capabilities
+/// determine how it identifies itself in polls and completions
+#[derive(Debug, Clone)]
+struct WorkerVersioning {
+    build_id: String,
+    /// If set, always sent as the binary checksum. See [WorkerVersioning::binary_checksum].
+    binary_checksum_override: Option<String>,
+    use_versioning: bool,
+}
+
+impl WorkerVersioning {
+    /// The binary checksum sent along with polls and completions, chosen in this order:
+    /// 1. The configured override, if any
+    /// 2. Empty, if the server supports build-id based versioning (the build id is sent in the
+    ///    version stamp / capabilities instead)
+    /// 3. The worker's build id
+    fn binary_checksum(&self, caps: &Capabilities) -> String {
+        if let Some(cs) = &self.binary_checksum_override {
+            cs.clone()
+        } else if caps.build_id_based_versioning {
+            "".to_string()
+        } else {
+            self.build_id.clone()
+        }
+    }
+
+    fn capabilities(&self, caps: &Capabilities) -> Option<WorkerVersionCapabilities> {
+        if caps.build_id_based_versioning {
+            Some(WorkerVersionCapabilities {
+                build_id: self.build_id.clone(),
+                use_versioning: self.use_versioning,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn stamp(&self, caps: &Capabilities) -> Option<WorkerVersionStamp> {
+        if caps.build_id_based_versioning {
+            Some(WorkerVersionStamp {
+                build_id: self.build_id.clone(),
+                bundle_id: "".to_string(),
+                use_versioning: self.use_versioning,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Counts polls so that a bag can periodically re-fetch server capabilities. Capabilities are
+/// otherwise only learned when connecting, so a worker polling a server which is upgraded mid-run
+/// would never start using (for example) build-id based versioning.
+#[derive(Debug)]
+struct CapabilitiesRefreshTrigger {
+    every_n_polls: usize,
+    polls: AtomicUsize,
+}
+
+impl CapabilitiesRefreshTrigger {
+    fn new(every_n_polls: usize) -> Self {
+        Self {
+            every_n_polls: every_n_polls.max(1),
+            polls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records that a poll is about to be issued, returning true if capabilities should be
+    /// refreshed first
+    fn record_poll(&self) -> bool {
+        (self.polls.fetch_add(1, Ordering::Relaxed) + 1) % self.every_n_polls == 0
+    }
+}
+
+/// Tracks consecutive overload failures of poll RPCs, see [CircuitBreakerConfig]
+struct PollCircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: usize,
+    open_until: Option<Instant>,
+}
+
+impl PollCircuitBreaker {
+    fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Returns [PollError::CircuitOpen] if polls should not currently be issued
+    fn check(&self, now: Instant) -> Result<(), PollError> {
+        let mut state = self.state.lock();
+        match state.open_until {
+            Some(until) if now < until => Err(PollError::CircuitOpen),
+            Some(_) => {
+                // Cooldown is over. Let a poll through - the failure count is left as-is, so if
+                // this one fails too the breaker reopens immediately.
+                state.open_until = None;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record<T>(&self, res: &Result<T, PollError>, now: Instant) {
+        let mut state = self.state.lock();
+        match res {
+            Err(PollError::TonicError(s))
+                if matches!(
+                    s.code(),
+                    tonic::Code::ResourceExhausted | tonic::Code::Unavailable
+                ) =>
+            {
+                state.consecutive_failures += 1;
+                if state.consecutive_failures >= self.config.failure_threshold {
+                    state.open_until = Some(now + self.config.cooldown);
+                }
+            }
+            // Neither of these tell us anything about the server's health
+            Err(PollError::PollCancelled | PollError::CircuitOpen) => {}
+            _ => state.consecutive_failures = 0,
+        }
+    }
+}
+
+/// Appends a payload recording why core asked lang to cancel an activity (if it did) to the
+/// details its cancellation is reported with, marked with [ACTIVITY_CANCEL_REASON_KEY]. It's
+/// appended before the details are encoded, so a payload codec sees it like any other detail.
+fn with_cancel_reason(
+    details: Option<Payloads>,
+    reason: Option<ActivityCancelReason>,
+) -> Option<Payloads> {
+    let mut reason = match reason.map(|r| r.as_str_name().as_json_payload()) {
+        Some(Ok(p)) => p,
+        _ => return details,
+    };
+    reason
+        .metadata
+        .insert(ACTIVITY_CANCEL_REASON_KEY.to_string(), vec![]);
+    let mut details = details.unwrap_or_default();
+    details.payloads.push(reason);
+    Some(details)
+}
+
+/// Notes which command caused a workflow task failure in the failure's message, and records it
+/// in the failure's details under [FAILING_COMMAND_INDEX_KEY]
+fn with_failing_command(failure: Option<Failure>, index: Option<usize>) -> Option<Failure> {
+    match index {
+        Some(i) => Some(with_recorded(
+            with_note(failure, format!("failing command index: {i}")),
+            FAILING_COMMAND_INDEX_KEY,
+            i.as_json_payload(),
+        )),
+        None => failure,
+    }
+}
+
+/// Notes the non-determinism which caused a workflow task failure, if any, in the failure's
+/// message, and records it in the failure's details under [NON_DETERMINISM_DETAIL_KEY]
+fn with_non_determinism(
+    failure: Option<Failure>,
+    detail: Option<&NonDeterminismDetail>,
+) -> Option<Failure> {
+    match detail {
+        Some(d) => Some(with_recorded(
+            with_note(
+                failure,
+                format!(
+                    "Nondeterminism at event {}: expected {}, but workflow did {}",
+                    d.event_id, d.expected, d.actual
+                ),
+            ),
+            NON_DETERMINISM_DETAIL_KEY,
+            d.as_json_payload(),
+        )),
+        None => failure,
+    }
+}
+
+/// Adds context core has about a failure to its message, creating the failure if needed
+fn with_note(failure: Option<Failure>, note: String) -> Failure {
+    let mut failure = failure.unwrap_or_default();
+    failure.message = if failure.message.is_empty() {
+        note
+    } else {
+        format!("{} ({note})", failure.message)
+    };
+    failure
+}
+
+/// Appends context core has about a failure to its application failure details, marked with
+/// `key`, so it can be read back without parsing the message. A failure with no info becomes an
+/// application failure. Other kinds of failure are left alone, since their info belongs to
+/// whoever raised them.
+fn with_recorded(mut failure: Failure, key: &str, payload: anyhow::Result<Payload>) -> Failure {
+    let info = failure
+        .failure_info
+        .get_or_insert_with(|| FailureInfo::ApplicationFailureInfo(Default::default()));
+    if let (FailureInfo::ApplicationFailureInfo(info), Ok(mut payload)) = (info, payload) {
+        payload.metadata.insert(key.to_string(), vec![]);
+        info.details
+            .get_or_insert_with(Default::default)
+            .payloads
+            .push(payload);
+    }
+    failure
+}
+
+fn check_heartbeat_details_size(
+    details: Option<&Payloads>,
+    limit: usize,
+) -> Result<(), HeartbeatError> {
+    let size = details.map_or(0, prost::Message::encoded_len);
+    if size > limit {
+        return Err(HeartbeatError::HeartbeatDetailsTooLarge { size, limit });
+    }
+    Ok(())
+}
+
+/// Sticky queues are only meaningful alongside the normal queue they stand in for
+fn validate_task_queue_kind(task_queue: &TaskQueue) -> Result<(), tonic::Status> {
+    if task_queue.kind == TaskQueueKind::Sticky as i32 && task_queue.normal_name.is_empty() {
+        return Err(tonic::Status::invalid_argument(format!(
+            "Sticky task queue `{}` must name its normal queue",
+            task_queue.name
+        )));
+    }
+    Ok(())
+}
+
+fn codec_failed(e: PayloadCodecError) -> tonic::Status {
+    tonic::Status::internal(e.to_string())
+}
+
+/// Adds the client name and version headers, if set, to a worker's static headers. Headers set
+/// per-request take precedence over the client's own, so these replace the name and version the
+/// client was connected with.
+fn with_client_identity(
+    mut headers: HashMap<String, String>,
+    client_name: Option<String>,
+    client_version: Option<String>,
+) -> HashMap<String, String> {
+    if let Some(name) = client_name {
+        headers.insert(CLIENT_NAME_HEADER_KEY.to_string(), name);
+    }
+    if let Some(version) = client_version {
+        headers.insert(CLIENT_VERSION_HEADER_KEY.to_string(), version);
+    }
+    headers
+}
+
+/// Validates user-supplied headers as ASCII gRPC metadata
+fn parse_headers(
+    headers: HashMap<String, String>,
+) -> Result<Vec<(AsciiMetadataKey, AsciiMetadataValue)>, InvalidHeaderError> {
+    headers
+        .into_iter()
+        .map(|(k, v)| {
+            let key = AsciiMetadataKey::from_str(&k).map_err(|_| InvalidHeaderError {
+                key: k.clone(),
+                reason: "not a valid ASCII metadata key",
+            })?;
+            let val = AsciiMetadataValue::try_from(v.as_str()).map_err(|_| InvalidHeaderError {
+                key: k.clone(),
+                reason: "value is not valid ASCII metadata",
+            })?;
+            Ok((key, val))
+        })
+        .collect()
+}
+
+/// Runs each interceptor, in the order they were configured, against a request about to be sent
+async fn intercept<T>(
+    interceptors: &[Arc<dyn RpcInterceptor>],
+    method: &'static str,
+    req: &mut tonic::Request<T>,
+) {
+    for interceptor in interceptors {
+        interceptor
+            .before(&mut RpcContext::new(method, req.metadata_mut()))
+            .await;
+    }
+}
+
+/// Fetches the whole history of a workflow, following page tokens as needed, and yields its
+/// events one at a time. The stream ends after the last page, or after yielding an RPC error.
+#[allow(dead_code)] // The worker pages through history with HistoryPaginator instead
+pub(crate) fn stream_workflow_execution_history(
+    client: Arc<dyn WorkerClient>,
+    workflow_id: String,
+    run_id: Option<String>,
+) -> impl Stream<Item = Result<HistoryEvent>> {
+    // State is the token for the next page to fetch, or None once there are no more pages
+    stream::unfold(Some(vec![]), move |page_token| {
+        let client = client.clone();
+        let workflow_id = workflow_id.clone();
+        let run_id = run_id.clone();
+        async move {
+            let page_token = page_token?;
+            match client
+                .get_workflow_execution_history(
+                    workflow_id,
+                    run_id,
+                    page_token,
+                    false,
+                    HistoryEventFilterType::AllEvent,
+                )
+                .await
+            {
+                Ok(resp) => {
+                    let events = resp.history.map(|h| h.events).unwrap_or_default();
+                    let next = Some(resp.next_page_token).filter(|t| !t.is_empty());
+                    Some((stream::iter(events.into_iter().map(Ok)).left_stream(), next))
+                }
+                Err(e) => Some((stream::once(async { Err(e) }).right_stream(), None)),
+            }
+        }
+    })
+    .flatten()
+}
+
+/// Fetches a single event from a workflow's history, or None if the history has no event with
+/// that id.
+///
+/// The most recent page of history is fetched first (in reverse), since the events worth looking
+/// at individually, like a failed workflow task, are usually near the end. It also tells us
+/// whether the event exists at all. If the event is older than that page, history is paged
+/// through from the start, stopping as soon as the event is reached.
+#[allow(dead_code)] // The worker only ever needs whole histories
+pub(crate) async fn get_history_event(
+    client: Arc<dyn WorkerClient>,
+    workflow_id: String,
+    run_id: Option<String>,
+    event_id: i64,
+) -> Result<Option<HistoryEvent>> {
+    if event_id <= 0 {
+        return Ok(None);
+    }
+    let latest = client
+        .get_workflow_execution_history_reverse(workflow_id.clone(), run_id.clone(), vec![])
+        .await?
+        .history
+        .map(|h| h.events)
+        .unwrap_or_default();
+    match latest.iter().map(|e| e.event_id).min() {
+        // Event ids are contiguous, so anything not older than the page must be in it
+        Some(oldest) if oldest <= event_id => {
+            return Ok(latest.into_iter().find(|e| e.event_id == event_id));
+        }
+        None => return Ok(None),
+        _ => {}
+    }
+
+    let events = stream_workflow_execution_history(client, workflow_id, run_id);
+    futures::pin_mut!(events);
+    while let Some(event) = events.next().await {
+        let event = event?;
+        if event.event_id == event_id {
+            return Ok(Some(event));
+        }
+        if event.event_id > event_id {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Lists all open workflow executions in the worker's namespace matching the filters, following
+/// page tokens as needed, and yields them one at a time. The stream ends after the last page, or
+/// after yielding an RPC error.
+#[allow(dead_code)] // The worker only deals with executions it has tasks for
+pub(crate) fn stream_open_workflow_executions(
+    client: Arc<dyn WorkerClient>,
+    start_time_filter: Option<StartTimeFilter>,
+    type_filter: Option<WorkflowTypeFilter>,
+) -> impl Stream<Item = Result<WorkflowExecutionInfo>> {
+    // State is the token for the next page to fetch, or None once there are no more pages
+    stream::unfold(Some(vec![]), move |page_token| {
+        let client = client.clone();
+        let start_time_filter = start_time_filter.clone();
+        let type_filter = type_filter.clone();
+        async move {
+            let page_token = page_token?;
+            match client
+                .list_open_workflow_executions(start_time_filter, type_filter, page_token)
+                .await
+            {
+                Ok(resp) => {
+                    let next = Some(resp.next_page_token).filter(|t| !t.is_empty());
+                    Some((
+                        stream::iter(resp.executions.into_iter().map(Ok)).left_stream(),
+                        next,
+                    ))
+                }
+                Err(e) => Some((stream::once(async { Err(e) }).right_stream(), None)),
+            }
+        }
+    })
+    .flatten()
+}
+
+/// A polled activity task, with what activity implementations usually need from it pulled out
+/// and its input decoded. See [poll_activity_task_decoded].
+#[derive(Debug)]
+pub(crate) struct DecodedActivityTask {
+    pub task_token: ActivityTaskToken,
+    pub activity_type: String,
+    pub activity_id: String,
+    pub input: Vec<Payload>,
+    /// If set, the activity times out if it goes this long without heartbeating
+    pub heartbeat_timeout: Option<Duration>,
+    pub start_to_close_timeout: Option<Duration>,
+    pub schedule_to_close_timeout: Option<Duration>,
+}
+
+/// Polls for an activity task and, if one was handed out, decodes its input with `codec` (if
+/// any). Returns None if the poll came back without a task. Use
+/// [WorkerClient::poll_activity_task] directly to set the poll's other options or to get the
+/// whole response. Clients configured with a payload codec of their own already decode the tasks
+/// they poll, so pass no `codec` for those.
+#[allow(dead_code)] // The worker hands activity tasks to lang without decoding them first
+pub(crate) async fn poll_activity_task_decoded(
+    client: &dyn WorkerClient,
+    task_queue: String,
+    codec: Option<&dyn PayloadCodec>,
+    cancel: CancellationToken,
+) -> Result<Option<DecodedActivityTask>, PollError> {
+    let resp = client
+        .poll_activity_task(task_queue, None, None, None, cancel)
+        .await?;
+    let mut resp = match PollOutcome::from(resp) {
+        PollOutcome::Task(t) => *t,
+        PollOutcome::Empty => return Ok(None),
+    };
+    if let Some(codec) = codec {
+        codec::decode_activity_task(codec, &mut resp).map_err(codec_failed)?;
+    }
+    Ok(Some(DecodedActivityTask {
+        task_token: ActivityTaskToken(TaskToken(resp.task_token)),
+        activity_type: resp.activity_type.map(|t| t.name).unwrap_or_default(),
+        activity_id: resp.activity_id,
+        input: resp.input.map(|i| i.payloads).unwrap_or_default(),
+        heartbeat_timeout: resp.heartbeat_timeout.try_into_or_none(),
+        start_to_close_timeout: resp.start_to_close_timeout.try_into_or_none(),
+        schedule_to_close_timeout: resp.schedule_to_close_timeout.try_into_or_none(),
+    }))
+}
+
+/// Polls several workflow task queues at once, returning the first task any of them hands out
+/// along with the queue it came from. Each queue has its own poll in flight, and a poll which
+/// comes back empty is reissued, so this only returns once there's a task (or a poll fails, or
+/// `cancel` is cancelled). The remaining polls are cancelled before returning.
+///
+/// This is meant for consolidating low-traffic queues onto fewer pollers, and isn't fair between
+/// queues:
+/// * When several queues have tasks, whichever poll the server answers first wins. A queue which
+///   always has tasks waiting can be picked over the others on every call.
+/// * The server may already have handed a task to a poll by the time it's cancelled. Such tasks
+///   aren't lost, but aren't retried until they time out either.
+/// * The server sees one poller per queue for every call in flight, which counts towards the
+///   pollers it reports for each queue.
+#[allow(dead_code)] // Workers still run one poller per queue
+pub(crate) async fn poll_workflow_task_multi(
+    client: &dyn WorkerClient,
+    queues: &[TaskQueue],
+    cancel: CancellationToken,
+) -> Result<(TaskQueue, PollWorkflowTaskQueueResponse), PollError> {
+    if queues.is_empty() {
+        return Err(
+            tonic::Status::invalid_argument("At least one task queue must be polled").into(),
+        );
+    }
+    let polls_cancel = cancel.child_token();
+    // Cancels whichever polls are still in flight however we return
+    let _cancel_remaining = polls_cancel.clone().drop_guard();
+    let poll = |task_queue: TaskQueue| {
+        let cancel = polls_cancel.clone();
+        async move {
+            let res = client
+                .poll_workflow_task(task_queue.clone(), None, cancel)
+                .await;
+            (task_queue, res)
+        }
+    };
+    let mut polls: FuturesUnordered<_> = queues.iter().cloned().map(&poll).collect();
+    while let Some((task_queue, res)) = polls.next().await {
+        match PollOutcome::from(res?) {
+            PollOutcome::Task(resp) => return Ok((task_queue, *resp)),
+            PollOutcome::Empty => polls.push(poll(task_queue)),
+        }
+    }
+    unreachable!("Empty polls are reissued, so there's always at least one in flight")
+}
+
+/// A signal to send to a workflow, see [complete_activity_with_followup]
+#[derive(Debug, Clone)]
+pub(crate) struct SignalSpec {
+    pub execution: WorkflowExecution,
+    pub signal_name: String,
+    pub input: Option<Payloads>,
+}
+
+/// What [complete_activity_with_followup] ended up reporting for the activity
+#[derive(Debug)]
+pub(crate) enum FollowupOutcome {
+    /// The followup signal (if any) was sent, and the activity was completed
+    Completed,
+    /// The followup signal could not be sent, so the activity was failed rather than completed
+    SignalFailed(tonic::Status),
+}
+
+/// Completes an activity, and signals another workflow (ex: one running a saga's compensations)
+/// along with it if `followup` is set.
+///
+/// The two RPCs are *not* atomic, and there's no way to take either back once made. So the signal
+/// is sent first:
+/// * If it fails, the activity is failed with a retryable application failure (of type
+///   [FOLLOWUP_SIGNAL_FAILED_TYPE]) instead of being completed, so that the activity's retry
+///   policy decides whether to run it, and so the signal, again.
+/// * If it succeeds but completing the activity then fails (ex: the activity timed out in the
+///   meantime), the signal has still been delivered, and the completion error is returned.
+///
+/// Receivers of the signal should therefore tolerate signals for activities which never completed,
+/// and duplicate signals from activity retries. An error is only returned if the RPC completing or
+/// failing the activity fails.
+#[allow(dead_code)] // Lang has no way to ask for a followup signal yet
+pub(crate) async fn complete_activity_with_followup(
+    client: &dyn WorkerClient,
+    task_token: ActivityTaskToken,
+    result: Option<Payloads>,
+    followup: Option<SignalSpec>,
+) -> Result<FollowupOutcome> {
+    if let Some(signal) = followup {
+        if let Err(e) = client
+            .signal_workflow_execution(signal.execution, signal.signal_name, signal.input)
+            .await
+        {
+            client
+                .fail_activity_task(task_token, Some(followup_signal_failure(&e)), None)
+                .await?;
+            return Ok(FollowupOutcome::SignalFailed(e));
+        }
+    }
+    client.complete_activity_task(task_token, result).await?;
+    Ok(FollowupOutcome::Completed)
+}
+
+/// The application failure type activities are failed with when their followup signal could not be
+/// sent, see [complete_activity_with_followup]
+pub(crate) const FOLLOWUP_SIGNAL_FAILED_TYPE: &str = "FollowupSignalFailed";
+
+fn followup_signal_failure(e: &tonic::Status) -> Failure {
+    Failure {
+        message: format!(
+            "Activity's followup signal could not be sent: {}",
+            e.message()
+        ),
+        failure_info: Some(FailureInfo::ApplicationFailureInfo(
+            ApplicationFailureInfo {
+                r#type: FOLLOWUP_SIGNAL_FAILED_TYPE.to_string(),
+                non_retryable: false,
+                details: None,
+            },
+        )),
+        ..Default::default()
+    }
+}
+
+/// Takes the new workflow task out of a completion response, if the server returned one because
+/// `return_new_workflow_task` was set. Tasks without a token are placeholders and are ignored.
+pub(crate) fn take_new_workflow_task(
+    resp: &mut RespondWorkflowTaskCompletedResponse,
+) -> Option<PollWorkflowTaskQueueResponse> {
+    resp.workflow_task
+        .take()
+        .filter(|wft| !wft.task_token.is_empty())
+}
+
+/// An RPC response body along with the metadata (headers and trailers) the server sent with it
+#[derive(Debug)]
+pub(crate) struct WithMeta<T> {
+    pub body: T,
+    pub metadata: tonic::metadata::MetadataMap,
+}
+
+impl<T> From<tonic::Response<T>> for WithMeta<T> {
+    fn from(resp: tonic::Response<T>) -> Self {
+        let (metadata, body, _) = resp.into_parts();
+        Self { body, metadata }
+    }
+}
+
+/// A poll given a shorter timeout on purpose is expected to run out of time when no task arrives,
+/// so if it does, that's treated like the server answering with no task
+async fn timeout_as_empty<T: Default>(
+    poll: impl Future<Output = Result<tonic::Response<T>>>,
+    poll_timeout_override: Option<Duration>,
+) -> Result<tonic::Response<T>> {
+    match poll.await {
+        Err(s) if poll_timeout_override.is_some() && s.code() == tonic::Code::DeadlineExceeded => {
+            Ok(tonic::Response::new(T::default()))
+        }
+        r => r,
+    }
+}
+
+/// Drives the provided poll future to completion unless `cancel` is cancelled first
+async fn cancellable_poll<T>(
+    poll: impl Future<Output = Result<T>>,
+    cancel: CancellationToken,
+) -> Result<T, PollError> {
+    tokio::select! {
+        biased;
+
+        _ = cancel.cancelled() => Err(PollError::PollCancelled),
+        r = poll => Ok(r?),
+    }
+}
+
+/// A version of [RespondWorkflowTaskCompletedRequest] that will finish being filled out by the
+/// server client
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WorkflowTaskCompletion {
+    /// The task token that would've been received from polling for a workflow activation
+    pub task_token: TaskToken,
+    /// A list of new commands to send to the server, such as starting a timer.
+    pub commands: Vec<Command>,
+    /// If set, indicate that next task should be queued on sticky queue with given attributes.
+    pub sticky_attributes: Option<StickyExecutionAttributes>,
+    /// Responses to queries in the `queries` field of the workflow task.
+    pub query_responses: Vec<QueryResult>,
+    /// Indicate that the task completion should return a new WFT if one is available
+    pub return_new_workflow_task: bool,
+    /// Force a new WFT to be created after this completion
+    pub force_create_new_workflow_task: bool,
+    /// SDK-specific metadata to send
+    pub sdk_metadata: WorkflowTaskCompletedMetadata,
     /// Metering info
     pub metering_metadata: MeteringMetadata,
+    /// If set, replaces the worker's usual version stamp for this completion only. `Some(None)`
+    /// sends no stamp at all, attributing the completion to an unversioned worker.
+    pub override_version_stamp: Option<Option<WorkerVersionStamp>>,
+    /// Protocol messages to send along with the completion, ex: accepting, rejecting or
+    /// completing workflow updates
+    pub messages: Vec<ProtocolMessage>,
+}
+
+/// Builds a [WorkflowTaskCompletion] fluently. Anything not set is empty / false / default, and
+/// no version stamp override is used.
+#[derive(Debug, Default)]
+pub(crate) struct WorkflowTaskCompletionBuilder {
+    commands: Vec<Command>,
+    query_responses: Vec<QueryResult>,
+    sticky_attributes: Option<StickyExecutionAttributes>,
+    return_new_workflow_task: bool,
+    force_create_new_workflow_task: bool,
+    sdk_metadata: WorkflowTaskCompletedMetadata,
+    metering_metadata: MeteringMetadata,
+    messages: Vec<ProtocolMessage>,
+}
+
+impl WorkflowTaskCompletionBuilder {
+    #[allow(dead_code)] // The worker always has a whole activation's worth, see `commands`
+    pub(crate) fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub(crate) fn commands(mut self, commands: impl IntoIterator<Item = Command>) -> Self {
+        self.commands.extend(commands);
+        self
+    }
+
+    #[allow(dead_code)] // The worker always has a whole activation's worth, see `query_responses`
+    pub(crate) fn query_response(mut self, response: QueryResult) -> Self {
+        self.query_responses.push(response);
+        self
+    }
+
+    pub(crate) fn query_responses(
+        mut self,
+        responses: impl IntoIterator<Item = QueryResult>,
+    ) -> Self {
+        self.query_responses.extend(responses);
+        self
+    }
+
+    #[allow(dead_code)] // The worker attaches these after building, see `with_sticky`
+    pub(crate) fn sticky(mut self, attributes: StickyExecutionAttributes) -> Self {
+        self.sticky_attributes = Some(attributes);
+        self
+    }
+
+    pub(crate) fn return_new_workflow_task(mut self, return_new: bool) -> Self {
+        self.return_new_workflow_task = return_new;
+        self
+    }
+
+    pub(crate) fn force_create_new_workflow_task(mut self, force: bool) -> Self {
+        self.force_create_new_workflow_task = force;
+        self
+    }
+
+    pub(crate) fn sdk_metadata(mut self, metadata: WorkflowTaskCompletedMetadata) -> Self {
+        self.sdk_metadata = metadata;
+        self
+    }
+
+    pub(crate) fn metering_metadata(mut self, metadata: MeteringMetadata) -> Self {
+        self.metering_metadata = metadata;
+        self
+    }
+
+    #[allow(dead_code)] // The worker hands over all of a task's messages at once via `messages`
+    pub(crate) fn message(mut self, message: ProtocolMessage) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    pub(crate) fn messages(mut self, messages: impl IntoIterator<Item = ProtocolMessage>) -> Self {
+        self.messages.extend(messages);
+        self
+    }
+
+    pub(crate) fn build(self, task_token: TaskToken) -> WorkflowTaskCompletion {
+        WorkflowTaskCompletion {
+            task_token,
+            commands: self.commands,
+            sticky_attributes: self.sticky_attributes,
+            query_responses: self.query_responses,
+            return_new_workflow_task: self.return_new_workflow_task,
+            force_create_new_workflow_task: self.force_create_new_workflow_task,
+            sdk_metadata: self.sdk_metadata,
+            metering_metadata: self.metering_metadata,
+            override_version_stamp: None,
+            messages: self.messages,
+        }
+    }
+}
+
+impl WorkflowTaskCompletion {
+    pub(crate) fn builder() -> WorkflowTaskCompletionBuilder {
+        WorkflowTaskCompletionBuilder::default()
+    }
+
+    /// Builds the exact request [WorkerClient::complete_workflow_task] would send for this
+    /// completion through `bag`, without sending it. Fails if the completion doesn't pass
+    /// [WorkflowTaskCompletion::validate] or the bag's payload codec fails.
+    pub(crate) fn to_proto(
+        &self,
+        bag: &WorkerClientBag,
+    ) -> Result<RespondWorkflowTaskCompletedRequest> {
+        self.validate()?;
+        let caps = bag.capabilities_snapshot();
+        let mut request = RespondWorkflowTaskCompletedRequest {
+            task_token: self.task_token.clone().into(),
+            commands: self.commands.clone(),
+            identity: bag.identity.clone(),
+            sticky_attributes: self.sticky_attributes.clone(),
+            return_new_workflow_task: self.return_new_workflow_task,
+            force_create_new_workflow_task: self.force_create_new_workflow_task,
+            worker_version_stamp: self
+                .override_version_stamp
+                .clone()
+                .unwrap_or_else(|| bag.worker_version_stamp(&caps)),
+            messages: self.messages.clone(),
+            binary_checksum: bag.binary_checksum(&caps),
+            query_results: self
+                .query_responses
+                .iter()
+                .cloned()
+                .map(|qr| {
+                    let (id, completed_type, query_result, error_message) = qr.into_components();
+                    (
+                        id,
+                        WorkflowQueryResult {
+                            result_type: completed_type as i32,
+                            answer: query_result,
+                            error_message,
+                        },
+                    )
+                })
+                .collect(),
+            namespace: bag.namespace.clone(),
+            sdk_metadata: Some(self.sdk_metadata.clone()),
+            metering_metadata: Some(self.metering_metadata.clone()),
+        };
+        if let Some(codec) = &bag.payload_codec {
+            codec::encode_commands(codec.as_ref(), &mut request.commands)
+                .and_then(|_| {
+                    codec::encode_query_results(codec.as_ref(), &mut request.query_results)
+                })
+                .map_err(codec_failed)?;
+        }
+        Ok(request)
+    }
+
+    /// How many bytes the request completing this task through `bag` will take on the wire, as
+    /// built by [WorkflowTaskCompletion::to_proto]. Useful for checking a completion against the
+    /// server's message size limit (ex: to move large payloads elsewhere) before sending it.
+    #[allow(dead_code)] // The worker doesn't check completion sizes before sending them yet
+    pub(crate) fn encoded_len(&self, bag: &WorkerClientBag) -> Result<usize> {
+        Ok(prost::Message::encoded_len(&self.to_proto(bag)?))
+    }
+
+    /// Completes the task with a sticky queue, so the workflow's next task is routed to this
+    /// worker. If the task isn't picked up from the sticky queue within `schedule_to_start`, the
+    /// server moves it to the normal queue.
+    pub(crate) fn with_sticky(
+        mut self,
+        queue_name: String,
+        normal_name: String,
+        schedule_to_start: Duration,
+    ) -> Result<Self, CompletionValidationError> {
+        if schedule_to_start.is_zero() {
+            return Err(CompletionValidationError::NonPositiveStickyTimeout);
+        }
+        self.sticky_attributes = Some(StickyExecutionAttributes {
+            worker_task_queue: Some(TaskQueue {
+                name: queue_name,
+                kind: TaskQueueKind::Sticky as i32,
+                normal_name,
+            }),
+            schedule_to_start_timeout: Some(
+                schedule_to_start
+                    .try_into()
+                    .map_err(|_| CompletionValidationError::StickyTimeoutTooLarge)?,
+            ),
+        });
+        Ok(self)
+    }
+
+    /// Checks basic structural invariants of the commands and query responses, so that obviously
+    /// malformed completions are caught locally rather than rejected by the server.
+    pub(crate) fn validate(&self) -> Result<(), CompletionValidationError> {
+        let mut seen_ids = HashSet::new();
+        for (index, command) in self.commands.iter().enumerate() {
+            match CommandType::from_i32(command.command_type) {
+                None | Some(CommandType::Unspecified) => {
+                    return Err(CompletionValidationError::InvalidCommandType {
+                        index,
+                        command_type: command.command_type,
+                    })
+                }
+                _ => {}
+            }
+            let id = match &command.attributes {
+                None => return Err(CompletionValidationError::MissingAttributes { index }),
+                Some(Attributes::ScheduleActivityTaskCommandAttributes(a)) => {
+                    Some(("activity", &a.activity_id))
+                }
+                Some(Attributes::StartTimerCommandAttributes(a)) => Some(("timer", &a.timer_id)),
+                _ => None,
+            };
+            if let Some((kind, id)) = id {
+                if !seen_ids.insert((kind, id)) {
+                    return Err(CompletionValidationError::DuplicateCommandId {
+                        kind,
+                        id: id.clone(),
+                    });
+                }
+            }
+        }
+        let mut seen_query_ids = HashSet::new();
+        for qr in &self.query_responses {
+            if !seen_query_ids.insert(&qr.query_id) {
+                return Err(CompletionValidationError::DuplicateQueryResponse {
+                    query_id: qr.query_id.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ways in which a [WorkflowTaskCompletion] can be structurally invalid
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub(crate) enum CompletionValidationError {
+    #[error("Command at index {index} has invalid command type {command_type}")]
+    InvalidCommandType { index: usize, command_type: i32 },
+    #[error("Command at index {index} is missing attributes")]
+    MissingAttributes { index: usize },
+    #[error("Multiple commands use {kind} id `{id}`")]
+    DuplicateCommandId { kind: &'static str, id: String },
+    #[error("Multiple responses to query `{query_id}`")]
+    DuplicateQueryResponse { query_id: String },
+    #[error("Sticky queue schedule-to-start timeout must be positive")]
+    NonPositiveStickyTimeout,
+    #[error("Sticky queue schedule-to-start timeout is too large to send to the server")]
+    StickyTimeoutTooLarge,
+}
+
+impl From<CompletionValidationError> for tonic::Status {
+    fn from(e: CompletionValidationError) -> Self {
+        tonic::Status::invalid_argument(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temporal_client::{ClientOptionsBuilder, RetryConfig};
+    use temporal_sdk_core_protos::temporal::api::{
+        command::v1::{ScheduleActivityTaskCommandAttributes, StartTimerCommandAttributes},
+        common::v1::{ActivityType, WorkflowType},
+        failure::v1::ApplicationFailureInfo,
+        history::v1::History,
+    };
+    use url::Url;
+
+    /// A bag whose client only connects once a call is made, so it can be built without a server
+    fn lazy_bag(namespace: &str) -> WorkerClientBag {
+        let opts = ClientOptionsBuilder::default()
+            .identity("enchicat".to_string())
+            .target_url(Url::parse("http://localhost:1").unwrap())
+            .client_name("cute-kitty".to_string())
+            .client_version("0.1.0".to_string())
+            .build()
+            .unwrap();
+        let client = opts
+            .connect_no_namespace_lazy(None, None)
+            .unwrap()
+            .into_inner();
+        WorkerClientBag::new(
+            RetryClient::new(
+                Client::new(client, namespace.to_string()),
+                RetryConfig::default(),
+            ),
+            WorkerClientBagOptions {
+                namespace: namespace.to_string(),
+                identity: "enchicat".to_string(),
+                worker_build_id: "build".to_string(),
+                poll_circuit_breaker: Some(CircuitBreakerConfig {
+                    failure_threshold: 1,
+                    cooldown: Duration::from_secs(1),
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn with_namespace_shares_connection_but_not_namespace_state() {
+        let bag = lazy_bag("ns-a");
+        let other = bag.with_namespace("ns-b".to_string());
+        assert_eq!(bag.namespace(), "ns-a");
+        assert_eq!(other.namespace(), "ns-b");
+        assert_eq!(other.identity(), bag.identity());
+        assert!(Arc::ptr_eq(&other.capabilities, &bag.capabilities));
+        assert!(Arc::ptr_eq(&other.reachability, &bag.reachability));
+        assert!(Arc::ptr_eq(&other.server_time, &bag.server_time));
+        // Breaker state starts afresh, but the configuration carries over
+        assert_eq!(
+            other.poll_breaker.as_ref().map(|b| b.config),
+            bag.poll_breaker.as_ref().map(|b| b.config)
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_poll_returns_poll_cancelled() {
+        let cancel = CancellationToken::new();
+        let poll = tokio::spawn(cancellable_poll(
+            futures::future::pending::<Result<()>>(),
+            cancel.clone(),
+        ));
+        cancel.cancel();
+        assert_matches!(poll.await.unwrap(), Err(PollError::PollCancelled));
+    }
+
+    #[tokio::test]
+    async fn uncancelled_poll_passes_through_errors() {
+        let res = cancellable_poll(
+            async { Err::<(), _>(tonic::Status::internal("ahh")) },
+            CancellationToken::new(),
+        )
+        .await;
+        assert_matches!(res, Err(PollError::TonicError(s)) if s.code() == tonic::Code::Internal);
+    }
+
+    fn completion_with(commands: Vec<Command>, query_ids: &[&str]) -> WorkflowTaskCompletion {
+        WorkflowTaskCompletion::builder()
+            .commands(commands)
+            .query_responses(query_ids.iter().map(|id| QueryResult {
+                query_id: id.to_string(),
+                variant: None,
+            }))
+            .build(TaskToken(vec![1]))
+    }
+
+    fn timer(id: &str) -> Command {
+        Attributes::StartTimerCommandAttributes(StartTimerCommandAttributes {
+            timer_id: id.to_string(),
+            ..Default::default()
+        })
+        .into()
+    }
+
+    #[test]
+    fn valid_completion_passes_validation() {
+        let activity = Attributes::ScheduleActivityTaskCommandAttributes(
+            ScheduleActivityTaskCommandAttributes {
+                activity_id: "1".to_string(),
+                ..Default::default()
+            },
+        )
+        .into();
+        // Timers and activities have separate id spaces
+        let completion = completion_with(vec![timer("1"), activity], &["q1", "q2"]);
+        assert_eq!(completion.validate(), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_timer_ids_fail_validation() {
+        let completion = completion_with(vec![timer("1"), timer("1")], &[]);
+        assert_eq!(
+            completion.validate(),
+            Err(CompletionValidationError::DuplicateCommandId {
+                kind: "timer",
+                id: "1".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn bad_commands_fail_validation() {
+        let mut no_type = timer("1");
+        no_type.command_type = CommandType::Unspecified as i32;
+        assert_matches!(
+            completion_with(vec![no_type], &[]).validate(),
+            Err(CompletionValidationError::InvalidCommandType { index: 0, .. })
+        );
+        let mut no_attrs = timer("1");
+        no_attrs.attributes = None;
+        assert_matches!(
+            completion_with(vec![timer("2"), no_attrs], &[]).validate(),
+            Err(CompletionValidationError::MissingAttributes { index: 1 })
+        );
+    }
+
+    #[test]
+    fn duplicate_query_responses_fail_validation() {
+        assert_matches!(
+            completion_with(vec![], &["q1", "q1"]).validate(),
+            Err(CompletionValidationError::DuplicateQueryResponse { .. })
+        );
+    }
+
+    #[tokio::test]
+    async fn history_stream_follows_page_tokens() {
+        let mut mock_client = mocks::mock_workflow_client();
+        let mut seq = mockall::Sequence::new();
+        for (page_token, next_page_token, event_ids) in [
+            (vec![], vec![1], vec![1, 2]),
+            (vec![1], vec![2], vec![3]),
+            (vec![2], vec![], vec![4, 5]),
+        ] {
+            mock_client
+                .expect_get_workflow_execution_history()
+                .withf(move |_, _, pt, _, _| pt == &page_token)
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(move |_, _, _, _, _| {
+                    Ok(GetWorkflowExecutionHistoryResponse {
+                        history: Some(History {
+                            events: event_ids
+                                .iter()
+                                .map(|&event_id| HistoryEvent {
+                                    event_id,
+                                    ..Default::default()
+                                })
+                                .collect(),
+                        }),
+                        next_page_token: next_page_token.clone(),
+                        ..Default::default()
+                    })
+                });
+        }
+
+        let ids: Vec<_> =
+            stream_workflow_execution_history(Arc::new(mock_client), "wfid".to_string(), None)
+                .map(|e| e.unwrap().event_id)
+                .collect()
+                .await;
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn history_stream_yields_rpc_errors_then_ends() {
+        let mut mock_client = mocks::mock_workflow_client();
+        mock_client
+            .expect_get_workflow_execution_history()
+            .times(1)
+            .returning(|_, _, _, _, _| Err(tonic::Status::not_found("gone")));
+
+        let items: Vec<_> =
+            stream_workflow_execution_history(Arc::new(mock_client), "wfid".to_string(), None)
+                .collect()
+                .await;
+        assert_eq!(items.len(), 1);
+        assert_matches!(&items[0], Err(e) if e.code() == tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn open_executions_stream_follows_page_tokens() {
+        let mut mock_client = mocks::mock_workflow_client();
+        let mut seq = mockall::Sequence::new();
+        for (page_token, next_page_token, wf_ids) in [
+            (vec![], vec![1], vec!["a", "b"]),
+            (vec![1], vec![], vec!["c"]),
+        ] {
+            mock_client
+                .expect_list_open_workflow_executions()
+                .withf(move |_, tf, pt| {
+                    pt == &page_token && tf.as_ref().map(|f| f.name.as_str()) == Some("reconciled")
+                })
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(move |_, _, _| {
+                    Ok(ListOpenWorkflowExecutionsResponse {
+                        executions: wf_ids
+                            .iter()
+                            .map(|wf_id| WorkflowExecutionInfo {
+                                execution: Some(WorkflowExecution {
+                                    workflow_id: wf_id.to_string(),
+                                    ..Default::default()
+                                }),
+                                ..Default::default()
+                            })
+                            .collect(),
+                        next_page_token: next_page_token.clone(),
+                    })
+                });
+        }
+
+        let ids: Vec<_> = stream_open_workflow_executions(
+            Arc::new(mock_client),
+            None,
+            Some(WorkflowTypeFilter {
+                name: "reconciled".to_string(),
+            }),
+        )
+        .map(|e| e.unwrap().execution.unwrap().workflow_id)
+        .collect()
+        .await;
+        assert_eq!(ids, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn task_receipts_report_processing_latency() {
+        let receipts = TaskReceipts::default();
+        let sticky = TaskQueue {
+            name: "sticky".to_string(),
+            kind: TaskQueueKind::Sticky as i32,
+            normal_name: "q".to_string(),
+        };
+        receipts.received(&[1], normal_queue_name(&sticky), Duration::from_secs(10));
+        // Empty polls didn't hand out a task
+        receipts.received(&[], "q".to_string(), Duration::from_secs(10));
+        assert_eq!(receipts.len(), 1);
+
+        assert_eq!(receipts.completed(&[1]).unwrap().task_queue, "q");
+        assert_eq!(receipts.len(), 0);
+        assert_eq!(receipts.completed(&[1]), None);
+    }
+
+    #[test]
+    fn task_tokens_from_other_namespaces_are_rejected() {
+        let token = |namespace_id: &str| {
+            TaskToken(prost::Message::encode_to_vec(&TaskTokenNamespace {
+                namespace_id: namespace_id.to_string(),
+            }))
+        };
+        let namespace_id = NamespaceId::default();
+        // There's nothing to compare against until the namespace's id is known
+        namespace_id.validate("ns", &token("other-id")).unwrap();
+
+        namespace_id.learn_from_token(&token("ns-id").0);
+        namespace_id.validate("ns", &token("ns-id")).unwrap();
+        let err = namespace_id.validate("ns", &token("other-id")).unwrap_err();
+        assert_eq!(err.token_namespace_id, "other-id");
+        assert_eq!(
+            tonic::Status::from(err).code(),
+            tonic::Code::InvalidArgument
+        );
+        // Tokens which don't decode are left for the server to judge
+        namespace_id.validate("ns", &TaskToken(vec![1])).unwrap();
+    }
+
+    #[test]
+    fn connection_state_follows_latest_rpc_outcome() {
+        let reachability = ServerReachability::default();
+        assert_eq!(reachability.state(), ConnectionState::Connecting);
+
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        reachability.record(tonic::Code::NotFound, at(1));
+        assert_eq!(
+            reachability.state(),
+            ConnectionState::Ready {
+                last_success: at(1)
+            }
+        );
+        reachability.record(tonic::Code::Unavailable, at(2));
+        // Timing out says nothing about whether the server is reachable
+        reachability.record(tonic::Code::DeadlineExceeded, at(3));
+        assert_eq!(
+            reachability.state(),
+            ConnectionState::TransientFailure {
+                last_failure: at(2),
+                last_success: Some(at(1))
+            }
+        );
+        reachability.record(tonic::Code::Ok, at(4));
+        assert_eq!(
+            reachability.state(),
+            ConnectionState::Ready {
+                last_success: at(4)
+            }
+        );
+    }
+
+    #[test]
+    fn identity_suffixes() {
+        assert_eq!(
+            suffixed_identity("worker@host", "pod-xyz"),
+            "worker@host/pod-xyz"
+        );
+        assert_eq!(suffixed_identity("worker@host", ""), "worker@host");
+    }
+
+    #[test]
+    fn task_receipts_forget_timed_out_tasks() {
+        let receipts = TaskReceipts::default();
+        receipts.received(&[1], "q".to_string(), Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        receipts.received(&[2], "q".to_string(), Duration::from_secs(10));
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts.completed(&[1]), None);
+    }
+
+    #[test]
+    fn task_receipts_are_bounded() {
+        let receipts = TaskReceipts::default();
+        for i in 0..=MAX_TASK_RECEIPTS {
+            receipts.received(&i.to_be_bytes(), "q".to_string(), Duration::from_secs(10));
+        }
+        assert_eq!(receipts.len(), MAX_TASK_RECEIPTS);
+        assert_eq!(receipts.completed(&MAX_TASK_RECEIPTS.to_be_bytes()), None);
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_requests_share_one_call() {
+        let in_flight = InFlightRequests::<&str, usize>::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let release_rx = release_rx.shared();
+        let start = |calls: Arc<AtomicUsize>| {
+            let release_rx = release_rx.clone();
+            move || {
+                async move {
+                    let _ = release_rx.await;
+                    calls.fetch_add(1, Ordering::SeqCst)
+                }
+                .boxed()
+            }
+        };
+
+        let first = in_flight.join_or_start("page", start(calls.clone()));
+        let second = in_flight.join_or_start("page", start(calls.clone()));
+        let other = in_flight.join_or_start("other", start(calls.clone()));
+        assert_eq!(in_flight.len(), 2);
+
+        release_tx.send(()).unwrap();
+        let (first, second, _) = tokio::join!(first, second, other);
+        assert_eq!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(in_flight.len(), 0);
+
+        // Once finished, the same request is issued anew
+        let again = in_flight.join_or_start("page", start(calls.clone()));
+        assert_eq!(in_flight.len(), 1);
+        again.await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn shared_request_survives_its_first_caller_giving_up() {
+        let in_flight = InFlightRequests::<&str, usize>::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let c = calls.clone();
+        let first = in_flight.join_or_start("page", move || {
+            async move {
+                let _ = release_rx.await;
+                c.fetch_add(1, Ordering::SeqCst) + 1
+            }
+            .boxed()
+        });
+        let second = in_flight.join_or_start("page", || unreachable!("request is in flight"));
+
+        // The first caller starts the request, then gives up on it while the second still waits
+        let mut first = first.boxed();
+        assert!(futures::poll!(&mut first).is_pending());
+        drop(first);
+
+        release_tx.send(()).unwrap();
+        assert_eq!(second.await, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn abandoned_request_is_forgotten() {
+        let in_flight = InFlightRequests::<&str, usize>::default();
+        let (_release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let mut req = in_flight.join_or_start("page", move || {
+            async move {
+                let _ = release_rx.await;
+                1
+            }
+            .boxed()
+        });
+        assert!(futures::poll!(&mut req).is_pending());
+        assert_eq!(in_flight.len(), 1);
+
+        // Once nobody is waiting on it, the request is dropped and its entry with it
+        drop(req);
+        assert_eq!(in_flight.len(), 0);
+        let again = in_flight.join_or_start("page", || async { 2 }.boxed());
+        assert_eq!(again.await, 2);
+    }
+
+    #[tokio::test]
+    async fn polls_timing_out_early_on_purpose_are_empty() {
+        let timed_out = || async {
+            Err::<tonic::Response<PollActivityTaskQueueResponse>, _>(
+                tonic::Status::deadline_exceeded("no task in time"),
+            )
+        };
+        let resp = timeout_as_empty(timed_out(), Some(Duration::from_millis(100)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_matches!(PollOutcome::from(resp), PollOutcome::Empty);
+        // Without an override, running out of time is unexpected
+        assert_matches!(
+            timeout_as_empty(timed_out(), None).await,
+            Err(s) if s.code() == tonic::Code::DeadlineExceeded
+        );
+    }
+
+    fn events(ids: impl IntoIterator<Item = i64>) -> Option<History> {
+        Some(History {
+            events: ids
+                .into_iter()
+                .map(|event_id| HistoryEvent {
+                    event_id,
+                    ..Default::default()
+                })
+                .collect(),
+        })
+    }
+
+    #[derive(Debug)]
+    struct UppercasingCodec;
+    impl PayloadCodec for UppercasingCodec {
+        fn encode(&self, payloads: Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError> {
+            Ok(payloads)
+        }
+        fn decode(&self, payloads: Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError> {
+            Ok(payloads
+                .into_iter()
+                .map(|mut p| {
+                    p.data.make_ascii_uppercase();
+                    p
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn decoded_activity_polls() {
+        let client = mocks::happy_path_client()
+            .activity_polls([PollActivityTaskQueueResponse {
+                task_token: vec![1],
+                activity_id: "act".to_string(),
+                activity_type: Some(ActivityType {
+                    name: "greet".to_string(),
+                }),
+                input: Some(Payloads {
+                    payloads: vec![Payload {
+                        data: b"hi".to_vec(),
+                        ..Default::default()
+                    }],
+                }),
+                heartbeat_timeout: Some(prost_types::Duration {
+                    seconds: 5,
+                    nanos: 0,
+                }),
+                ..Default::default()
+            }])
+            .build();
+
+        let task = poll_activity_task_decoded(
+            &client,
+            "q".to_string(),
+            Some(&UppercasingCodec),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(task.task_token.0, TaskToken(vec![1]));
+        assert_eq!(task.activity_type, "greet");
+        assert_eq!(task.activity_id, "act");
+        assert_eq!(task.input[0].data, b"HI");
+        assert_eq!(task.heartbeat_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(task.start_to_close_timeout, None);
+
+        // The queue is now empty, so polls come back without a task
+        let task =
+            poll_activity_task_decoded(&client, "q".to_string(), None, CancellationToken::new())
+                .await
+                .unwrap();
+        assert!(task.is_none());
+    }
+
+    #[tokio::test]
+    async fn multi_queue_polls_return_first_task() {
+        let mut mock_client = mocks::mock_workflow_client();
+        mock_client
+            .expect_poll_workflow_task()
+            .withf(|tq, _, _| tq.name == "quiet")
+            .returning(|_, _, _| Ok(Default::default()));
+        mock_client
+            .expect_poll_workflow_task()
+            .withf(|tq, _, _| tq.name == "busy")
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(PollWorkflowTaskQueueResponse {
+                    task_token: vec![1],
+                    ..Default::default()
+                })
+            });
+        let queues = ["quiet", "busy"].map(|name| TaskQueue {
+            name: name.to_string(),
+            ..Default::default()
+        });
+
+        let (tq, resp) = poll_workflow_task_multi(&mock_client, &queues, CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(tq.name, "busy");
+        assert_eq!(resp.task_token, vec![1]);
+
+        assert_matches!(
+            poll_workflow_task_multi(&mock_client, &[], CancellationToken::new()).await,
+            Err(PollError::TonicError(s)) if s.code() == tonic::Code::InvalidArgument
+        );
+    }
+
+    fn followup() -> Option<SignalSpec> {
+        Some(SignalSpec {
+            execution: WorkflowExecution {
+                workflow_id: "compensator".to_string(),
+                run_id: "".to_string(),
+            },
+            signal_name: "compensate".to_string(),
+            input: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn followup_signal_sent_before_completing() {
+        let mut seq = mockall::Sequence::new();
+        let mut mock_client = mocks::mock_workflow_client();
+        mock_client
+            .expect_signal_workflow_execution()
+            .withf(|ex, name, _| ex.workflow_id == "compensator" && name == "compensate")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(Default::default()));
+        mock_client
+            .expect_complete_activity_task()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(Default::default()));
+        mock_client.expect_fail_activity_task().never();
+
+        let outcome = complete_activity_with_followup(
+            &mock_client,
+            ActivityTaskToken(TaskToken(vec![1])),
+            None,
+            followup(),
+        )
+        .await
+        .unwrap();
+        assert_matches!(outcome, FollowupOutcome::Completed);
+    }
+
+    #[tokio::test]
+    async fn failed_followup_signal_fails_activity() {
+        let mut mock_client = mocks::mock_workflow_client();
+        mock_client
+            .expect_signal_workflow_execution()
+            .times(1)
+            .returning(|_, _, _| Err(tonic::Status::unavailable("nope")));
+        mock_client.expect_complete_activity_task().never();
+        mock_client
+            .expect_fail_activity_task()
+            .withf(|_, f, _| {
+                matches!(
+                    f.as_ref().and_then(|f| f.failure_info.as_ref()),
+                    Some(FailureInfo::ApplicationFailureInfo(info))
+                        if info.r#type == FOLLOWUP_SIGNAL_FAILED_TYPE && !info.non_retryable
+                )
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(Default::default()));
+
+        let outcome = complete_activity_with_followup(
+            &mock_client,
+            ActivityTaskToken(TaskToken(vec![1])),
+            None,
+            followup(),
+        )
+        .await
+        .unwrap();
+        assert_matches!(outcome, FollowupOutcome::SignalFailed(e) if e.code() == tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn history_event_found_in_latest_page() {
+        let mut mock_client = mocks::mock_workflow_client();
+        mock_client
+            .expect_get_workflow_execution_history_reverse()
+            .times(2)
+            .returning(|_, _, _| {
+                Ok(GetWorkflowExecutionHistoryReverseResponse {
+                    history: events([10, 9, 8]),
+                    next_page_token: vec![1],
+                })
+            });
+        mock_client.expect_get_workflow_execution_history().never();
+        let client: Arc<dyn WorkerClient> = Arc::new(mock_client);
+
+        let event = get_history_event(client.clone(), "wfid".to_string(), None, 9)
+            .await
+            .unwrap();
+        assert_eq!(event.map(|e| e.event_id), Some(9));
+        let event = get_history_event(client, "wfid".to_string(), None, 11)
+            .await
+            .unwrap();
+        assert_eq!(event, None);
+    }
+
+    #[tokio::test]
+    async fn history_event_older_than_latest_page_is_paged_to() {
+        let mut mock_client = mocks::mock_workflow_client();
+        mock_client
+            .expect_get_workflow_execution_history_reverse()
+            .returning(|_, _, _| {
+                Ok(GetWorkflowExecutionHistoryReverseResponse {
+                    history: events([10, 9]),
+                    next_page_token: vec![1],
+                })
+            });
+        // Only the first page should be fetched, since it already goes past the event
+        mock_client
+            .expect_get_workflow_execution_history()
+            .withf(|_, _, pt, _, _| pt.is_empty())
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(GetWorkflowExecutionHistoryResponse {
+                    history: events(1..=4),
+                    next_page_token: vec![1],
+                    ..Default::default()
+                })
+            });
+
+        let event = get_history_event(Arc::new(mock_client), "wfid".to_string(), None, 3)
+            .await
+            .unwrap();
+        assert_eq!(event.map(|e| e.event_id), Some(3));
+    }
+
+    #[test]
+    fn describe_task_queue_request_targets_namespace_and_queue() {
+        let req =
+            describe_task_queue_request("ns".to_string(), "tq".to_string(), TaskQueueKind::Sticky);
+        assert_eq!(req.namespace, "ns");
+        let tq = req.task_queue.unwrap();
+        assert_eq!(tq.name, "tq");
+        assert_eq!(tq.kind, TaskQueueKind::Sticky as i32);
+        assert!(req.include_task_queue_status);
+    }
+
+    #[test]
+    fn build_id_compatibility_request_targets_task_queue() {
+        let req = build_id_compatibility_request("ns".to_string(), "tq".to_string());
+        assert_eq!(req.namespace, "ns");
+        assert_eq!(req.task_queue, "tq");
+        assert_eq!(req.max_sets, 0);
+    }
+
+    #[test]
+    fn build_id_compatibility_operations_convert() {
+        use update_worker_build_id_compatibility_request::{AddNewCompatibleVersion, Operation};
+        assert_eq!(
+            Operation::from(BuildIdCompatibilityOperation::AddNewDefault(
+                "2.0".to_string()
+            )),
+            Operation::AddNewBuildIdInNewDefaultSet("2.0".to_string())
+        );
+        assert_eq!(
+            Operation::from(BuildIdCompatibilityOperation::AddCompatible {
+                new_build_id: "1.1".to_string(),
+                existing_compatible_build_id: "1.0".to_string(),
+                make_set_default: true,
+            }),
+            Operation::AddNewCompatibleBuildId(AddNewCompatibleVersion {
+                new_build_id: "1.1".to_string(),
+                existing_compatible_build_id: "1.0".to_string(),
+                make_set_default: true,
+            })
+        );
+        assert_eq!(
+            Operation::from(BuildIdCompatibilityOperation::PromoteSet("1.0".to_string())),
+            Operation::PromoteSetByBuildId("1.0".to_string())
+        );
+    }
+
+    #[test]
+    fn health_status_from_rpc_outcome() {
+        assert_eq!(health_status(Ok(())).unwrap(), HealthStatus::Healthy);
+        assert_eq!(
+            health_status::<()>(Err(tonic::Status::unavailable("conn refused"))).unwrap(),
+            HealthStatus::FrontendUnreachable {
+                reason: "conn refused".to_string()
+            }
+        );
+        assert_eq!(
+            health_status::<()>(Err(tonic::Status::not_found("no such namespace"))).unwrap(),
+            HealthStatus::NamespaceNotFound
+        );
+        assert_eq!(
+            health_status::<()>(Err(tonic::Status::permission_denied("nope")))
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+    }
+
+    #[tokio::test]
+    async fn completion_limiter_queues_excess_completions() {
+        let limiter = Arc::new(CompletionLimiter::new(1));
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let first = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.run(release_rx).await }
+        });
+        while limiter.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(limiter.queued(), 0);
+
+        let second = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.run(async { 2 }).await }
+        });
+        while limiter.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!second.is_finished());
+        assert_eq!(limiter.saturation(), 1.0);
+
+        release_tx.send(()).unwrap();
+        first.await.unwrap().unwrap();
+        assert_eq!(second.await.unwrap(), 2);
+        assert_eq!(limiter.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn completion_backpressure_rises_with_in_flight_completions() {
+        let limiter = Arc::new(CompletionLimiter::new(4));
+        assert_eq!(limiter.saturation(), 0.0);
+        let mut releases = vec![];
+        let mut completions = vec![];
+        for in_flight in 1..=4 {
+            let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+            releases.push(release_tx);
+            completions.push(tokio::spawn({
+                let limiter = limiter.clone();
+                async move { limiter.run(release_rx).await }
+            }));
+            while limiter.permits.available_permits() > 4 - in_flight {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(limiter.saturation(), in_flight as f64 / 4.0);
+        }
+
+        for release_tx in releases {
+            release_tx.send(()).unwrap();
+        }
+        for completion in completions {
+            completion.await.unwrap().unwrap();
+        }
+        assert_eq!(limiter.saturation(), 0.0);
+    }
+
+    #[test]
+    fn oversized_heartbeat_details_rejected() {
+        let details = Payloads {
+            payloads: vec![Payload {
+                data: vec![0; 100],
+                ..Default::default()
+            }],
+        };
+        check_heartbeat_details_size(None, 0).unwrap();
+        check_heartbeat_details_size(Some(&details), 200).unwrap();
+        assert_matches!(
+            check_heartbeat_details_size(Some(&details), 50),
+            Err(HeartbeatError::HeartbeatDetailsTooLarge { size, limit: 50 }) if size > 100
+        );
+    }
+
+    #[test]
+    fn sticky_queues_need_normal_name() {
+        let mut tq = TaskQueue {
+            name: "sticky".to_string(),
+            kind: TaskQueueKind::Sticky as i32,
+            normal_name: "".to_string(),
+        };
+        assert_matches!(
+            validate_task_queue_kind(&tq),
+            Err(s) if s.code() == tonic::Code::InvalidArgument
+        );
+        tq.normal_name = "normal".to_string();
+        validate_task_queue_kind(&tq).unwrap();
+        tq.kind = TaskQueueKind::Normal as i32;
+        tq.normal_name = "".to_string();
+        validate_task_queue_kind(&tq).unwrap();
+    }
+
+    #[test]
+    fn keeps_latest_server_time() {
+        let latest = LatestServerTime::default();
+        let at = |seconds| Some(prost_types::Timestamp { seconds, nanos: 0 });
+        latest.observe(None);
+        assert_eq!(latest.get(), None);
+        latest.observe(at(100));
+        latest.observe(at(50));
+        assert_eq!(
+            latest.get(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(100))
+        );
+        latest.observe(at(200));
+        assert_eq!(
+            latest.get(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(200))
+        );
+    }
+
+    #[test]
+    fn client_identity_overrides_headers() {
+        let headers = HashMap::from([
+            ("client-name".to_string(), "from-headers".to_string()),
+            ("x-team-id".to_string(), "abc".to_string()),
+        ]);
+        let merged = with_client_identity(
+            headers.clone(),
+            Some("wrapper-sdk".to_string()),
+            Some("1.2.3".to_string()),
+        );
+        assert_eq!(merged["client-name"], "wrapper-sdk");
+        assert_eq!(merged["client-version"], "1.2.3");
+        assert_eq!(merged["x-team-id"], "abc");
+        assert_eq!(with_client_identity(headers.clone(), None, None), headers);
+    }
+
+    #[test]
+    fn headers_must_be_ascii_metadata() {
+        let parsed = parse_headers(HashMap::from([(
+            "x-team-id".to_string(),
+            "abc".to_string(),
+        )]))
+        .unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].0.as_str(), "x-team-id");
+        assert_eq!(parsed[0].1, "abc");
+
+        for (k, v) in [
+            ("bad key", "abc"),
+            ("x-thing-bin", "abc"),
+            ("x-team-id", "caf\u{e9}\n"),
+        ] {
+            let err = parse_headers(HashMap::from([(k.to_string(), v.to_string())])).unwrap_err();
+            assert_eq!(err.key, k);
+        }
+    }
+
+    #[derive(Debug)]
+    struct RecordingInterceptor {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<(&'static str, &'static str)>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RpcInterceptor for RecordingInterceptor {
+        async fn before(&self, ctx: &mut RpcContext<'_>) {
+            self.calls.lock().push((self.name, ctx.method));
+            ctx.metadata
+                .insert("authorization", AsciiMetadataValue::from_static(self.name));
+        }
+    }
+
+    #[tokio::test]
+    async fn interceptors_run_in_order_before_poll() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let interceptors: Vec<Arc<dyn RpcInterceptor>> = ["first", "second"]
+            .into_iter()
+            .map(|name| {
+                Arc::new(RecordingInterceptor {
+                    name,
+                    calls: calls.clone(),
+                }) as Arc<dyn RpcInterceptor>
+            })
+            .collect();
+        let mut req = tonic::Request::new(PollWorkflowTaskQueueRequest::default());
+        intercept(&interceptors, "poll_workflow_task_queue", &mut req).await;
+
+        assert_eq!(
+            *calls.lock(),
+            vec![
+                ("first", "poll_workflow_task_queue"),
+                ("second", "poll_workflow_task_queue")
+            ]
+        );
+        // The last interceptor to run gets the final say
+        assert_eq!(req.metadata().get("authorization").unwrap(), "second");
+    }
+
+    #[test]
+    fn empty_task_tokens_are_rejected() {
+        assert_eq!(
+            ActivityTaskToken(TaskToken(vec![1, 2]))
+                .into_bytes()
+                .unwrap(),
+            vec![1, 2]
+        );
+        let err = WorkflowTaskToken(TaskToken(vec![]))
+            .into_bytes()
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn circuit_breaker_opens_after_consecutive_overloads() {
+        let breaker = PollCircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(10),
+        });
+        let overloaded: Result<(), PollError> =
+            Err(tonic::Status::resource_exhausted("slow down").into());
+        let start = Instant::now();
+
+        breaker.check(start).unwrap();
+        breaker.record(&overloaded, start);
+        // Any other outcome resets the count
+        breaker.record(&Ok(()), start);
+        breaker.record(&overloaded, start);
+        breaker.check(start).unwrap();
+        breaker.record(&overloaded, start);
+        assert_matches!(breaker.check(start), Err(PollError::CircuitOpen));
+        assert_matches!(
+            breaker.check(start + Duration::from_secs(9)),
+            Err(PollError::CircuitOpen)
+        );
+
+        // After the cooldown one poll is let through, and reopens the breaker if it fails
+        let after = start + Duration::from_secs(10);
+        breaker.check(after).unwrap();
+        breaker.record(&overloaded, after);
+        assert_matches!(breaker.check(after), Err(PollError::CircuitOpen));
+
+        let later = after + Duration::from_secs(10);
+        breaker.check(later).unwrap();
+        breaker.record(&Ok(()), later);
+        breaker.check(later).unwrap();
+        breaker.record(&overloaded, later);
+        breaker.check(later).unwrap();
+    }
+
+    #[derive(Debug)]
+    struct ManualClock(Mutex<Instant>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            *self.0.lock() += duration;
+            futures::future::ready(()).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_cooldown_follows_injected_clock() {
+        let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+        let mut bag = lazy_bag("ns");
+        bag.client.set_clock(clock.clone());
+        let overloaded = bag
+            .guarded_poll(async {
+                Err::<(), PollError>(tonic::Status::resource_exhausted("slow down").into())
+            })
+            .await;
+        assert_matches!(overloaded, Err(PollError::TonicError(_)));
+        assert_matches!(
+            bag.guarded_poll(async { Ok(()) }).await,
+            Err(PollError::CircuitOpen)
+        );
+
+        // Nothing actually waits out the cooldown
+        clock.sleep(Duration::from_secs(1)).await;
+        bag.guarded_poll(async { Ok(()) }).await.unwrap();
+    }
+
+    #[test]
+    fn takes_new_workflow_task_from_completion() {
+        let new_task = PollWorkflowTaskQueueResponse {
+            task_token: vec![1, 2, 3],
+            workflow_type: Some(WorkflowType {
+                name: "wf".to_string(),
+            }),
+            history: Some(History {
+                events: vec![HistoryEvent {
+                    event_id: 7,
+                    ..Default::default()
+                }],
+            }),
+            ..Default::default()
+        };
+        let mut resp = RespondWorkflowTaskCompletedResponse {
+            workflow_task: Some(new_task.clone()),
+            ..Default::default()
+        };
+        assert_eq!(take_new_workflow_task(&mut resp), Some(new_task));
+        assert_eq!(resp.workflow_task, None);
+        assert_eq!(take_new_workflow_task(&mut resp), None);
+
+        let mut placeholder = RespondWorkflowTaskCompletedResponse {
+            workflow_task: Some(PollWorkflowTaskQueueResponse::default()),
+            ..Default::default()
+        };
+        assert_eq!(take_new_workflow_task(&mut placeholder), None);
+    }
+
+    fn recorded_details(failure: &Failure, key: &str) -> Vec<serde_json::Value> {
+        match &failure.failure_info {
+            Some(FailureInfo::ApplicationFailureInfo(ApplicationFailureInfo {
+                details: Some(d),
+                ..
+            })) => d
+                .payloads
+                .iter()
+                .filter(|p| p.metadata.contains_key(key))
+                .map(|p| serde_json::from_slice(&p.data).unwrap())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn non_determinism_detail_is_noted_and_recorded() {
+        assert_eq!(with_non_determinism(None, None), None);
+
+        let detail = NonDeterminismDetail {
+            expected: "TimerStarted".to_string(),
+            actual: "ScheduleActivityTask".to_string(),
+            event_id: 5,
+        };
+        let failure = with_non_determinism(None, Some(&detail)).unwrap();
+        assert_eq!(
+            failure.message,
+            "Nondeterminism at event 5: expected TimerStarted, but workflow did ScheduleActivityTask"
+        );
+        assert_eq!(
+            recorded_details(&failure, NON_DETERMINISM_DETAIL_KEY),
+            vec![serde_json::json!({
+                "expected": "TimerStarted",
+                "actual": "ScheduleActivityTask",
+                "event_id": 5,
+            })]
+        );
+
+        let user_failure = Failure {
+            message: "boom".to_string(),
+            ..Default::default()
+        };
+        let failure = with_non_determinism(Some(user_failure), Some(&detail)).unwrap();
+        assert!(failure
+            .message
+            .starts_with("boom (Nondeterminism at event 5"));
+        assert_eq!(
+            recorded_details(&failure, NON_DETERMINISM_DETAIL_KEY).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn failing_command_index_is_noted_and_recorded() {
+        assert_eq!(with_failing_command(None, None), None);
+
+        let only_index = with_failing_command(None, Some(3)).unwrap();
+        assert_eq!(only_index.message, "failing command index: 3");
+        assert_eq!(
+            recorded_details(&only_index, FAILING_COMMAND_INDEX_KEY),
+            vec![serde_json::json!(3)]
+        );
+
+        let user_payload = Payload::from(b"user");
+        let user_failure = Failure {
+            message: "boom".to_string(),
+            failure_info: Some(FailureInfo::ApplicationFailureInfo(
+                ApplicationFailureInfo {
+                    details: Some(Payloads {
+                        payloads: vec![user_payload.clone()],
+                    }),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        let with_index = with_failing_command(Some(user_failure), Some(0)).unwrap();
+        assert_eq!(with_index.message, "boom (failing command index: 0)");
+        assert_matches!(
+            with_index.failure_info,
+            Some(FailureInfo::ApplicationFailureInfo(ApplicationFailureInfo {
+                details: Some(ref d), ..
+            })) if d.payloads.len() == 2 && d.payloads[0] == user_payload
+        );
+        assert_eq!(
+            recorded_details(&with_index, FAILING_COMMAND_INDEX_KEY),
+            vec![serde_json::json!(0)]
+        );
+
+        // Other kinds of failure only get the note
+        let timeout = Failure {
+            failure_info: Some(FailureInfo::TimeoutFailureInfo(Default::default())),
+            ..Default::default()
+        };
+        let with_index = with_failing_command(Some(timeout.clone()), Some(1)).unwrap();
+        assert_eq!(with_index.failure_info, timeout.failure_info);
+    }
+
+    #[test]
+    fn cancel_reason_is_appended_to_details() {
+        let details = Payloads {
+            payloads: vec![Payload::from(b"lang's".as_slice())],
+        };
+        assert_eq!(with_cancel_reason(None, None), None);
+        assert_eq!(
+            with_cancel_reason(Some(details.clone()), None),
+            Some(details.clone())
+        );
+
+        let with_reason =
+            with_cancel_reason(Some(details.clone()), Some(ActivityCancelReason::TimedOut))
+                .unwrap();
+        assert_eq!(with_reason.payloads[0], details.payloads[0]);
+        let reason = &with_reason.payloads[1];
+        assert!(reason.metadata.contains_key(ACTIVITY_CANCEL_REASON_KEY));
+        assert_eq!(reason.data, b"\"TIMED_OUT\"");
+
+        let only_reason = with_cancel_reason(None, Some(ActivityCancelReason::NotFound)).unwrap();
+        assert_eq!(only_reason.payloads.len(), 1);
+    }
+
+    #[test]
+    fn with_sticky_sets_attributes() {
+        let completion = completion_with(vec![], &[])
+            .with_sticky(
+                "sticky".to_string(),
+                "normal".to_string(),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        let attrs = completion.sticky_attributes.unwrap();
+        assert_eq!(
+            attrs.worker_task_queue,
+            Some(TaskQueue {
+                name: "sticky".to_string(),
+                kind: TaskQueueKind::Sticky as i32,
+                normal_name: "normal".to_string(),
+            })
+        );
+        assert_eq!(attrs.schedule_to_start_timeout.unwrap().seconds, 5);
+
+        assert_eq!(
+            completion_with(vec![], &[])
+                .with_sticky("sticky".to_string(), "normal".to_string(), Duration::ZERO)
+                .unwrap_err(),
+            CompletionValidationError::NonPositiveStickyTimeout
+        );
+        assert_eq!(
+            completion_with(vec![], &[])
+                .with_sticky("sticky".to_string(), "normal".to_string(), Duration::MAX)
+                .unwrap_err(),
+            CompletionValidationError::StickyTimeoutTooLarge
+        );
+    }
+
+    #[test]
+    fn capabilities_refresh_every_n_polls() {
+        let trigger = CapabilitiesRefreshTrigger::new(3);
+        let fired: Vec<_> = (0..7).map(|_| trigger.record_poll()).collect();
+        assert_eq!(fired, [false, false, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn request_versioning_follows_refreshed_capabilities() {
+        let versioning = WorkerVersioning {
+            build_id: "build".to_string(),
+            binary_checksum_override: None,
+            use_versioning: false,
+        };
+        // Before the server upgrade the build id goes in the legacy checksum field
+        let old_caps = Capabilities::default();
+        assert_eq!(versioning.binary_checksum(&old_caps), "build");
+        assert_eq!(versioning.capabilities(&old_caps), None);
+        assert_eq!(versioning.stamp(&old_caps), None);
+
+        // Once refreshed capabilities say it's supported, it moves to the versioning fields
+        let new_caps = Capabilities {
+            build_id_based_versioning: true,
+            ..Default::default()
+        };
+        assert_eq!(versioning.binary_checksum(&new_caps), "");
+        assert_eq!(
+            versioning.capabilities(&new_caps),
+            Some(WorkerVersionCapabilities {
+                build_id: "build".to_string(),
+                use_versioning: false,
+            })
+        );
+        assert_eq!(
+            versioning.stamp(&new_caps).map(|s| s.build_id),
+            Some("build".to_string())
+        );
+    }
+
+    #[test]
+    fn completion_builder_defaults_unset_fields() {
+        let attrs = StickyExecutionAttributes {
+            worker_task_queue: Some(TaskQueue {
+                name: "sticky".to_string(),
+                kind: TaskQueueKind::Sticky as i32,
+                normal_name: "normal".to_string(),
+            }),
+            schedule_to_start_timeout: None,
+        };
+        let completion = WorkflowTaskCompletion::builder()
+            .command(timer("1"))
+            .command(timer("2"))
+            .query_response(QueryResult {
+                query_id: "q".to_string(),
+                variant: None,
+            })
+            .sticky(attrs.clone())
+            .message(ProtocolMessage {
+                id: "accept-update".to_string(),
+                ..Default::default()
+            })
+            .build(TaskToken(vec![1]));
+        assert_eq!(completion.commands, vec![timer("1"), timer("2")]);
+        assert_eq!(completion.messages[0].id, "accept-update");
+        assert_eq!(completion.query_responses.len(), 1);
+        assert_eq!(completion.sticky_attributes, Some(attrs));
+        assert!(!completion.return_new_workflow_task);
+        assert!(!completion.force_create_new_workflow_task);
+        assert_eq!(completion.sdk_metadata, Default::default());
+        assert_eq!(completion.metering_metadata, Default::default());
+        assert_eq!(completion.override_version_stamp, None);
+    }
+
+    #[test]
+    fn not_found_on_complete_is_a_timed_out_task() {
+        let err = CompleteWftError::from(tonic::Status::not_found("Workflow task not found."));
+        assert_matches!(err, CompleteWftError::WorkflowTaskTimedOut(_));
+        assert_eq!(tonic::Status::from(err).code(), tonic::Code::NotFound);
+        assert_matches!(
+            CompleteWftError::from(tonic::Status::internal("oops")),
+            CompleteWftError::TonicError(_)
+        );
+    }
 }
@@ -0,0 +1,373 @@
+//! Applies a worker's [PayloadCodec] to the payloads it exchanges with the server. Everything
+//! encoded on the way out is decoded when it comes back in history, so a codec only needs to
+//! round-trip its own output. Failures and markers are left alone in both directions.
+
+use std::collections::HashMap;
+use temporal_sdk_core_api::worker::{PayloadCodec, PayloadCodecError};
+use temporal_sdk_core_protos::temporal::api::{
+    command::v1::{command::Attributes, Command},
+    common::v1::{Payload, Payloads},
+    history::v1::{history_event, History},
+    query::v1::WorkflowQueryResult,
+    workflowservice::v1::{
+        PollActivityTaskQueueResponse, PollWorkflowTaskQueueResponse,
+        RespondWorkflowTaskCompletedResponse,
+    },
+};
+
+type CodecFn = fn(&dyn PayloadCodec, Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError>;
+
+/// Decodes the history and the arguments of all queries (legacy or otherwise) in a polled
+/// workflow task
+pub(super) fn decode_poll_response(
+    codec: &dyn PayloadCodec,
+    resp: &mut PollWorkflowTaskQueueResponse,
+) -> Result<(), PayloadCodecError> {
+    let decode: CodecFn = |c, p| c.decode(p);
+    if let Some(h) = resp.history.as_mut() {
+        decode_history(codec, h)?;
+    }
+    if let Some(q) = resp.query.as_mut() {
+        apply(codec, decode, &mut q.query_args)?;
+    }
+    for q in resp.queries.values_mut() {
+        apply(codec, decode, &mut q.query_args)?;
+    }
+    Ok(())
+}
+
+/// Decodes the user payloads carried by history events. These are the counterparts of what
+/// [encode_commands] and [encode_payloads] encode, plus whatever clients sent in (workflow and
+/// signal inputs), which are expected to have been encoded with the same codec.
+pub(super) fn decode_history(
+    codec: &dyn PayloadCodec,
+    history: &mut History,
+) -> Result<(), PayloadCodecError> {
+    use history_event::Attributes;
+
+    let decode: CodecFn = |c, p| c.decode(p);
+    for event in history.events.iter_mut() {
+        match event.attributes.as_mut() {
+            Some(Attributes::WorkflowExecutionStartedEventAttributes(a)) => {
+                apply(codec, decode, &mut a.input)?;
+                apply(codec, decode, &mut a.last_completion_result)?;
+            }
+            Some(Attributes::WorkflowExecutionSignaledEventAttributes(a)) => {
+                apply(codec, decode, &mut a.input)?
+            }
+            Some(Attributes::WorkflowExecutionCompletedEventAttributes(a)) => {
+                apply(codec, decode, &mut a.result)?
+            }
+            Some(Attributes::WorkflowExecutionCanceledEventAttributes(a)) => {
+                apply(codec, decode, &mut a.details)?
+            }
+            Some(Attributes::WorkflowExecutionContinuedAsNewEventAttributes(a)) => {
+                apply(codec, decode, &mut a.input)?;
+                apply(codec, decode, &mut a.last_completion_result)?;
+            }
+            Some(Attributes::ActivityTaskScheduledEventAttributes(a)) => {
+                apply(codec, decode, &mut a.input)?
+            }
+            Some(Attributes::ActivityTaskCompletedEventAttributes(a)) => {
+                apply(codec, decode, &mut a.result)?
+            }
+            Some(Attributes::ActivityTaskCanceledEventAttributes(a)) => {
+                apply(codec, decode, &mut a.details)?
+            }
+            Some(Attributes::StartChildWorkflowExecutionInitiatedEventAttributes(a)) => {
+                apply(codec, decode, &mut a.input)?
+            }
+            Some(Attributes::ChildWorkflowExecutionCompletedEventAttributes(a)) => {
+                apply(codec, decode, &mut a.result)?
+            }
+            Some(Attributes::ChildWorkflowExecutionCanceledEventAttributes(a)) => {
+                apply(codec, decode, &mut a.details)?
+            }
+            Some(Attributes::SignalExternalWorkflowExecutionInitiatedEventAttributes(a)) => {
+                apply(codec, decode, &mut a.input)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the input and last heartbeat details of a polled (or eagerly started) activity task
+pub(super) fn decode_activity_task(
+    codec: &dyn PayloadCodec,
+    task: &mut PollActivityTaskQueueResponse,
+) -> Result<(), PayloadCodecError> {
+    let decode: CodecFn = |c, p| c.decode(p);
+    apply(codec, decode, &mut task.input)?;
+    apply(codec, decode, &mut task.heartbeat_details)
+}
+
+/// Decodes the tasks the server handed back in response to a workflow task completion
+pub(super) fn decode_completion_response(
+    codec: &dyn PayloadCodec,
+    resp: &mut RespondWorkflowTaskCompletedResponse,
+) -> Result<(), PayloadCodecError> {
+    if let Some(wft) = resp.workflow_task.as_mut() {
+        decode_poll_response(codec, wft)?;
+    }
+    for task in resp.activity_tasks.iter_mut() {
+        decode_activity_task(codec, task)?;
+    }
+    Ok(())
+}
+
+/// Encodes the user payloads carried by outgoing commands. Markers are skipped since their
+/// details are read back by core itself during replay.
+pub(super) fn encode_commands(
+    codec: &dyn PayloadCodec,
+    commands: &mut [Command],
+) -> Result<(), PayloadCodecError> {
+    let encode: CodecFn = |c, p| c.encode(p);
+    for command in commands {
+        match command.attributes.as_mut() {
+            Some(Attributes::ScheduleActivityTaskCommandAttributes(a)) => {
+                apply(codec, encode, &mut a.input)?
+            }
+            Some(Attributes::CompleteWorkflowExecutionCommandAttributes(a)) => {
+                apply(codec, encode, &mut a.result)?
+            }
+            Some(Attributes::CancelWorkflowExecutionCommandAttributes(a)) => {
+                apply(codec, encode, &mut a.details)?
+            }
+            Some(Attributes::ContinueAsNewWorkflowExecutionCommandAttributes(a)) => {
+                apply(codec, encode, &mut a.input)?;
+                apply(codec, encode, &mut a.last_completion_result)?;
+            }
+            Some(Attributes::StartChildWorkflowExecutionCommandAttributes(a)) => {
+                apply(codec, encode, &mut a.input)?
+            }
+            Some(Attributes::SignalExternalWorkflowExecutionCommandAttributes(a)) => {
+                apply(codec, encode, &mut a.input)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the answers to queries being responded to
+pub(super) fn encode_query_results(
+    codec: &dyn PayloadCodec,
+    results: &mut HashMap<String, WorkflowQueryResult>,
+) -> Result<(), PayloadCodecError> {
+    for r in results.values_mut() {
+        apply(codec, |c, p| c.encode(p), &mut r.answer)?;
+    }
+    Ok(())
+}
+
+/// Encodes payloads sent to the server outside of commands, like legacy query answers, activity
+/// results and heartbeat details, or signal inputs
+pub(super) fn encode_payloads(
+    codec: &dyn PayloadCodec,
+    answer: &mut Option<Payloads>,
+) -> Result<(), PayloadCodecError> {
+    apply(codec, |c, p| c.encode(p), answer)
+}
+
+fn apply(
+    codec: &dyn PayloadCodec,
+    f: CodecFn,
+    payloads: &mut Option<Payloads>,
+) -> Result<(), PayloadCodecError> {
+    if let Some(p) = payloads.as_mut() {
+        p.payloads = f(codec, std::mem::take(&mut p.payloads))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temporal_sdk_core_protos::temporal::api::{
+        command::v1::{
+            RecordMarkerCommandAttributes, ScheduleActivityTaskCommandAttributes,
+            StartChildWorkflowExecutionCommandAttributes,
+        },
+        enums::v1::{CommandType, EventType},
+        history::v1::{
+            ActivityTaskCompletedEventAttributes, HistoryEvent,
+            WorkflowExecutionStartedEventAttributes,
+        },
+        query::v1::WorkflowQuery,
+    };
+
+    /// Reverses payload data on encode, and fails to decode anything that isn't reversed
+    #[derive(Debug)]
+    struct ReversingCodec;
+    impl PayloadCodec for ReversingCodec {
+        fn encode(&self, payloads: Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError> {
+            Ok(payloads
+                .into_iter()
+                .map(|mut p| {
+                    p.data.reverse();
+                    p
+                })
+                .collect())
+        }
+        fn decode(&self, payloads: Vec<Payload>) -> Result<Vec<Payload>, PayloadCodecError> {
+            payloads
+                .into_iter()
+                .map(|mut p| {
+                    if p.data.first() != Some(&b'>') {
+                        return Err(PayloadCodecError("not encoded".to_string()));
+                    }
+                    p.data.reverse();
+                    Ok(p)
+                })
+                .collect()
+        }
+    }
+
+    fn payloads(data: &[u8]) -> Option<Payloads> {
+        Some(Payloads {
+            payloads: vec![Payload {
+                data: data.to_vec(),
+                ..Default::default()
+            }],
+        })
+    }
+
+    #[test]
+    fn encodes_command_inputs_but_not_markers() {
+        let mut commands = vec![
+            Command {
+                command_type: CommandType::ScheduleActivityTask as i32,
+                attributes: Some(Attributes::ScheduleActivityTaskCommandAttributes(
+                    ScheduleActivityTaskCommandAttributes {
+                        input: payloads(b"abc"),
+                        ..Default::default()
+                    },
+                )),
+            },
+            Command {
+                command_type: CommandType::RecordMarker as i32,
+                attributes: Some(Attributes::RecordMarkerCommandAttributes(
+                    RecordMarkerCommandAttributes {
+                        details: HashMap::from([("data".to_string(), payloads(b"abc").unwrap())]),
+                        ..Default::default()
+                    },
+                )),
+            },
+        ];
+        encode_commands(&ReversingCodec, &mut commands).unwrap();
+        assert_matches!(
+            &commands[0].attributes,
+            Some(Attributes::ScheduleActivityTaskCommandAttributes(a))
+                if a.input == payloads(b"cba")
+        );
+        assert_matches!(
+            &commands[1].attributes,
+            Some(Attributes::RecordMarkerCommandAttributes(a))
+                if a.details["data"] == payloads(b"abc").unwrap()
+        );
+    }
+
+    #[test]
+    fn decodes_query_args() {
+        let mut resp = PollWorkflowTaskQueueResponse {
+            query: Some(WorkflowQuery {
+                query_args: payloads(b">1"),
+                ..Default::default()
+            }),
+            queries: HashMap::from([(
+                "q".to_string(),
+                WorkflowQuery {
+                    query_args: payloads(b">2"),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        decode_poll_response(&ReversingCodec, &mut resp).unwrap();
+        assert_eq!(resp.query.unwrap().query_args, payloads(b"1>"));
+        assert_eq!(resp.queries["q"].query_args, payloads(b"2>"));
+
+        let mut bad = PollWorkflowTaskQueueResponse {
+            query: Some(WorkflowQuery {
+                query_args: payloads(b"plain"),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(decode_poll_response(&ReversingCodec, &mut bad).is_err());
+    }
+
+    #[test]
+    fn child_input_round_trips_to_child_history() {
+        let mut commands = vec![Command {
+            command_type: CommandType::StartChildWorkflowExecution as i32,
+            attributes: Some(Attributes::StartChildWorkflowExecutionCommandAttributes(
+                StartChildWorkflowExecutionCommandAttributes {
+                    input: payloads(b"abc>"),
+                    ..Default::default()
+                },
+            )),
+        }];
+        encode_commands(&ReversingCodec, &mut commands).unwrap();
+        let sent = match commands.pop().unwrap().attributes {
+            Some(Attributes::StartChildWorkflowExecutionCommandAttributes(a)) => a.input,
+            _ => unreachable!(),
+        };
+        assert_eq!(sent, payloads(b">cba"));
+
+        // The server starts the child with the input as it was sent, and the child's first poll
+        // hands it back in the started event
+        let mut child_poll = PollWorkflowTaskQueueResponse {
+            history: Some(History {
+                events: vec![HistoryEvent {
+                    event_id: 1,
+                    event_type: EventType::WorkflowExecutionStarted as i32,
+                    attributes: Some(
+                        history_event::Attributes::WorkflowExecutionStartedEventAttributes(
+                            WorkflowExecutionStartedEventAttributes {
+                                input: sent,
+                                ..Default::default()
+                            },
+                        ),
+                    ),
+                    ..Default::default()
+                }],
+            }),
+            ..Default::default()
+        };
+        decode_poll_response(&ReversingCodec, &mut child_poll).unwrap();
+        assert_matches!(
+            &child_poll.history.unwrap().events[0].attributes,
+            Some(history_event::Attributes::WorkflowExecutionStartedEventAttributes(a))
+                if a.input == payloads(b"abc>")
+        );
+    }
+
+    #[test]
+    fn activity_result_round_trips_to_history() {
+        let mut result = payloads(b"done>");
+        encode_payloads(&ReversingCodec, &mut result).unwrap();
+        let mut history = History {
+            events: vec![HistoryEvent {
+                event_id: 7,
+                event_type: EventType::ActivityTaskCompleted as i32,
+                attributes: Some(
+                    history_event::Attributes::ActivityTaskCompletedEventAttributes(
+                        ActivityTaskCompletedEventAttributes {
+                            result,
+                            ..Default::default()
+                        },
+                    ),
+                ),
+                ..Default::default()
+            }],
+        };
+        decode_history(&ReversingCodec, &mut history).unwrap();
+        assert_matches!(
+            &history.events[0].attributes,
+            Some(history_event::Attributes::ActivityTaskCompletedEventAttributes(a))
+                if a.result == payloads(b"done>")
+        );
+    }
+}
@@ -18,15 +18,172 @@ pub(crate) static DEFAULT_TEST_CAPABILITIES: &Capabilities = &Capabilities {
 pub(crate) fn mock_workflow_client() -> MockWorkerClient {
     let mut r = MockWorkerClient::new();
     r.expect_capabilities()
-        .returning(|| Some(DEFAULT_TEST_CAPABILITIES));
+        .returning(|| Some(DEFAULT_TEST_CAPABILITIES.clone()));
+    r.expect_capabilities_snapshot()
+        .returning(|| DEFAULT_TEST_CAPABILITIES.clone());
     r
 }
 
+#[cfg(test)]
+/// Start building a mock client which responds to every RPC successfully with an empty response.
+/// See [HappyPathClientBuilder].
+pub(crate) fn happy_path_client() -> HappyPathClientBuilder {
+    HappyPathClientBuilder::default()
+}
+
+#[cfg(test)]
+type MockCustomizer = Box<dyn FnOnce(&mut MockWorkerClient)>;
+
+#[cfg(test)]
+/// Builds a [MockWorkerClient] with happy-path defaults for every method. Polls hand out any
+/// queued responses in order before falling back to empty ones. Customizations added with
+/// [HappyPathClientBuilder::customize] are set up before the defaults, so mockall prefers them
+/// until they are saturated. Calls past that fall through to the defaults, so a `times` bound on
+/// a customization only enforces the minimum; tests counting calls exactly should use
+/// [mock_workflow_client] instead.
+#[derive(Default)]
+pub(crate) struct HappyPathClientBuilder {
+    wft_polls: std::collections::VecDeque<PollWorkflowTaskQueueResponse>,
+    act_polls: std::collections::VecDeque<PollActivityTaskQueueResponse>,
+    customizers: Vec<MockCustomizer>,
+}
+
+#[cfg(test)]
+impl HappyPathClientBuilder {
+    /// Queue responses to be returned by workflow task polls (sticky or not)
+    pub(crate) fn wft_polls(
+        mut self,
+        resps: impl IntoIterator<Item = PollWorkflowTaskQueueResponse>,
+    ) -> Self {
+        self.wft_polls.extend(resps);
+        self
+    }
+
+    /// Queue responses to be returned by activity task polls (of any queue kind)
+    pub(crate) fn activity_polls(
+        mut self,
+        resps: impl IntoIterator<Item = PollActivityTaskQueueResponse>,
+    ) -> Self {
+        self.act_polls.extend(resps);
+        self
+    }
+
+    /// Set up custom expectations, which take precedence over the defaults
+    pub(crate) fn customize(mut self, f: impl FnOnce(&mut MockWorkerClient) + 'static) -> Self {
+        self.customizers.push(Box::new(f));
+        self
+    }
+
+    pub(crate) fn build(self) -> MockWorkerClient {
+        let mut r = mock_workflow_client();
+        for customizer in self.customizers {
+            customizer(&mut r);
+        }
+
+        let wft_polls = Arc::new(Mutex::new(self.wft_polls));
+        let next_wft = move || -> Result<_, PollError> {
+            Ok(wft_polls.lock().pop_front().unwrap_or_default())
+        };
+        let next_wft_sticky = next_wft.clone();
+        let next_wft_meta = next_wft.clone();
+        r.expect_poll_workflow_task()
+            .returning(move |_, _, _| next_wft());
+        r.expect_poll_workflow_task_sticky()
+            .returning(move |_, _, _| next_wft_sticky());
+        r.expect_poll_workflow_task_with_meta()
+            .returning(move |_, _, _, _| next_wft_meta().map(empty_meta));
+        let act_polls = Arc::new(Mutex::new(self.act_polls));
+        let kinded_act_polls = act_polls.clone();
+        r.expect_poll_activity_task()
+            .returning(move |_, _, _, _, _| Ok(act_polls.lock().pop_front().unwrap_or_default()));
+        r.expect_poll_activity_task_of_kind()
+            .returning(move |_, _, _, _, _, _| {
+                Ok(kinded_act_polls.lock().pop_front().unwrap_or_default())
+            });
+
+        r.expect_complete_workflow_task()
+            .returning(|_| Ok(Default::default()));
+        r.expect_complete_workflow_task_with_meta()
+            .returning(|_| Ok(empty_meta(Default::default())));
+        r.expect_complete_activity_task()
+            .returning(|_, _| Ok(Default::default()));
+        r.expect_record_activity_heartbeat()
+            .returning(|_, _| Ok(Default::default()));
+        r.expect_heartbeat_and_check_cancel()
+            .returning(|_, _| Ok(false));
+        r.expect_record_activity_heartbeats()
+            .returning(|beats| beats.iter().map(|_| Ok(Default::default())).collect());
+        r.expect_cancel_activity_task()
+            .returning(|_, _, _| Ok(Default::default()));
+        r.expect_fail_activity_task()
+            .returning(|_, _, _| Ok(Default::default()));
+        r.expect_complete_activity_task_by_id()
+            .returning(|_, _, _, _| Ok(Default::default()));
+        r.expect_fail_activity_task_by_id()
+            .returning(|_, _, _, _, _| Ok(Default::default()));
+        r.expect_cancel_activity_task_by_id()
+            .returning(|_, _, _, _| Ok(Default::default()));
+        r.expect_fail_workflow_task()
+            .returning(|_, _, _, _, _| Ok(Default::default()));
+        r.expect_get_workflow_execution_history()
+            .returning(|_, _, _, _, _| Ok(Default::default()));
+        r.expect_get_workflow_execution_history_reverse()
+            .returning(|_, _, _| Ok(Default::default()));
+        r.expect_respond_legacy_query()
+            .returning(|_, _| Ok(Default::default()));
+        r.expect_respond_legacy_queries()
+            .returning(|resps| resps.iter().map(|_| Ok(Default::default())).collect());
+        r.expect_describe_task_queue()
+            .returning(|_, _| Ok(Default::default()));
+        r.expect_reset_sticky_task_queue()
+            .returning(|_| Ok(Default::default()));
+        r.expect_disclaim_sticky().returning(|_| Ok(()));
+        r.expect_delete_workflow_execution()
+            .returning(|_| Ok(Default::default()));
+        r.expect_terminate_workflow_execution()
+            .returning(|_, _, _| Ok(Default::default()));
+        r.expect_reset_workflow_execution()
+            .returning(|_, _, _, _| Ok(Default::default()));
+        r.expect_signal_workflow_execution()
+            .returning(|_, _, _| Ok(Default::default()));
+        r.expect_count_workflow_executions()
+            .returning(|_| Ok(Default::default()));
+        r.expect_list_open_workflow_executions()
+            .returning(|_, _, _| Ok(Default::default()));
+        r.expect_get_worker_build_id_compatibility()
+            .returning(|_| Ok(Default::default()));
+        r.expect_update_worker_build_id_compatibility()
+            .returning(|_, _| Ok(Default::default()));
+        r.expect_check_health()
+            .returning(|| Ok(HealthStatus::Healthy));
+        r.expect_warmup().returning(|| Ok(()));
+        r.expect_observed_server_time().returning(|| None);
+        r.expect_completion_backpressure().returning(|| 0.0);
+        r.expect_connection_state()
+            .returning(|| ConnectionState::Ready {
+                last_success: Instant::now(),
+            });
+        r.expect_refresh_capabilities()
+            .returning(|| Ok(DEFAULT_TEST_CAPABILITIES.clone()));
+        r
+    }
+}
+
+#[cfg(test)]
+fn empty_meta<T>(body: T) -> WithMeta<T> {
+    WithMeta {
+        body,
+        metadata: Default::default(),
+    }
+}
+
 /// Create a mock manual client primed with basic necessary expectations
 pub(crate) fn mock_manual_workflow_client() -> MockManualWorkerClient {
     let mut r = MockManualWorkerClient::new();
     r.expect_capabilities()
-        .returning(|| Some(DEFAULT_TEST_CAPABILITIES));
+        .returning(|| Some(DEFAULT_TEST_CAPABILITIES.clone()));
+    r.expect_capabilities_snapshot()
+        .returning(|| DEFAULT_TEST_CAPABILITIES.clone());
     r
 }
 
@@ -37,71 +194,785 @@ mockall::mock! {
     pub(crate) ManualWorkerClient {}
     #[allow(unused)]
     impl WorkerClient for ManualWorkerClient {
-        fn poll_workflow_task<'a, 'b>(&'a self, task_queue: TaskQueue)
-            -> impl Future<Output = Result<PollWorkflowTaskQueueResponse>> + Send + 'b
+        fn poll_workflow_task<'a, 'b>(
+            &'a self,
+            task_queue: TaskQueue,
+            identity_override: Option<String>,
+            cancel: CancellationToken,
+        ) -> impl Future<Output = Result<PollWorkflowTaskQueueResponse, PollError>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn poll_workflow_task_with_meta<'a, 'b>(
+            &'a self,
+            task_queue: TaskQueue,
+            identity_override: Option<String>,
+            poll_timeout_override: Option<Duration>,
+            cancel: CancellationToken,
+        ) -> impl Future<Output = Result<WithMeta<PollWorkflowTaskQueueResponse>, PollError>>
+            + Send + 'b
             where 'a: 'b, Self: 'b;
 
-        fn poll_activity_task<'a, 'b>(&self, task_queue: String, max_tasks_per_sec: Option<f64>)
-            -> impl Future<Output = Result<PollActivityTaskQueueResponse>> + Send + 'b
+        fn poll_workflow_task_sticky<'a, 'b>(
+            &'a self,
+            sticky_queue_name: String,
+            normal_name: String,
+            cancel: CancellationToken,
+        ) -> impl Future<Output = Result<PollWorkflowTaskQueueResponse, PollError>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn poll_activity_task<'a, 'b>(
+            &self,
+            task_queue: String,
+            max_tasks_per_sec: Option<f64>,
+            build_id_affinity: Option<String>,
+            identity_override: Option<String>,
+            cancel: CancellationToken,
+        ) -> impl Future<Output = Result<PollActivityTaskQueueResponse, PollError>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn poll_activity_task_of_kind<'a, 'b>(
+            &self,
+            task_queue: TaskQueue,
+            max_tasks_per_sec: Option<f64>,
+            build_id_affinity: Option<String>,
+            identity_override: Option<String>,
+            poll_timeout_override: Option<Duration>,
+            cancel: CancellationToken,
+        ) -> impl Future<Output = Result<PollActivityTaskQueueResponse, PollError>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
         fn complete_workflow_task<'a, 'b>(
             &self,
             request: WorkflowTaskCompletion,
-        ) -> impl Future<Output = Result<RespondWorkflowTaskCompletedResponse>> + Send + 'b
+        ) -> impl Future<Output = Result<RespondWorkflowTaskCompletedResponse, CompleteWftError>>
+            + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn complete_workflow_task_with_meta<'a, 'b>(
+            &self,
+            request: WorkflowTaskCompletion,
+        ) -> impl Future<
+            Output = Result<WithMeta<RespondWorkflowTaskCompletedResponse>, CompleteWftError>,
+        > + Send + 'b
             where 'a: 'b, Self: 'b;
 
         fn complete_activity_task<'a, 'b>(
             &self,
-            task_token: TaskToken,
+            task_token: ActivityTaskToken,
             result: Option<Payloads>,
         ) -> impl Future<Output = Result<RespondActivityTaskCompletedResponse>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
         fn cancel_activity_task<'a, 'b>(
             &self,
-            task_token: TaskToken,
+            task_token: ActivityTaskToken,
             details: Option<Payloads>,
+            reason: Option<ActivityCancelReason>,
         ) -> impl Future<Output = Result<RespondActivityTaskCanceledResponse>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
         fn fail_activity_task<'a, 'b>(
             &self,
-            task_token: TaskToken,
+            task_token: ActivityTaskToken,
             failure: Option<Failure>,
+            last_heartbeat_details: Option<Payloads>,
         ) -> impl Future<Output = Result<RespondActivityTaskFailedResponse>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
+        fn complete_activity_task_by_id<'a, 'b>(
+            &self,
+            workflow_id: String,
+            run_id: Option<String>,
+            activity_id: String,
+            result: Option<Payloads>,
+        ) -> impl Future<Output = Result<RespondActivityTaskCompletedByIdResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn fail_activity_task_by_id<'a, 'b>(
+            &self,
+            workflow_id: String,
+            run_id: Option<String>,
+            activity_id: String,
+            failure: Option<Failure>,
+            last_heartbeat_details: Option<Payloads>,
+        ) -> impl Future<Output = Result<RespondActivityTaskFailedByIdResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn cancel_activity_task_by_id<'a, 'b>(
+            &self,
+            workflow_id: String,
+            run_id: Option<String>,
+            activity_id: String,
+            details: Option<Payloads>,
+        ) -> impl Future<Output = Result<RespondActivityTaskCanceledByIdResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
         fn fail_workflow_task<'a, 'b>(
             &self,
-            task_token: TaskToken,
+            task_token: WorkflowTaskToken,
             cause: WorkflowTaskFailedCause,
             failure: Option<Failure>,
+            failing_command_index: Option<usize>,
+            non_determinism: Option<NonDeterminismDetail>,
         ) -> impl Future<Output = Result<RespondWorkflowTaskFailedResponse>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
         fn record_activity_heartbeat<'a, 'b>(
            &self,
-           task_token: TaskToken,
+           task_token: ActivityTaskToken,
            details: Option<Payloads>,
-        ) -> impl Future<Output = Result<RecordActivityTaskHeartbeatResponse>> + Send + 'b
+        ) -> impl Future<Output = Result<RecordActivityTaskHeartbeatResponse, HeartbeatError>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn heartbeat_and_check_cancel<'a, 'b>(
+            &self,
+            task_token: ActivityTaskToken,
+            details: Option<Payloads>,
+        ) -> impl Future<Output = Result<bool>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn record_activity_heartbeats<'a, 'b>(
+            &self,
+            beats: Vec<(ActivityTaskToken, Option<Payloads>)>,
+        ) -> impl Future<Output = Vec<Result<RecordActivityTaskHeartbeatResponse, HeartbeatError>>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
         fn get_workflow_execution_history<'a, 'b>(
             &self,
             workflow_id: String,
             run_id: Option<String>,
-            page_token: Vec<u8>
+            page_token: Vec<u8>,
+            wait_new_event: bool,
+            filter_type: HistoryEventFilterType,
         ) -> impl Future<Output = Result<GetWorkflowExecutionHistoryResponse>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
+        fn get_workflow_execution_history_reverse<'a, 'b>(
+            &self,
+            workflow_id: String,
+            run_id: Option<String>,
+            page_token: Vec<u8>,
+        ) -> impl Future<Output = Result<GetWorkflowExecutionHistoryReverseResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
         fn respond_legacy_query<'a, 'b>(
             &self,
-            task_token: TaskToken,
+            task_token: WorkflowTaskToken,
             query_result: QueryResult,
         ) -> impl Future<Output = Result<RespondQueryTaskCompletedResponse>> + Send + 'b
             where 'a: 'b, Self: 'b;
 
-        fn capabilities(&self) -> Option<&'static get_system_info_response::Capabilities>;
+        fn respond_legacy_queries<'a, 'b>(
+            &self,
+            responses: Vec<(WorkflowTaskToken, QueryResult)>,
+        ) -> impl Future<Output = Vec<Result<RespondQueryTaskCompletedResponse>>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn describe_task_queue<'a, 'b>(
+            &self,
+            task_queue: String,
+            kind: TaskQueueKind,
+        ) -> impl Future<Output = Result<DescribeTaskQueueResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn get_worker_build_id_compatibility<'a, 'b>(
+            &self,
+            task_queue: String,
+        ) -> impl Future<Output = Result<GetWorkerBuildIdCompatibilityResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn update_worker_build_id_compatibility<'a, 'b>(
+            &self,
+            task_queue: String,
+            operation: BuildIdCompatibilityOperation,
+        ) -> impl Future<Output = Result<UpdateWorkerBuildIdCompatibilityResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn reset_sticky_task_queue<'a, 'b>(
+            &self,
+            execution: WorkflowExecution,
+        ) -> impl Future<Output = Result<ResetStickyTaskQueueResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn disclaim_sticky<'a, 'b>(
+            &self,
+            execution: WorkflowExecution,
+        ) -> impl Future<Output = Result<()>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn delete_workflow_execution<'a, 'b>(
+            &self,
+            execution: WorkflowExecution,
+        ) -> impl Future<Output = Result<DeleteWorkflowExecutionResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn terminate_workflow_execution<'a, 'b>(
+            &self,
+            execution: WorkflowExecution,
+            reason: String,
+            details: Option<Payloads>,
+        ) -> impl Future<Output = Result<TerminateWorkflowExecutionResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn reset_workflow_execution<'a, 'b>(
+            &self,
+            execution: WorkflowExecution,
+            reset_to_event_id: i64,
+            reason: String,
+            reset_reapply_type: ResetReapplyType,
+        ) -> impl Future<Output = Result<ResetWorkflowExecutionResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn signal_workflow_execution<'a, 'b>(
+            &self,
+            execution: WorkflowExecution,
+            signal_name: String,
+            input: Option<Payloads>,
+        ) -> impl Future<Output = Result<SignalWorkflowExecutionResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn count_workflow_executions<'a, 'b>(
+            &self,
+            query: String,
+        ) -> impl Future<Output = Result<CountWorkflowExecutionsResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn list_open_workflow_executions<'a, 'b>(
+            &self,
+            start_time_filter: Option<StartTimeFilter>,
+            type_filter: Option<WorkflowTypeFilter>,
+            page_token: Vec<u8>,
+        ) -> impl Future<Output = Result<ListOpenWorkflowExecutionsResponse>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn check_health<'a, 'b>(
+            &self,
+        ) -> impl Future<Output = Result<HealthStatus>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn refresh_capabilities<'a, 'b>(
+            &self,
+        ) -> impl Future<Output = Result<get_system_info_response::Capabilities>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn warmup<'a, 'b>(&self) -> impl Future<Output = Result<()>> + Send + 'b
+            where 'a: 'b, Self: 'b;
+
+        fn capabilities(&self) -> Option<get_system_info_response::Capabilities>;
+
+        fn capabilities_snapshot(&self) -> get_system_info_response::Capabilities;
+
+        fn observed_server_time(&self) -> Option<SystemTime>;
+
+        fn completion_backpressure(&self) -> f64;
+
+        fn connection_state(&self) -> ConnectionState;
+    }
+}
+
+#[cfg(test)]
+/// Wrap `inner` so that each of its RPCs first fails, with probability `failure_rate` (between 0
+/// and 1), with a status whose code is picked at random from `errors`, instead of being made.
+/// Failures are decided by an RNG seeded with `seed`, so a test making the same calls in the same
+/// order sees the same failures every run. Batch calls roll for each item separately, and
+/// methods which make no RPC never fail.
+pub(crate) fn flaky_client(
+    inner: Arc<dyn WorkerClient>,
+    failure_rate: f64,
+    errors: Vec<tonic::Code>,
+    seed: u64,
+) -> FlakyClient {
+    assert!(
+        (0.0..=1.0).contains(&failure_rate),
+        "failure rate must be between 0 and 1"
+    );
+    assert!(
+        failure_rate == 0.0 || !errors.is_empty(),
+        "must have errors to fail with"
+    );
+    use rand::SeedableRng;
+
+    FlakyClient {
+        inner,
+        failure_rate,
+        errors,
+        rng: Mutex::new(rand::rngs::StdRng::seed_from_u64(seed)),
+    }
+}
+
+#[cfg(test)]
+/// A [WorkerClient] which fails some RPCs before delegating to another. See [flaky_client].
+pub(crate) struct FlakyClient {
+    inner: Arc<dyn WorkerClient>,
+    failure_rate: f64,
+    errors: Vec<tonic::Code>,
+    rng: Mutex<rand::rngs::StdRng>,
+}
+
+#[cfg(test)]
+impl FlakyClient {
+    fn maybe_fail(&self) -> Result<()> {
+        use rand::{seq::SliceRandom, Rng};
+
+        let mut rng = self.rng.lock();
+        if rng.gen_bool(self.failure_rate) {
+            let code = *self.errors.choose(&mut *rng).expect("errors are not empty");
+            return Err(tonic::Status::new(code, "injected transient failure"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+macro_rules! flaky {
+    ($self:ident.$method:ident($($arg:expr),*)) => {{
+        $self.maybe_fail()?;
+        $self.inner.$method($($arg),*).await
+    }};
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl WorkerClient for FlakyClient {
+    async fn poll_workflow_task(
+        &self,
+        task_queue: TaskQueue,
+        identity_override: Option<String>,
+        cancel: CancellationToken,
+    ) -> Result<PollWorkflowTaskQueueResponse, PollError> {
+        flaky!(self.poll_workflow_task(task_queue, identity_override, cancel))
+    }
+
+    async fn poll_workflow_task_with_meta(
+        &self,
+        task_queue: TaskQueue,
+        identity_override: Option<String>,
+        poll_timeout_override: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<WithMeta<PollWorkflowTaskQueueResponse>, PollError> {
+        flaky!(self.poll_workflow_task_with_meta(
+            task_queue,
+            identity_override,
+            poll_timeout_override,
+            cancel
+        ))
+    }
+
+    async fn poll_workflow_task_sticky(
+        &self,
+        sticky_queue_name: String,
+        normal_name: String,
+        cancel: CancellationToken,
+    ) -> Result<PollWorkflowTaskQueueResponse, PollError> {
+        flaky!(self.poll_workflow_task_sticky(sticky_queue_name, normal_name, cancel))
+    }
+
+    async fn poll_activity_task(
+        &self,
+        task_queue: String,
+        max_tasks_per_sec: Option<f64>,
+        build_id_affinity: Option<String>,
+        identity_override: Option<String>,
+        cancel: CancellationToken,
+    ) -> Result<PollActivityTaskQueueResponse, PollError> {
+        flaky!(self.poll_activity_task(
+            task_queue,
+            max_tasks_per_sec,
+            build_id_affinity,
+            identity_override,
+            cancel
+        ))
+    }
+
+    async fn poll_activity_task_of_kind(
+        &self,
+        task_queue: TaskQueue,
+        max_tasks_per_sec: Option<f64>,
+        build_id_affinity: Option<String>,
+        identity_override: Option<String>,
+        poll_timeout_override: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<PollActivityTaskQueueResponse, PollError> {
+        flaky!(self.poll_activity_task_of_kind(
+            task_queue,
+            max_tasks_per_sec,
+            build_id_affinity,
+            identity_override,
+            poll_timeout_override,
+            cancel
+        ))
+    }
+
+    async fn complete_workflow_task(
+        &self,
+        request: WorkflowTaskCompletion,
+    ) -> Result<RespondWorkflowTaskCompletedResponse, CompleteWftError> {
+        flaky!(self.complete_workflow_task(request))
+    }
+
+    async fn complete_workflow_task_with_meta(
+        &self,
+        request: WorkflowTaskCompletion,
+    ) -> Result<WithMeta<RespondWorkflowTaskCompletedResponse>, CompleteWftError> {
+        flaky!(self.complete_workflow_task_with_meta(request))
+    }
+
+    async fn complete_activity_task(
+        &self,
+        task_token: ActivityTaskToken,
+        result: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCompletedResponse> {
+        flaky!(self.complete_activity_task(task_token, result))
+    }
+
+    async fn record_activity_heartbeat(
+        &self,
+        task_token: ActivityTaskToken,
+        details: Option<Payloads>,
+    ) -> Result<RecordActivityTaskHeartbeatResponse, HeartbeatError> {
+        flaky!(self.record_activity_heartbeat(task_token, details))
+    }
+
+    async fn heartbeat_and_check_cancel(
+        &self,
+        task_token: ActivityTaskToken,
+        details: Option<Payloads>,
+    ) -> Result<bool> {
+        flaky!(self.heartbeat_and_check_cancel(task_token, details))
+    }
+
+    async fn record_activity_heartbeats(
+        &self,
+        beats: Vec<(ActivityTaskToken, Option<Payloads>)>,
+    ) -> Vec<Result<RecordActivityTaskHeartbeatResponse, HeartbeatError>> {
+        let mut results = vec![];
+        for (task_token, details) in beats {
+            results.push(self.record_activity_heartbeat(task_token, details).await);
+        }
+        results
+    }
+
+    async fn cancel_activity_task(
+        &self,
+        task_token: ActivityTaskToken,
+        details: Option<Payloads>,
+        reason: Option<ActivityCancelReason>,
+    ) -> Result<RespondActivityTaskCanceledResponse> {
+        flaky!(self.cancel_activity_task(task_token, details, reason))
+    }
+
+    async fn fail_activity_task(
+        &self,
+        task_token: ActivityTaskToken,
+        failure: Option<Failure>,
+        last_heartbeat_details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskFailedResponse> {
+        flaky!(self.fail_activity_task(task_token, failure, last_heartbeat_details))
+    }
+
+    async fn complete_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        result: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCompletedByIdResponse> {
+        flaky!(self.complete_activity_task_by_id(workflow_id, run_id, activity_id, result))
+    }
+
+    async fn fail_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        failure: Option<Failure>,
+        last_heartbeat_details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskFailedByIdResponse> {
+        flaky!(self.fail_activity_task_by_id(
+            workflow_id,
+            run_id,
+            activity_id,
+            failure,
+            last_heartbeat_details
+        ))
+    }
+
+    async fn cancel_activity_task_by_id(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        activity_id: String,
+        details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCanceledByIdResponse> {
+        flaky!(self.cancel_activity_task_by_id(workflow_id, run_id, activity_id, details))
+    }
+
+    async fn fail_workflow_task(
+        &self,
+        task_token: WorkflowTaskToken,
+        cause: WorkflowTaskFailedCause,
+        failure: Option<Failure>,
+        failing_command_index: Option<usize>,
+        non_determinism: Option<NonDeterminismDetail>,
+    ) -> Result<RespondWorkflowTaskFailedResponse> {
+        flaky!(self.fail_workflow_task(
+            task_token,
+            cause,
+            failure,
+            failing_command_index,
+            non_determinism
+        ))
+    }
+
+    async fn get_workflow_execution_history(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        page_token: Vec<u8>,
+        wait_new_event: bool,
+        filter_type: HistoryEventFilterType,
+    ) -> Result<GetWorkflowExecutionHistoryResponse> {
+        flaky!(self.get_workflow_execution_history(
+            workflow_id,
+            run_id,
+            page_token,
+            wait_new_event,
+            filter_type
+        ))
+    }
+
+    async fn get_workflow_execution_history_reverse(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        page_token: Vec<u8>,
+    ) -> Result<GetWorkflowExecutionHistoryReverseResponse> {
+        flaky!(self.get_workflow_execution_history_reverse(workflow_id, run_id, page_token))
+    }
+
+    async fn respond_legacy_query(
+        &self,
+        task_token: WorkflowTaskToken,
+        query_result: QueryResult,
+    ) -> Result<RespondQueryTaskCompletedResponse> {
+        flaky!(self.respond_legacy_query(task_token, query_result))
+    }
+
+    async fn respond_legacy_queries(
+        &self,
+        responses: Vec<(WorkflowTaskToken, QueryResult)>,
+    ) -> Vec<Result<RespondQueryTaskCompletedResponse>> {
+        let mut results = vec![];
+        for (task_token, query_result) in responses {
+            results.push(self.respond_legacy_query(task_token, query_result).await);
+        }
+        results
+    }
+
+    async fn describe_task_queue(
+        &self,
+        task_queue: String,
+        kind: TaskQueueKind,
+    ) -> Result<DescribeTaskQueueResponse> {
+        flaky!(self.describe_task_queue(task_queue, kind))
+    }
+
+    async fn get_worker_build_id_compatibility(
+        &self,
+        task_queue: String,
+    ) -> Result<GetWorkerBuildIdCompatibilityResponse> {
+        flaky!(self.get_worker_build_id_compatibility(task_queue))
+    }
+
+    async fn update_worker_build_id_compatibility(
+        &self,
+        task_queue: String,
+        operation: BuildIdCompatibilityOperation,
+    ) -> Result<UpdateWorkerBuildIdCompatibilityResponse> {
+        flaky!(self.update_worker_build_id_compatibility(task_queue, operation))
+    }
+
+    async fn reset_sticky_task_queue(
+        &self,
+        execution: WorkflowExecution,
+    ) -> Result<ResetStickyTaskQueueResponse> {
+        flaky!(self.reset_sticky_task_queue(execution))
+    }
+
+    async fn disclaim_sticky(&self, execution: WorkflowExecution) -> Result<()> {
+        flaky!(self.disclaim_sticky(execution))
+    }
+
+    async fn delete_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+    ) -> Result<DeleteWorkflowExecutionResponse> {
+        flaky!(self.delete_workflow_execution(execution))
+    }
+
+    async fn terminate_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        reason: String,
+        details: Option<Payloads>,
+    ) -> Result<TerminateWorkflowExecutionResponse> {
+        flaky!(self.terminate_workflow_execution(execution, reason, details))
+    }
+
+    async fn reset_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        reset_to_event_id: i64,
+        reason: String,
+        reset_reapply_type: ResetReapplyType,
+    ) -> Result<ResetWorkflowExecutionResponse> {
+        flaky!(self.reset_workflow_execution(
+            execution,
+            reset_to_event_id,
+            reason,
+            reset_reapply_type
+        ))
+    }
+
+    async fn signal_workflow_execution(
+        &self,
+        execution: WorkflowExecution,
+        signal_name: String,
+        input: Option<Payloads>,
+    ) -> Result<SignalWorkflowExecutionResponse> {
+        flaky!(self.signal_workflow_execution(execution, signal_name, input))
+    }
+
+    async fn count_workflow_executions(
+        &self,
+        query: String,
+    ) -> Result<CountWorkflowExecutionsResponse> {
+        flaky!(self.count_workflow_executions(query))
+    }
+
+    async fn list_open_workflow_executions(
+        &self,
+        start_time_filter: Option<StartTimeFilter>,
+        type_filter: Option<WorkflowTypeFilter>,
+        page_token: Vec<u8>,
+    ) -> Result<ListOpenWorkflowExecutionsResponse> {
+        flaky!(self.list_open_workflow_executions(start_time_filter, type_filter, page_token))
+    }
+
+    async fn check_health(&self) -> Result<HealthStatus> {
+        flaky!(self.check_health())
+    }
+
+    async fn refresh_capabilities(&self) -> Result<Capabilities> {
+        flaky!(self.refresh_capabilities())
+    }
+
+    async fn warmup(&self) -> Result<()> {
+        flaky!(self.warmup())
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.inner.capabilities()
+    }
+
+    fn capabilities_snapshot(&self) -> Capabilities {
+        self.inner.capabilities_snapshot()
+    }
+
+    fn observed_server_time(&self) -> Option<SystemTime> {
+        self.inner.observed_server_time()
+    }
+
+    fn completion_backpressure(&self) -> f64 {
+        self.inner.completion_backpressure()
+    }
+
+    fn connection_state(&self) -> ConnectionState {
+        self.inner.connection_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn flaky_client_failures_are_seeded() {
+        let codes = [tonic::Code::Unavailable, tonic::Code::DeadlineExceeded];
+        let outcomes = |seed| async move {
+            let client = flaky_client(
+                Arc::new(happy_path_client().build()),
+                0.5,
+                codes.to_vec(),
+                seed,
+            );
+            let mut outcomes = vec![];
+            for _ in 0..20 {
+                outcomes.push(
+                    client
+                        .count_workflow_executions("".to_string())
+                        .await
+                        .map_err(|e| e.code())
+                        .map(|_| ()),
+                );
+            }
+            outcomes
+        };
+
+        let first = outcomes(7).await;
+        assert_eq!(first, outcomes(7).await);
+        assert!(first.iter().any(Result::is_ok));
+        assert!(first
+            .iter()
+            .filter_map(|o| o.err())
+            .all(|c| codes.contains(&c)));
+        assert!(first.iter().any(Result::is_err));
+    }
+
+    #[tokio::test]
+    async fn flaky_client_fails_polls_before_delegating() {
+        let mut inner = mock_workflow_client();
+        inner.expect_poll_workflow_task().never();
+        let client = flaky_client(Arc::new(inner), 1.0, vec![tonic::Code::Unavailable], 0);
+        assert_matches!(
+            client
+                .poll_workflow_task(TaskQueue::default(), None, CancellationToken::new())
+                .await,
+            Err(PollError::TonicError(s)) if s.code() == tonic::Code::Unavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn happy_path_client_defaults_and_overrides() {
+        let client = happy_path_client()
+            .wft_polls([PollWorkflowTaskQueueResponse {
+                task_token: vec![1],
+                ..Default::default()
+            }])
+            .customize(|mock| {
+                mock.expect_complete_activity_task()
+                    .times(1)
+                    .returning(|_, _| Err(tonic::Status::not_found("gone")));
+            })
+            .build();
+
+        let poll =
+            || client.poll_workflow_task(TaskQueue::default(), None, CancellationToken::new());
+        assert_eq!(poll().await.unwrap().task_token, vec![1]);
+        assert!(poll().await.unwrap().task_token.is_empty());
+
+        let complete = || client.complete_activity_task(TaskToken(vec![1]).into(), None);
+        assert!(complete().await.is_err());
+        complete().await.unwrap();
+        client
+            .fail_workflow_task(
+                TaskToken(vec![1]).into(),
+                WorkflowTaskFailedCause::Unspecified,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
     }
 }
@@ -42,6 +42,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 use temporal_sdk_core_protos::{
     coresdk::{
@@ -52,8 +53,7 @@ use temporal_sdk_core_protos::{
         ActivityTaskCompletion,
     },
     temporal::api::{
-        enums::v1::TaskQueueKind,
-        taskqueue::v1::{StickyExecutionAttributes, TaskQueue},
+        enums::v1::TaskQueueKind, taskqueue::v1::TaskQueue,
         workflowservice::v1::get_system_info_response,
     },
     TaskToken,
@@ -288,6 +288,7 @@ impl Worker {
                         config.max_concurrent_at_polls,
                         act_semaphore.clone(),
                         config.max_task_queue_activities_per_second,
+                        config.activity_build_id_affinity.clone(),
                         shutdown_token.child_token(),
                         Some(move |np| act_metrics.record_num_pollers(np)),
                         config.max_worker_activities_per_second,
@@ -339,9 +340,14 @@ impl Worker {
                 ap,
                 client.clone(),
                 metrics.clone(),
-                config.max_heartbeat_throttle_interval,
+                if config.disable_heartbeat_throttling {
+                    Duration::ZERO
+                } else {
+                    config.max_heartbeat_throttle_interval
+                },
                 config.default_heartbeat_throttle_interval,
                 config.graceful_shutdown_period,
+                config.accepted_activity_types.clone(),
             )
         });
         let poll_on_non_local_activities = at_task_mgr.is_some();
@@ -356,21 +362,9 @@ impl Worker {
                     &mut config,
                     metrics,
                     shutdown_token.child_token(),
-                    client.capabilities().cloned().unwrap_or_default(),
+                    client.capabilities_snapshot(),
                 ),
-                sticky_queue_name.map(|sq| StickyExecutionAttributes {
-                    worker_task_queue: Some(TaskQueue {
-                        name: sq,
-                        kind: TaskQueueKind::Sticky as i32,
-                        normal_name: config.task_queue.clone(),
-                    }),
-                    schedule_to_start_timeout: Some(
-                        config
-                            .sticky_queue_schedule_to_start_timeout
-                            .try_into()
-                            .expect("timeout fits into proto"),
-                    ),
-                }),
+                sticky_queue_name.map(|sq| (sq, config.sticky_queue_schedule_to_start_timeout)),
                 client,
                 wft_semaphore,
                 wft_stream,
@@ -697,7 +691,7 @@ mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_poll_activity_task()
-            .returning(|_, _| Ok(PollActivityTaskQueueResponse::default()));
+            .returning(|_, _, _, _, _| Ok(PollActivityTaskQueueResponse::default()));
 
         let cfg = test_worker_cfg()
             .max_outstanding_activities(5_usize)
@@ -721,7 +715,7 @@ mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_poll_activity_task()
-            .returning(|_, _| Err(tonic::Status::internal("ahhh")));
+            .returning(|_, _, _, _, _| Err(tonic::Status::internal("ahhh").into()));
 
         let cfg = test_worker_cfg()
             .max_outstanding_activities(5_usize)
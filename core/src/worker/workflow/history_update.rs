@@ -18,7 +18,7 @@ use std::{
     task::{Context, Poll},
 };
 use temporal_sdk_core_protos::temporal::api::{
-    enums::v1::EventType,
+    enums::v1::{EventType, HistoryEventFilterType},
     history::v1::{history_event, History, HistoryEvent, WorkflowTaskCompletedEventAttributes},
 };
 use tracing::Instrument;
@@ -163,6 +163,7 @@ impl HistoryPaginator {
             workflow_type: wft.workflow_type,
             legacy_query: wft.legacy_query,
             query_requests: wft.query_requests,
+            messages: wft.messages,
             update,
         };
         Ok((paginator, prepared))
@@ -348,7 +349,13 @@ impl HistoryPaginator {
             debug!(run_id=%self.run_id, "Fetching new history page");
             let fetch_res = self
                 .client
-                .get_workflow_execution_history(self.wf_id.clone(), Some(self.run_id.clone()), npt)
+                .get_workflow_execution_history(
+                    self.wf_id.clone(),
+                    Some(self.run_id.clone()),
+                    npt,
+                    false,
+                    HistoryEventFilterType::AllEvent,
+                )
                 .instrument(span!(tracing::Level::TRACE, "fetch_history_in_paginator"))
                 .await?;
 
@@ -944,7 +951,7 @@ pub mod tests {
         let mut npt = 1;
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, passed_npt| {
+            .returning(move |_, _, passed_npt, _, _| {
                 assert_eq!(passed_npt, vec![npt]);
                 let mut hist_chunks = full_hist.chunks(chunk_size).peekable();
                 let next_chunks = hist_chunks.nth(npt.into()).unwrap_or_default();
@@ -1156,7 +1163,7 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(history_from_get.clone()));
+            .returning(move |_, _, _, _, _| Ok(history_from_get.clone()));
 
         let mut paginator = HistoryPaginator::new(
             partial_task.into(),
@@ -1214,7 +1221,7 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(Default::default()));
+            .returning(move |_, _, _, _, _| Ok(Default::default()));
 
         let mut paginator = HistoryPaginator::new(
             partial_task.into(),
@@ -1241,7 +1248,7 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(History { events: vec![] }),
                     raw_history: vec![],
@@ -1252,7 +1259,7 @@ pub mod tests {
             .times(1);
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(full_resp.clone()))
+            .returning(move |_, _, _, _, _| Ok(full_resp.clone()))
             .times(1);
 
         let mut paginator = HistoryPaginator::new(
@@ -1290,11 +1297,11 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(full_resp_with_npt.clone()))
+            .returning(move |_, _, _, _, _| Ok(full_resp_with_npt.clone()))
             .times(1);
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(History { events: vec![] }),
                     raw_history: vec![],
@@ -1369,11 +1376,11 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(resp_1.clone()))
+            .returning(move |_, _, _, _, _| Ok(resp_1.clone()))
             .times(1);
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(History { events: vec![] }),
                     raw_history: vec![],
@@ -1389,11 +1396,11 @@ pub mod tests {
         resp_2.history.as_mut().unwrap().events.truncate(4);
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(resp_2.clone()))
+            .returning(move |_, _, _, _, _| Ok(resp_2.clone()))
             .times(1);
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(History { events: vec![] }),
                     raw_history: vec![],
@@ -1485,7 +1492,7 @@ pub mod tests {
             // Add an empty page
             mock_client
                 .expect_get_workflow_execution_history()
-                .returning(move |_, _, _| {
+                .returning(move |_, _, _, _, _| {
                     Ok(GetWorkflowExecutionHistoryResponse {
                         history: Some(History { events: vec![] }),
                         raw_history: vec![],
@@ -1498,7 +1505,7 @@ pub mod tests {
             // Add a page with only event i
             mock_client
                 .expect_get_workflow_execution_history()
-                .returning(move |_, _, _| {
+                .returning(move |_, _, _, _, _| {
                     Ok(GetWorkflowExecutionHistoryResponse {
                         history: Some(History {
                             events: vec![event.clone()],
@@ -1514,7 +1521,7 @@ pub mod tests {
         // Add an extra empty page at the end, with no NPT
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(History { events: vec![] }),
                     raw_history: vec![],
@@ -1599,7 +1606,7 @@ pub mod tests {
             // Add an empty page
             mock_client
                 .expect_get_workflow_execution_history()
-                .returning(move |_, _, _| {
+                .returning(move |_, _, _, _, _| {
                     Ok(GetWorkflowExecutionHistoryResponse {
                         history: Some(History { events: vec![] }),
                         raw_history: vec![],
@@ -1612,7 +1619,7 @@ pub mod tests {
             // Add a page with just event i
             mock_client
                 .expect_get_workflow_execution_history()
-                .returning(move |_, _, _| {
+                .returning(move |_, _, _, _, _| {
                     Ok(GetWorkflowExecutionHistoryResponse {
                         history: Some(History {
                             events: vec![event.clone()],
@@ -1628,7 +1635,7 @@ pub mod tests {
         // Add an extra empty page at the end, with no NPT
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| {
+            .returning(move |_, _, _, _, _| {
                 Ok(GetWorkflowExecutionHistoryResponse {
                     history: Some(History { events: vec![] }),
                     raw_history: vec![],
@@ -1716,13 +1723,13 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(resp_1.clone()))
+            .returning(move |_, _, _, _, _| Ok(resp_1.clone()))
             .times(1);
         // Since there aren't sufficient events, we should try to see another fetch, and that'll
         // say there aren't any
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(Default::default()))
+            .returning(move |_, _, _, _, _| Ok(Default::default()))
             .times(1);
 
         let mut paginator = HistoryPaginator::new(
@@ -1796,7 +1803,7 @@ pub mod tests {
         let mut mock_client = mock_workflow_client();
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(full_resp.clone()))
+            .returning(move |_, _, _, _, _| Ok(full_resp.clone()))
             .times(1);
 
         let mut paginator = HistoryPaginator::new(
@@ -1836,7 +1843,7 @@ pub mod tests {
         one_task_resp.next_page_token = vec![1];
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(one_task_resp.clone()))
+            .returning(move |_, _, _, _, _| Ok(one_task_resp.clone()))
             .times(1);
         let mut up_to_sched_start: GetWorkflowExecutionHistoryResponse =
             t.get_full_history_info().unwrap().into();
@@ -1848,7 +1855,7 @@ pub mod tests {
             .truncate(9);
         mock_client
             .expect_get_workflow_execution_history()
-            .returning(move |_, _, _| Ok(up_to_sched_start.clone()))
+            .returning(move |_, _, _, _, _| Ok(up_to_sched_start.clone()))
             .times(1);
 
         let mut paginator = HistoryPaginator::new(
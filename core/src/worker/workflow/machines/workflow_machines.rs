@@ -28,7 +28,7 @@ use crate::{
                 HistEventData,
             },
             CommandID, DrivenWorkflow, HistoryUpdate, InternalFlagsRef, LocalResolution,
-            OutgoingJob, RunBasics, WFCommand, WFMachinesError, WorkflowFetcher,
+            OutgoingJob, RunBasics, WFCommand, WFMachinesError, WFTFailureContext, WorkflowFetcher,
             WorkflowStartedInfo,
         },
         ExecutingLAId, LocalActRequest, LocalActivityExecutionResult, LocalActivityResolution,
@@ -45,6 +45,7 @@ use std::{
     rc::Rc,
     time::{Duration, Instant, SystemTime},
 };
+use temporal_sdk_core_api::worker::NonDeterminismDetail;
 use temporal_sdk_core_protos::{
     coresdk::{
         common::{NamespacedWorkflowExecution, VersioningIntent},
@@ -58,7 +59,7 @@ use temporal_sdk_core_protos::{
     },
     temporal::api::{
         command::v1::{command::Attributes as ProtoCmdAttrs, Command as ProtoCommand},
-        enums::v1::EventType,
+        enums::v1::{CommandType, EventType},
         history::v1::{history_event, history_event::Attributes, HistoryEvent},
         sdk::v1::WorkflowTaskCompletedMetadata,
     },
@@ -146,6 +147,9 @@ pub(crate) struct WorkflowMachines {
     /// The workflow that is being driven by this instance of the machines
     drive_me: DrivenWorkflow,
 
+    /// What we know about the cause of the most recent error, reported along with the WFT failure
+    failure_context: WFTFailureContext,
+
     /// Metrics context
     pub metrics: MetricsContext,
 }
@@ -257,10 +261,16 @@ impl WorkflowMachines {
             current_wf_task_commands: Default::default(),
             encountered_change_markers: Default::default(),
             local_activity_data: LocalActivityData::default(),
+            failure_context: Default::default(),
             have_seen_terminal_event: false,
         }
     }
 
+    /// Takes whatever was learned about the cause of the last error these machines returned
+    pub(crate) fn take_failure_context(&mut self) -> WFTFailureContext {
+        std::mem::take(&mut self.failure_context)
+    }
+
     /// Returns true if workflow has seen a terminal command
     pub(crate) const fn workflow_is_finished(&self) -> bool {
         self.workflow_end_time.is_some()
@@ -732,6 +742,11 @@ impl WorkflowMachines {
             let command = if let Some(c) = maybe_command {
                 c
             } else {
+                self.failure_context.non_determinism = Some(NonDeterminismDetail {
+                    expected: format!("{:?}", event.event_type()),
+                    actual: "no command".to_string(),
+                    event_id,
+                });
                 return Err(WFMachinesError::Nondeterminism(format!(
                     "No command scheduled for event {event}"
                 )));
@@ -742,8 +757,24 @@ impl WorkflowMachines {
                 .was_cancelled_before_sent_to_server();
 
             if !canceled_before_sent {
+                let expected = format!("{:?}", event.event_type());
+                let actual = match &command.command {
+                    MachineAssociatedCommand::Real(cmd) => format!("{:?}", cmd.command_type()),
+                    MachineAssociatedCommand::FakeLocalActivityMarker(_) => {
+                        format!("{:?}", CommandType::RecordMarker)
+                    }
+                };
                 // Feed the machine the event
-                self.submachine_handle_event(command.machine, event_dat)?;
+                if let Err(e) = self.submachine_handle_event(command.machine, event_dat) {
+                    if matches!(e, WFMachinesError::Nondeterminism(_)) {
+                        self.failure_context.non_determinism = Some(NonDeterminismDetail {
+                            expected,
+                            actual,
+                            event_id,
+                        });
+                    }
+                    return Err(e);
+                }
                 break command;
             }
         };
@@ -1046,7 +1077,9 @@ impl WorkflowMachines {
     /// immediately unblock lang side without having it to poll for an actual workflow task from the
     /// server.
     fn handle_driven_results(&mut self, results: Vec<WFCommand>) -> Result<()> {
-        for cmd in results {
+        for (index, cmd) in results.into_iter().enumerate() {
+            // Left in place if handling this command fails, so the failure can point at it
+            self.failure_context.failing_command_index = Some(index);
             match cmd {
                 WFCommand::AddTimer(attrs) => {
                     let seq = attrs.seq;
@@ -1235,6 +1268,7 @@ impl WorkflowMachines {
                 WFCommand::NoCommandsFromLang => (),
             }
         }
+        self.failure_context.failing_command_index = None;
         Ok(())
     }
 
@@ -6,7 +6,7 @@ pub(crate) use managed_wf_test::ManagedWFFunc;
 
 use crate::{
     abstractions::dbg_panic,
-    protosext::WorkflowActivationExt,
+    protosext::{reject_update_requests, WorkflowActivationExt},
     worker::{
         workflow::{
             history_update::HistoryPaginator, machines::WorkflowMachines, ActivationAction,
@@ -32,13 +32,15 @@ use std::{
 use temporal_sdk_core_protos::{
     coresdk::{
         workflow_activation::{
-            create_evict_activation, query_to_job, remove_from_cache::EvictionReason,
-            workflow_activation_job, RemoveFromCache, WorkflowActivation,
+            create_evict_activation, remove_from_cache::EvictionReason, workflow_activation_job,
+            RemoveFromCache, WorkflowActivation,
         },
         workflow_commands::QueryResult,
         workflow_completion,
     },
-    temporal::api::{enums::v1::WorkflowTaskFailedCause, failure::v1::Failure},
+    temporal::api::{
+        common::v1::WorkflowExecution, enums::v1::WorkflowTaskFailedCause, failure::v1::Failure,
+    },
     TaskToken,
 };
 use tokio::sync::oneshot;
@@ -145,6 +147,22 @@ impl ManagedRun {
         self.trying_to_evict.is_some()
     }
 
+    /// If this run is being evicted to free up space in the cache, and the workflow isn't already
+    /// finished, returns its execution so the server can be told to stop sending its tasks to our
+    /// sticky queue.
+    pub(super) fn evicted_for_cache_space(&self) -> Option<WorkflowExecution> {
+        match self.trying_to_evict.as_ref() {
+            Some(RequestEvictMsg {
+                reason: EvictionReason::CacheFull,
+                ..
+            }) if !self.have_seen_terminal_event() => Some(WorkflowExecution {
+                workflow_id: self.wfm.machines.workflow_id.clone(),
+                run_id: self.run_id().to_string(),
+            }),
+            _ => None,
+        }
+    }
+
     /// Called whenever a new workflow task is obtained for this run
     pub(super) fn incoming_wft(&mut self, pwft: PermittedWFT) -> RunUpdateAct {
         let res = self._incoming_wft(pwft);
@@ -176,9 +194,7 @@ impl ManagedRun {
             wf_id: work.execution.workflow_id.clone(),
         };
 
-        let legacy_query_from_poll = work
-            .legacy_query
-            .map(|q| query_to_job(LEGACY_QUERY_ID.to_string(), q));
+        let legacy_query_from_poll = work.legacy_query;
 
         let mut pending_queries = work.query_requests;
         if !pending_queries.is_empty() && legacy_query_from_poll.is_some() {
@@ -202,6 +218,7 @@ impl ManagedRun {
             info: wft_info,
             hit_cache: !did_miss_cache,
             pending_queries,
+            outgoing_messages: reject_update_requests(work.messages),
             start_time,
             permit: pwft.permit,
         });
@@ -375,6 +392,7 @@ impl ManagedRun {
                     tt,
                     WorkflowTaskFailedCause::Unspecified,
                     Failure::application_failure(reason, true).into(),
+                    Default::default(),
                 ))
             } else {
                 ActivationCompleteOutcome::DoNothing
@@ -499,6 +517,9 @@ impl ManagedRun {
     /// or user code threw/panicked, respectively. The `cause` and `reason` fields are determined
     /// inside core always. The `failure` field may come from lang. `resp_chan` will be used to
     /// unblock the completion call when everything we need to do to fulfill it has happened.
+    ///
+    /// Anything the machines learned about what caused their most recent error is reported along
+    /// with the failure.
     pub(super) fn failed_completion(
         &mut self,
         cause: WorkflowTaskFailedCause,
@@ -539,7 +560,10 @@ impl ManagedRun {
             )
         } else if should_report {
             ActivationCompleteOutcome::ReportWFTFail(FailedActivationWFTReport::Report(
-                tt, cause, failure,
+                tt,
+                cause,
+                failure,
+                self.wfm.machines.take_failure_context(),
             ))
         } else {
             ActivationCompleteOutcome::WFTFailedDontReport
@@ -1013,6 +1037,11 @@ impl ManagedRun {
                     commands: outgoing_cmds.commands,
                     query_responses,
                     sdk_metadata: self.wfm.machines.get_metadata_for_wft_complete(),
+                    messages: self
+                        .wft
+                        .as_mut()
+                        .map(|wft| std::mem::take(&mut wft.outgoing_messages))
+                        .unwrap_or_default(),
                 },
             })
         } else {
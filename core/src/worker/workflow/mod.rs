@@ -31,7 +31,7 @@ use crate::{
     telemetry::{set_trace_subscriber_for_current_thread, TelemetryInstance, VecDisplayer},
     worker::{
         activities::{ActivitiesFromWFTsHandle, LocalActivityManager, TrackedPermittedTqResp},
-        client::{WorkerClient, WorkflowTaskCompletion},
+        client::{take_new_workflow_task, WorkerClient, WorkflowTaskCompletion},
         workflow::{
             history_update::HistoryPaginator,
             managed_run::RunUpdateAct,
@@ -62,7 +62,11 @@ use std::{
     thread,
     time::{Duration, Instant},
 };
-use temporal_sdk_core_api::errors::{CompleteWfError, PollWfError};
+use temporal_client::{classify_rpc_error, RpcErrorClass};
+use temporal_sdk_core_api::{
+    errors::{CompleteWfError, PollWfError},
+    worker::NonDeterminismDetail,
+};
 use temporal_sdk_core_protos::{
     coresdk::{
         workflow_activation::{
@@ -79,9 +83,8 @@ use temporal_sdk_core_protos::{
         command::v1::{command::Attributes, Command as ProtoCommand, Command},
         common::v1::{Memo, MeteringMetadata, RetryPolicy, SearchAttributes, WorkflowExecution},
         enums::v1::WorkflowTaskFailedCause,
-        query::v1::WorkflowQuery,
+        protocol::v1::Message as ProtocolMessage,
         sdk::v1::WorkflowTaskCompletedMetadata,
-        taskqueue::v1::StickyExecutionAttributes,
         workflowservice::v1::{get_system_info_response, PollActivityTaskQueueResponse},
     },
     TaskToken,
@@ -119,8 +122,8 @@ pub(crate) struct Workflows {
     )>,
     client: Arc<dyn WorkerClient>,
     /// Will be populated when this worker is using a cache and should complete WFTs with a sticky
-    /// queue.
-    sticky_attrs: Option<StickyExecutionAttributes>,
+    /// queue. Holds the queue name and its schedule-to-start timeout.
+    sticky_queue: Option<(String, Duration)>,
     /// If set, can be used to reserve activity task slots for eager-return of new activity tasks.
     activity_tasks_handle: Option<ActivitiesFromWFTsHandle>,
     /// Ensures we stay at or below this worker's maximum concurrent workflow task limit
@@ -157,7 +160,7 @@ impl Workflows {
     #[allow(clippy::too_many_arguments)] // Not much worth combining here
     pub(super) fn new(
         basics: WorkflowBasics,
-        sticky_attrs: Option<StickyExecutionAttributes>,
+        sticky_queue: Option<(String, Duration)>,
         client: Arc<dyn WorkerClient>,
         wft_semaphore: Arc<MeteredSemaphore>,
         wft_stream: impl Stream<Item = WFTStreamIn> + Send + 'static,
@@ -186,6 +189,9 @@ impl Workflows {
         // We must spawn a task to constantly poll the activation stream, because otherwise
         // activation completions would not cause anything to happen until the next poll.
         let tracing_sub = telem_instance.map(|ti| ti.trace_subscriber());
+        // Runs evicted for cache space only need their sticky affinity disclaimed if we have a
+        // sticky queue in the first place
+        let disclaim_client = sticky_queue.as_ref().map(|_| client.clone());
         let processing_task = thread::spawn(move || {
             if let Some(ts) = tracing_sub {
                 set_trace_subscriber_for_current_thread(ts);
@@ -225,6 +231,16 @@ impl Workflows {
                                     .send(fetchreq)
                                     .expect("Fetch channel must not be dropped");
                             }
+                            if let Some(client) = disclaim_client.as_ref() {
+                                for execution in o.evicted_for_space {
+                                    let client = client.clone();
+                                    tokio::task::spawn_local(async move {
+                                        if let Err(e) = client.disclaim_sticky(execution).await {
+                                            warn!(error=?e, "Failed to disclaim sticky affinity");
+                                        }
+                                    });
+                                }
+                            }
                             for act in o.activations {
                                 activation_tx
                                     .send(Ok(act))
@@ -247,7 +263,7 @@ impl Workflows {
                 Some(start_polling_tx),
             )),
             client,
-            sticky_attrs,
+            sticky_queue,
             activity_tasks_handle,
             wft_semaphore,
             local_act_mgr,
@@ -359,38 +375,41 @@ impl Workflows {
                             query_responses,
                             force_new_wft,
                             sdk_metadata,
+                            messages,
                         },
                 } => {
                     let reserved_act_permits =
                         self.reserve_activity_slots_for_outgoing_commands(commands.as_mut_slice());
                     debug!(commands=%commands.display(), query_responses=%query_responses.display(),
                            force_new_wft, "Sending responses to server");
-                    let mut completion = WorkflowTaskCompletion {
-                        task_token,
-                        commands,
-                        query_responses,
-                        sticky_attributes: None,
-                        return_new_workflow_task: true,
-                        force_create_new_workflow_task: force_new_wft,
-                        sdk_metadata,
-                        metering_metadata: MeteringMetadata {
+                    let completion = WorkflowTaskCompletion::builder()
+                        .commands(commands)
+                        .query_responses(query_responses)
+                        // Do not return new WFT if we would not cache, because returned new WFTs
+                        // are always partial.
+                        .return_new_workflow_task(self.sticky_queue.is_some())
+                        .force_create_new_workflow_task(force_new_wft)
+                        .sdk_metadata(sdk_metadata)
+                        .messages(messages)
+                        .metering_metadata(MeteringMetadata {
                             nonfirst_local_activity_execution_attempts: self
                                 .local_act_mgr
                                 .get_nonfirst_attempt_count(&run_id)
                                 as u32,
-                        },
-                    };
-                    let sticky_attrs = self.sticky_attrs.clone();
-                    // Do not return new WFT if we would not cache, because returned new WFTs are
-                    // always partial.
-                    if sticky_attrs.is_none() {
-                        completion.return_new_workflow_task = false;
-                    }
-                    completion.sticky_attributes = sticky_attrs;
+                        })
+                        .build(task_token);
 
                     self.handle_wft_reporting_errs(&run_id, || async {
-                        let maybe_wft = self.client.complete_workflow_task(completion).await?;
-                        if let Some(wft) = maybe_wft.workflow_task {
+                        let completion = match &self.sticky_queue {
+                            Some((name, schedule_to_start)) => completion.with_sticky(
+                                name.clone(),
+                                self.task_queue.clone(),
+                                *schedule_to_start,
+                            )?,
+                            None => completion,
+                        };
+                        let mut maybe_wft = self.client.complete_workflow_task(completion).await?;
+                        if let Some(wft) = take_new_workflow_task(&mut maybe_wft) {
                             wft_from_complete = Some(validate_wft(wft)?);
                         }
                         self.handle_eager_activities(
@@ -411,11 +430,17 @@ impl Workflows {
                 }
             },
             ActivationCompleteOutcome::ReportWFTFail(outcome) => match outcome {
-                FailedActivationWFTReport::Report(tt, cause, failure) => {
+                FailedActivationWFTReport::Report(tt, cause, failure, context) => {
                     warn!(run_id=%run_id, failure=?failure, "Failing workflow task");
                     self.handle_wft_reporting_errs(&run_id, || async {
                         self.client
-                            .fail_workflow_task(tt, cause, failure.failure.map(Into::into))
+                            .fail_workflow_task(
+                                tt.into(),
+                                cause,
+                                failure.failure.map(Into::into),
+                                context.failing_command_index,
+                                context.non_determinism,
+                            )
                             .await
                     })
                     .await;
@@ -683,9 +708,9 @@ impl Workflows {
 
     /// Wraps responding to legacy queries. Handles ignore-able failures.
     async fn respond_legacy_query(&self, tt: TaskToken, res: QueryResult) {
-        match self.client.respond_legacy_query(tt, res).await {
+        match self.client.respond_legacy_query(tt.into(), res).await {
             Ok(_) => {}
-            Err(e) if e.code() == tonic::Code::NotFound => {
+            Err(e) if classify_rpc_error(&e) == RpcErrorClass::NotFound => {
                 warn!(error=?e, "Query not found when attempting to respond to it");
             }
             Err(e) => {
@@ -719,6 +744,8 @@ struct NextPageReq {
 struct WFStreamOutput {
     activations: VecDeque<ActivationOrAuto>,
     fetch_histories: VecDeque<HistoryFetchReq>,
+    /// Runs which were just removed from the cache to make room for others
+    evicted_for_space: VecDeque<WorkflowExecution>,
 }
 
 #[derive(Debug, derive_more::Display)]
@@ -778,8 +805,9 @@ struct PreparedWFT {
     attempt: u32,
     execution: WorkflowExecution,
     workflow_type: String,
-    legacy_query: Option<WorkflowQuery>,
+    legacy_query: Option<QueryWorkflow>,
     query_requests: Vec<QueryWorkflow>,
+    messages: Vec<ProtocolMessage>,
     update: HistoryUpdate,
 }
 impl PreparedWFT {
@@ -798,6 +826,8 @@ pub(crate) struct OutstandingTask {
     pub hit_cache: bool,
     /// Set if the outstanding task has quer(ies) which must be fulfilled upon finishing replay
     pub pending_queries: Vec<QueryWorkflow>,
+    /// Protocol messages to send back to the server when this task is completed
+    pub outgoing_messages: Vec<ProtocolMessage>,
     pub start_time: Instant,
     /// The WFT permit owned by this task, ensures we don't exceed max concurrent WFT, and makes
     /// sure the permit is automatically freed when we delete the task.
@@ -863,10 +893,24 @@ pub struct WorkflowTaskInfo {
 
 #[derive(Debug)]
 pub enum FailedActivationWFTReport {
-    Report(TaskToken, WorkflowTaskFailedCause, Failure),
+    Report(
+        TaskToken,
+        WorkflowTaskFailedCause,
+        Failure,
+        WFTFailureContext,
+    ),
     ReportLegacyQueryFailure(TaskToken, Failure),
 }
 
+/// What the machines could tell about why they failed, beyond the error itself
+#[derive(Debug, Default)]
+pub struct WFTFailureContext {
+    /// The index of the command from lang's completion which couldn't be handled
+    pub failing_command_index: Option<usize>,
+    /// How the workflow diverged from history, if it did
+    pub non_determinism: Option<NonDeterminismDetail>,
+}
+
 #[derive(Debug)]
 pub(crate) struct ServerCommandsWithWorkflowInfo {
     pub task_token: TaskToken,
@@ -881,6 +925,7 @@ pub(crate) enum ActivationAction {
         query_responses: Vec<QueryResult>,
         force_new_wft: bool,
         sdk_metadata: WorkflowTaskCompletedMetadata,
+        messages: Vec<ProtocolMessage>,
     },
     /// We should respond to a legacy query request
     RespondLegacyQuery { result: Box<QueryResult> },
@@ -1,6 +1,6 @@
 use crate::{
     abstractions::OwnedMeteredSemPermit,
-    pollers::{BoxedWFPoller, Poller},
+    pollers::{BoxedWFPoller, PollOutcome, Poller},
     protosext::ValidPollWFTQResponse,
     MetricsContext,
 };
@@ -15,12 +15,15 @@ pub(crate) fn new_wft_poller(
         loop {
             return match poller.poll().await {
                 Some(Ok((wft, permit))) => {
-                    if wft == PollWorkflowTaskQueueResponse::default() {
-                        // We get the default proto in the event that the long poll times out.
-                        debug!("Poll wft timeout");
-                        metrics.wf_tq_poll_empty();
-                        continue;
-                    }
+                    let wft = match PollOutcome::from(wft) {
+                        PollOutcome::Task(wft) => *wft,
+                        // We get a tokenless response in the event that the long poll times out.
+                        PollOutcome::Empty => {
+                            debug!("Poll wft timeout");
+                            metrics.wf_tq_poll_empty();
+                            continue;
+                        }
+                    };
                     if let Some(dur) = wft.sched_to_start() {
                         metrics.wf_task_sched_to_start_latency(dur);
                     }
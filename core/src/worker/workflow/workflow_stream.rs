@@ -35,6 +35,9 @@ pub(super) struct WFStream {
     /// Is filled with runs that we decided need to have their history fetched during state
     /// manipulation. Must be drained after handling each input.
     runs_needing_fetching: VecDeque<HistoryFetchReq>,
+    /// Is filled with runs that were removed from the cache to make room for others, and whose
+    /// sticky affinity should be disclaimed with the server. Drained like `runs_needing_fetching`.
+    runs_evicted_for_space: VecDeque<WorkflowExecution>,
 
     history_fetch_refcounter: Arc<HistfetchRC>,
     shutdown_token: CancellationToken,
@@ -106,6 +109,7 @@ impl WFStream {
             ignore_evicts_on_shutdown: basics.ignore_evicts_on_shutdown,
             metrics: basics.metrics,
             runs_needing_fetching: Default::default(),
+            runs_evicted_for_space: Default::default(),
             history_fetch_refcounter: Arc::new(HistfetchRC {}),
 
             #[cfg(feature = "save_wf_inputs")]
@@ -206,6 +210,7 @@ impl WFStream {
                 Ok(WFStreamOutput {
                     activations: activations.into(),
                     fetch_histories: std::mem::take(&mut state.runs_needing_fetching),
+                    evicted_for_space: std::mem::take(&mut state.runs_evicted_for_space),
                 })
             })
             .inspect(|o| {
@@ -352,6 +357,9 @@ impl WFStream {
                 debug!(run_id=%run_id, "Evicting run");
 
                 if let Some(mut rh) = self.runs.remove(run_id) {
+                    if let Some(execution) = rh.evicted_for_cache_space() {
+                        self.runs_evicted_for_space.push_back(execution);
+                    }
                     if let Some(buff) = rh.take_buffered_wft() {
                         // Don't try to apply a buffered poll for this run if we just got a new WFT
                         // from completing, because by definition that buffered poll is now an
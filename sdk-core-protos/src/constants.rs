@@ -5,3 +5,18 @@ pub const PATCH_MARKER_NAME: &str = "core_patch";
 
 /// Used as `marker_name` field when recording local activity markers
 pub const LOCAL_ACTIVITY_MARKER_NAME: &str = "core_local_activity";
+
+/// Metadata key marking the payload core appends to the details of an activity cancellation, which
+/// records why core asked lang to cancel the activity. The payload is the reason's name (ex:
+/// `TIMED_OUT`) as JSON.
+pub const ACTIVITY_CANCEL_REASON_KEY: &str = "core_activity_cancel_reason";
+
+/// Metadata key marking the payload core appends to the application failure details of a failed
+/// workflow task, which records the index of the command that caused the failure. The payload is
+/// the index as JSON.
+pub const FAILING_COMMAND_INDEX_KEY: &str = "core_failing_command_index";
+
+/// Metadata key marking the payload core appends to the application failure details of a workflow
+/// task which failed because of non-determinism, which records what didn't match. The payload is
+/// the `expected`, `actual`, and `event_id` of the mismatch as a JSON object.
+pub const NON_DETERMINISM_DETAIL_KEY: &str = "core_non_determinism_detail";
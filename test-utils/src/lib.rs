@@ -571,6 +571,7 @@ pub fn get_integ_tls_config() -> Option<TlsConfig> {
                 client_cert,
                 client_private_key,
             }),
+            client_cert_source: None,
         })
     } else {
         None
@@ -80,6 +80,7 @@ mod integ_tests {
                     client_cert,
                     client_private_key,
                 }),
+                client_cert_source: None,
             })
             .build()
             .unwrap();